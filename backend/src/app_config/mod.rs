@@ -16,6 +16,169 @@ pub struct Settings {
     pub oss_bucket_name: String,
     pub time_zone: i32,
     pub script_home: String,
+    // 邮件发送方式: file smtp
+    #[serde(default = "default_mail_transport")]
+    pub mail_transport: String,
+    #[serde(default = "default_mail_dir")]
+    pub mail_dir: String,
+    #[serde(default)]
+    pub smtp_host: String,
+    #[serde(default)]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub mail_from: String,
+    // 邀请邮件中的链接，附带 ?token=
+    #[serde(default)]
+    pub invite_url: String,
+    #[serde(default = "default_invite_expire_hours")]
+    pub invite_expire_hours: u64,
+    // site:* 键无写入后的过期天数
+    #[serde(default = "default_redis_key_ttl_days")]
+    pub redis_key_ttl_days: u64,
+    // 附件存储: oss s3 fs memory
+    #[serde(default = "default_storage_backend")]
+    pub storage_backend: String,
+    #[serde(default = "default_storage_root")]
+    pub storage_root: String,
+    #[serde(default)]
+    pub s3_endpoint: String,
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+    #[serde(default)]
+    pub s3_bucket: String,
+    #[serde(default)]
+    pub s3_access_key_id: String,
+    #[serde(default)]
+    pub s3_secret_access_key: String,
+    // fs memory 存储的签名链接和加密附件的解密链接，由本服务的 /pub/file/download /pub/file/decrypt 提供
+    #[serde(default)]
+    pub storage_url_base: String,
+    #[serde(default)]
+    pub storage_sign_secret: String,
+    // 签名链接有效期，附件链接每次访问时重新签发
+    #[serde(default = "default_storage_url_expire_secs")]
+    pub storage_url_expire_secs: u64,
+    // 上传限制的默认值，站点可单独设置
+    #[serde(default = "default_upload_max_bytes")]
+    pub upload_max_bytes: u64,
+    // 单次上传请求体的上限，边接收边计数，站点的 upload_max_bytes 不应超过它
+    #[serde(default = "default_upload_request_max_bytes")]
+    pub upload_request_max_bytes: u64,
+    // 按文件内容识别的类型，逗号分隔，支持 image/* 通配
+    #[serde(default = "default_upload_mime_types")]
+    pub upload_mime_types: String,
+    #[serde(default = "default_room_quota_bytes")]
+    pub room_quota_bytes: u64,
+    #[serde(default = "default_site_quota_bytes")]
+    pub site_quota_bytes: u64,
+    // 上传后超过该时长仍未发送的附件会被清理
+    #[serde(default = "default_orphan_upload_ttl_hours")]
+    pub orphan_upload_ttl_hours: u64,
+    // 附件病毒扫描: none clamd
+    #[serde(default = "default_attachment_scanner")]
+    pub attachment_scanner: String,
+    #[serde(default = "default_clamd_host")]
+    pub clamd_host: String,
+    #[serde(default = "default_clamd_port")]
+    pub clamd_port: u16,
+    #[serde(default = "default_clamd_timeout_secs")]
+    pub clamd_timeout_secs: u64,
+    // IP 归属地数据源，逗号分隔按顺序查询: mmdb ipinfo
+    #[serde(default = "default_geoip_providers")]
+    pub geoip_providers: String,
+    #[serde(default = "default_geoip_mmdb_path")]
+    pub geoip_mmdb_path: String,
+    // 可选的 ASN 数据库，用于填充 org
+    #[serde(default)]
+    pub geoip_asn_mmdb_path: String,
+    #[serde(default)]
+    pub ipinfo_token: String,
+    #[serde(default = "default_ip_detail_ttl_days")]
+    pub ip_detail_ttl_days: u64,
+}
+
+fn default_mail_transport() -> String {
+    "file".to_owned()
+}
+
+fn default_mail_dir() -> String {
+    "local/mails".to_owned()
+}
+
+fn default_invite_expire_hours() -> u64 {
+    72
+}
+
+fn default_redis_key_ttl_days() -> u64 {
+    7
+}
+
+fn default_storage_backend() -> String {
+    "oss".to_owned()
+}
+
+fn default_storage_root() -> String {
+    "local/storage".to_owned()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_owned()
+}
+
+fn default_storage_url_expire_secs() -> u64 {
+    300
+}
+
+fn default_upload_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_upload_request_max_bytes() -> u64 {
+    50 * 1024 * 1024
+}
+
+fn default_upload_mime_types() -> String {
+    "image/*,application/pdf,text/plain,application/zip".to_owned()
+}
+
+fn default_room_quota_bytes() -> u64 {
+    50 * 1024 * 1024
+}
+
+fn default_site_quota_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_orphan_upload_ttl_hours() -> u64 {
+    24
+}
+
+fn default_attachment_scanner() -> String {
+    "none".to_owned()
+}
+
+fn default_clamd_host() -> String {
+    "127.0.0.1".to_owned()
+}
+
+fn default_clamd_port() -> u16 {
+    3310
+}
+
+fn default_clamd_timeout_secs() -> u64 {
+    30
+}
+
+fn default_geoip_providers() -> String {
+    "mmdb".to_owned()
+}
+
+fn default_geoip_mmdb_path() -> String {
+    "local/GeoLite2-City.mmdb".to_owned()
+}
+
+fn default_ip_detail_ttl_days() -> u64 {
+    30
 }
 
 impl Settings {
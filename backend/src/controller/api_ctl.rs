@@ -0,0 +1,188 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    datetime::DateTime,
+    extension::JsonObjectExt,
+    json,
+    model::Query,
+    orm::Schema,
+    response::{ExtractRejection, Rejection},
+    warn, Map, Uuid,
+};
+
+use crate::{
+    controller::chat_ctl::find_user_site,
+    dto::{chat_message_entity::ChatMessageDto, cursor_page::PageCursor},
+    model::{ChatMessage, ChatRoom, SiteApiKey},
+    router::SERVER,
+    service::{
+        api_key_service::ApiKeyService, chat_service::ChatService, media_service::MediaService,
+        permission_service::SiteRole, webhook_service::WebhookService,
+    },
+    utils::{str_from_map, str_from_map_required},
+    wsserver::server::RoomBroadcast,
+};
+
+// 站点 API 密钥管理（客服后台）
+pub async fn list_api_keys(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let data = ApiKeyService::list_keys(&chat_site.id).await.extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn create_api_key(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let name = str_from_map_required("name", &body)?;
+    let bot_name = str_from_map("bot_name", &body)?.unwrap_or(name.clone());
+    let (key, secret) = ApiKeyService::create_key(&chat_site.id, &name, &bot_name)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!({
+        "key": key,
+        "secret_access_key": secret,
+    }));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn revoke_api_key(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let key_id = str_from_map_required("id", &body)?;
+    ApiKeyService::revoke_key(&chat_site.id, &key_id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 以下为外部系统接口，使用站点 API 密钥认证
+pub async fn list_rooms(req: Request) -> Result {
+    let api_key = authenticate(&req).await?;
+    let cursor = PageCursor::from_map(&req.parse_query::<Map>()?)?;
+    let data = ChatService::list_rooms(&api_key.site_id, &cursor)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn list_messages(req: Request) -> Result {
+    let api_key = authenticate(&req).await?;
+    let room = find_site_room(&req, &api_key).await?;
+    let cursor = PageCursor::from_map(&req.parse_query::<Map>()?)?;
+    let data = ChatService::list_messages(&room, &cursor)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn post_message(mut req: Request) -> Result {
+    let api_key = authenticate(&req).await?;
+    let room = find_site_room(&req, &api_key).await?;
+    let body = req.parse_body::<Map>().await?;
+    let content = str_from_map("content", &body)?.unwrap_or_default();
+    // 附件为本房间已上传文件的 id
+    let file_ids: Vec<Uuid> = body
+        .parse_str_array("file_ids")
+        .map(|v| v.into_iter().filter_map(|id| Uuid::parse_str(id).ok()).collect())
+        .unwrap_or_default();
+    if content.is_empty() && file_ids.is_empty() {
+        return Err(Rejection::from_error(warn!("content should provied")).into());
+    }
+    let bot_name = str_from_map("bot_name", &body)?.unwrap_or(api_key.bot_name.clone());
+
+    let mut message = ChatMessage::default();
+    message.id = Uuid::now_v7();
+    message.name = bot_name.clone();
+    message.content = content;
+    message.file_ids = file_ids;
+    message.room_id = room.id;
+    message.status = "sended".to_owned();
+    message.sender_type = "bot".to_owned();
+    message.create_at = DateTime::now();
+    message.update_at = DateTime::now();
+    MediaService::attach(&room, &mut message).await.extract(&req)?;
+    ChatService::save_message(&message).await.extract(&req)?;
+    WebhookService::emit_later(room.room_site_id, "message.created", json!(message));
+
+    // 通过 ChatServer 推送给房间内在线的访客和客服
+    let mut dto = ChatMessageDto::new_bot_msg(
+        &message.content,
+        Some(bot_name),
+        Some(room.id.to_string()),
+    );
+    dto.files = message.files.clone();
+    match serde_json::to_string(&dto) {
+        Ok(json) => SERVER.do_send(RoomBroadcast {
+            room: room.id.to_string(),
+            msg: json,
+        }),
+        Err(e) => tracing::error!("api message handle error: {:?}", e),
+    }
+
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(message));
+    res.set_code(StatusCode::CREATED);
+    Ok(res.clone().into())
+}
+
+async fn authenticate(req: &Request) -> Result<SiteApiKey> {
+    let Some(authorization) = req.get_header("authorization") else {
+        return Err(Rejection::with_message("401 Unauthorized: api key should provied")
+            .context(req)
+            .into());
+    };
+    match ApiKeyService::authenticate(authorization).await {
+        Ok(key) => Ok(key),
+        Err(e) => Err(Rejection::with_message(e.to_string()).context(req).into()),
+    }
+}
+
+async fn find_site_room(req: &Request, api_key: &SiteApiKey) -> Result<ChatRoom> {
+    let room_id: Uuid = req.parse_param("room_id")?;
+    let mut room_query = Query::from_entry("id", room_id.to_string());
+    room_query.add_filter("room_site_id", api_key.site_id.to_string());
+    match ChatRoom::find_one::<ChatRoom>(&room_query).await {
+        Ok(ro) => {
+            if ro.is_some() {
+                Ok(ro.unwrap())
+            } else {
+                Err(Rejection::from_error(warn!("room forbidden")).into())
+            }
+        }
+        Err(e) => Err(Rejection::from_error(e).into()),
+    }
+}
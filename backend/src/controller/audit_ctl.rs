@@ -0,0 +1,60 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    json,
+    response::ExtractRejection,
+    warn, Map, Uuid,
+};
+
+use crate::{
+    controller::chat_ctl::find_user_site,
+    model::AuditEvent,
+    service::{audit_service::AuditService, permission_service::SiteRole},
+    utils::{str_from_map, str_from_map_required, usize_from_map_default},
+};
+
+// 操作日志查询，仅站点管理员可见
+pub async fn list_audit_events(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let action = str_from_map("action", &body)?;
+    let actor_id = str_from_map("actor_id", &body)?;
+    let target_id = str_from_map("target_id", &body)?;
+    let page = usize_from_map_default("page", &body, 1)?;
+    let page_size = usize_from_map_default("page_size", &body, 10)?;
+    let data = AuditService::list_events(&chat_site, action, actor_id, target_id, page, page_size)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+/// 记录一条操作日志，`target` 为 (模型名, 主键)
+pub(crate) async fn audit(
+    req: &Request,
+    site_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+    action: &str,
+    target: (&str, String),
+    diff: Map,
+) {
+    let mut event = AuditEvent::default();
+    event.site_id = site_id;
+    event.actor_id = actor_id;
+    event.action = action.to_owned();
+    event.target_model = target.0.to_owned();
+    event.target_id = target.1;
+    event.diff = diff;
+    event.ip = req.client_ip().map(|ip| ip.to_string());
+    AuditService::record_or_log(event).await;
+}
@@ -1,10 +1,25 @@
 use zino::{prelude::*, Request, Response, Result};
 use zino_model::user::{JwtAuthService, User};
 
+use crate::controller::audit_ctl::audit;
+
 pub async fn login(mut req: Request) -> Result {
     let current_time = DateTime::now();
     let body: Map = req.parse_body().await?;
-    let (user_id, mut data) = User::generate_token(body).await.extract(&req)?;
+    let account = body.get_str("account").unwrap_or_default().to_owned();
+    let result = User::generate_token(body).await;
+    if result.is_err() {
+        // 登录失败也记录，账号存在时记在该用户名下，便于站点管理员查看
+        let user = User::find_one::<User>(&Query::from_entry("account", account.clone()))
+            .await
+            .ok()
+            .flatten();
+        let actor_id = user.as_ref().map(|u| *u.id());
+        let mut diff = Map::new();
+        diff.upsert("account", account.clone());
+        audit(&req, None, actor_id, "auth.login_failed", ("User", account), diff).await;
+    }
+    let (user_id, mut data) = result.extract(&req)?;
 
     let user_updates = json!({
         "status": "Active",
@@ -23,6 +38,7 @@ pub async fn login(mut req: Request) -> Result {
         reject!(req, validation);
     }
     data.upsert("entry", user.snapshot());
+    audit(&req, None, Some(user_id), "auth.login", ("User", user_id.to_string()), Map::new()).await;
 
     let mut res = Response::default().context(&req);
     res.set_json_data(data);
@@ -52,6 +68,8 @@ pub async fn logout(req: Request) -> Result {
         reject!(req, validation);
     }
 
+    audit(&req, None, Some(*user_id), "auth.logout", ("User", user_id.to_string()), Map::new()).await;
+
     let data = Map::data_entry(user.snapshot());
     let mut res = Response::default().context(&req);
     res.set_json_data(data);
@@ -0,0 +1,106 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    extension::JsonObjectExt,
+    json,
+    response::ExtractRejection,
+    warn, Map, Uuid,
+};
+
+use crate::{
+    controller::chat_ctl::find_user_site,
+    model::AutoReplyRule,
+    service::{auto_reply_service::AutoReplyService, permission_service::SiteRole},
+    utils::{str_from_map, str_from_map_required},
+};
+
+pub async fn list_rules(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let data = AutoReplyService::list_rules(&chat_site.id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn save_rule(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+
+    let mut rule = AutoReplyRule::default();
+    if let Some(id) = str_from_map("id", &body)? {
+        rule.id = Uuid::parse_str(&id).extract(&req)?;
+    }
+    rule.site_id = chat_site.id;
+    rule.name = str_from_map_required("name", &body)?;
+    rule.match_type = str_from_map_required("match_type", &body)?;
+    rule.pattern = str_from_map("pattern", &body)?.unwrap_or_default();
+    rule.reply = str_from_map_required("reply", &body)?;
+    rule.sender_name = str_from_map("sender_name", &body)?;
+    rule.hours_start = str_from_map("hours_start", &body)?;
+    rule.hours_end = str_from_map("hours_end", &body)?;
+    rule.priority = body.get_i32("priority").unwrap_or(0);
+    rule.status = str_from_map("status", &body)?.unwrap_or("active".to_owned());
+    AutoReplyService::save_rule(&mut rule).await.extract(&req)?;
+
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(rule));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn delete_rule(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let rule_id = str_from_map_required("id", &body)?;
+    AutoReplyService::delete_rule(&chat_site.id, &rule_id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 规则试运行
+pub async fn dry_run(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let content = str_from_map("content", &body)?.unwrap_or_default();
+    let first_message = body.get_bool("first_message").unwrap_or(false);
+    let data = AutoReplyService::dry_run(&chat_site.id, &content, first_message)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
@@ -14,23 +14,29 @@ use zino_core::{
     orm::Schema,
     response::{ExtractRejection, Rejection},
     validation::{UuidValidator, Validation, Validator},
-    warn, Map, Uuid,
+    warn, JsonValue, Map, Uuid,
 };
 
 use crate::app_config::SETTINGS;
 use crate::utils::date_utils::current_date;
 use crate::utils::date_utils::current_ms;
-use crate::utils::date_utils::current_s;
 use crate::utils::date_utils::date_ymdhms;
+use crate::utils::bool_from_map;
+use crate::utils::i64_from_map;
 use crate::utils::str_from_map;
 use crate::utils::str_from_map_required;
-use crate::utils::str_to_usize;
 use crate::utils::usize_from_map_default;
 use crate::{
     domain::website_config::WebsiteConfig,
+    dto::cursor_page::PageCursor,
     model::{ChatRoom, ChatWebsite},
-    service::chat_service::ChatService,
-    utils::{self},
+    controller::audit_ctl::audit,
+    service::{
+        audit_service::AuditService,
+        chat_service::ChatService,
+        permission_service::{PermissionService, SiteRole},
+        read_cursor_service::{ReadCursorService, VISITOR_SIDE},
+    },
 };
 
 pub async fn admin_config_website(mut req: Request) -> Result {
@@ -44,9 +50,17 @@ pub async fn admin_config_website(mut req: Request) -> Result {
         title: str_from_map("title", &body)?,
         welcome_slogan: str_from_map("welcome_slogan", &body)?,
         site_key: None,
+        domain: str_from_map("domain", &body)?,
         user_id: user_id.clone(),
         position: str_from_map("position", &body)?,
         id: str_from_map("id", &body)?,
+        bot_type: str_from_map("bot_type", &body)?,
+        bot_webhook_url: str_from_map("bot_webhook_url", &body)?,
+        upload_max_bytes: i64_from_map("upload_max_bytes", &body)?,
+        upload_mime_types: str_from_map("upload_mime_types", &body)?,
+        room_quota_bytes: i64_from_map("room_quota_bytes", &body)?,
+        site_quota_bytes: i64_from_map("site_quota_bytes", &body)?,
+        encrypt_attachments: bool_from_map("encrypt_attachments", &body)?,
     };
     let res = &mut Response::default().context(&req);
     match ChatService::config_site(&website_config).await {
@@ -74,14 +88,35 @@ pub async fn save_site_config(mut req: Request) -> Result {
         title: str_from_map("title", &body)?,
         welcome_slogan: str_from_map("welcome_slogan", &body)?,
         site_key: str_from_map("site_key", &body)?,
+        domain: str_from_map("domain", &body)?,
         user_id: user_id.clone(),
         position: str_from_map("position", &body)?,
         id: str_from_map("id", &body)?,
+        bot_type: str_from_map("bot_type", &body)?,
+        bot_webhook_url: str_from_map("bot_webhook_url", &body)?,
+        upload_max_bytes: i64_from_map("upload_max_bytes", &body)?,
+        upload_mime_types: str_from_map("upload_mime_types", &body)?,
+        room_quota_bytes: i64_from_map("room_quota_bytes", &body)?,
+        site_quota_bytes: i64_from_map("site_quota_bytes", &body)?,
+        encrypt_attachments: bool_from_map("encrypt_attachments", &body)?,
+    };
+    let before = match &website_config.id {
+        Some(site_id) => find_user_site(user_id, site_id, SiteRole::Admin).await?,
+        None => return Err(Rejection::from_error(warn!("site id should provied")).into()),
     };
     let res = &mut Response::default().context(&req);
     match ChatService::save_site(&website_config).await {
         Ok(data) => {
-            res.set_json_data(json!(data.unwrap()));
+            let site = data.unwrap();
+            audit(
+                &req,
+                Some(site.id),
+                Some(*user_id),
+                "site.update",
+                ("ChatWebsite", site.id.to_string()),
+                AuditService::diff(&json!(before), &json!(site)),
+            ).await;
+            res.set_json_data(json!(site));
             res.set_code(StatusCode::OK);
         }
         Err(e) => {
@@ -92,6 +127,83 @@ pub async fn save_site_config(mut req: Request) -> Result {
     Ok(res.clone().into())
 }
 
+// 新建站点
+pub async fn create_site(mut req: Request) -> Result {
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let body: Map = req.parse_body().await?;
+    let website_config = WebsiteConfig {
+        title: str_from_map("title", &body)?,
+        welcome_slogan: str_from_map("welcome_slogan", &body)?,
+        site_key: None,
+        domain: str_from_map("domain", &body)?,
+        user_id: user_id.clone(),
+        position: str_from_map("position", &body)?,
+        id: None,
+        bot_type: None,
+        bot_webhook_url: None,
+        upload_max_bytes: None,
+        upload_mime_types: None,
+        room_quota_bytes: None,
+        site_quota_bytes: None,
+        encrypt_attachments: None,
+    };
+    let site = ChatService::create_site(&website_config)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(site));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn list_sites(req: Request) -> Result {
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let include_archived = req.get_query("include_archived") == Some("true");
+    let data = ChatService::list_sites(user_id, include_archived)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 归档或恢复站点
+pub async fn archive_site(mut req: Request) -> Result {
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let body: Map = req.parse_body().await?;
+    let site_id = str_from_map_required("site_id", &body)?;
+    let archived = body.get_bool("archived").unwrap_or(true);
+    let before = find_user_site(user_id, &site_id, SiteRole::Owner).await?;
+    let site = ChatService::archive_site(user_id, &site_id, archived)
+        .await
+        .extract(&req)?;
+    audit(
+        &req,
+        Some(site.id),
+        Some(*user_id),
+        "site.archive",
+        ("ChatWebsite", site.id.to_string()),
+        AuditService::diff(&json!(before), &json!(site)),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(site));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
 pub async fn load_site_js(req: Request) -> Result<HttpResponse> {
     if let Some(key) = req.get_query("key") {
         match ChatService::load_site(&key.to_string()).await {
@@ -192,7 +304,6 @@ pub async fn list_rooms(req: Request) -> Result {
         .extract(&req)?;
     let user_id: &Uuid = user_session.user_id();
     tracing::info!("user_id:{}", user_id);
-    let mut query = Query::from_entry("user_id", user_id.to_string());
     let site_id = if let Some(s_id) = req.get_query("site_id") {
         if let Err(e) = UuidValidator.validate(s_id) {
             return Err(Rejection::from_error(e).into());
@@ -203,38 +314,12 @@ pub async fn list_rooms(req: Request) -> Result {
         validation.record("site_id", "should provide site_id");
         return Err(Rejection::bad_request(validation).into());
     };
-    query.add_filter("site_id", site_id);
     tracing::info!("site_id:{}", site_id);
-    let chat_site = match ChatWebsite::find_one::<ChatWebsite>(&query).await {
-        Ok(site) => {
-            tracing::info!("chat website:{:?}", &site);
-            if site.is_some() {
-                site.unwrap()
-            } else {
-                return Err(Rejection::from_error(warn!("room site not found")).into());
-            }
-        }
-        Err(e) => return Err(Rejection::from_error(e).into()),
-    };
-    let page = if req.get_query("page").is_none() {
-        "1"
-    } else {
-        req.get_query("page").unwrap()
-    };
-    let page_size = if req.get_query("page_size").is_none() {
-        "10"
-    } else {
-        req.get_query("page_size").unwrap()
-    };
+    let chat_site = find_user_site(user_id, site_id, SiteRole::Viewer).await?;
+    let cursor = PageCursor::from_map(&req.parse_query::<Map>()?)?;
 
     let res = &mut Response::default().context(&req);
-    match ChatService::list_rooms(
-        &chat_site.id,
-        utils::str_to_usize(page)?,
-        utils::str_to_usize(page_size)?,
-    )
-    .await
-    {
+    match ChatService::list_rooms(&chat_site.id, &cursor).await {
         Ok(data) => {
             res.set_json_data(json!(data));
             res.set_code(StatusCode::OK);
@@ -254,29 +339,15 @@ pub async fn list_chatmessage(mut req: Request) -> Result {
         .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
         .extract(&req)?;
     let user_id: &Uuid = user_session.user_id();
-    let mut query = Query::from_entry("user_id", user_id.to_string());
-
     let site_id = str_from_map_required("site_id", &body)?;
-
-    query.add_filter("site_id", site_id);
-
-    let chat_site = match ChatWebsite::find_one::<ChatWebsite>(&query).await {
-        Ok(site) => {
-            if site.is_some() {
-                site.unwrap()
-            } else {
-                return Err(Rejection::from_error(warn!("room site not found")).into());
-            }
-        }
-        Err(e) => return Err(Rejection::from_error(e).into()),
-    };
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
     let room_id = str_from_map_required("room_id", &body)?;
     // let room_uuid = match uuid::Uuid::parse_str(room_id) {
     //     Ok(id) => id,
     //     Err(e) => return Err(Rejection::from_error(e).into()),
     // };
     let mut room_query = Query::from_entry("id", room_id);
-    room_query.add_filter("key", chat_site.id.to_string());
+    room_query.add_filter("room_site_id", chat_site.id.to_string());
     let room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
         Ok(ro) => {
             if ro.is_some() {
@@ -288,22 +359,12 @@ pub async fn list_chatmessage(mut req: Request) -> Result {
         Err(e) => return Err(Rejection::from_error(e).into()),
     };
 
-    let page = usize_from_map_default("page", &body, 1)?;
-    let page_size = usize_from_map_default("page_size", &body, 10)?;
-    let date;
-    if page == 1 {
-        date = current_s() as usize;
-    } else {
-        date = str_to_usize(&str_from_map_required("ts", &body)?)?;
-    }
+    let cursor = PageCursor::from_map(&body)?;
     let res = &mut Response::default().context(&req);
-    let data = ChatService::list_messages(&room, page, page_size, date)
+    let data = ChatService::list_messages(&room, &cursor)
         .await
         .extract(&req)?;
-    let mut res_map = HashMap::new();
-    res_map.insert("data", json!(data));
-    res_map.insert("ts", date.into());
-    res.set_json_data(json!(res_map));
+    res.set_json_data(json!(data));
     res.set_code(StatusCode::OK);
     Ok(res.clone().into())
 }
@@ -337,22 +398,18 @@ pub async fn list_chatmessage_from_chat(mut req: Request) -> Result {
         }
         Err(e) => return Err(Rejection::from_error(e).into()),
     };
-    let page = usize_from_map_default("page", &body, 1)?;
-    let page_size = usize_from_map_default("page_size", &body, 10)?;
-    let date;
-    if page == 1 {
-        date = current_s() as usize;
-    } else {
-        date = str_to_usize(&str_from_map_required("ts", &body)?)?;
-    }
+    let cursor = PageCursor::from_map(&body)?;
     let res = &mut Response::default().context(&req);
-    let data = ChatService::list_messages(&room, page, page_size, date)
+    // 访客打开聊天窗口，客服发出的消息变为已读
+    if cursor.before.is_none() {
+        ReadCursorService::mark_read(&room, VISITOR_SIDE, None)
+            .await
+            .extract(&req)?;
+    }
+    let data = ChatService::list_messages(&room, &cursor)
         .await
         .extract(&req)?;
-    let mut res_map = HashMap::new();
-    res_map.insert("data", json!(data));
-    res_map.insert("ts", date.into());
-    res.set_json_data(json!(res_map));
+    res.set_json_data(json!(data));
     res.set_code(StatusCode::OK);
     Ok(res.clone().into())
 }
@@ -381,3 +438,191 @@ pub async fn load_site(mut req: Request) -> Result {
     res.set_code(StatusCode::OK);
     Ok(res.clone().into())
 }
+
+// 转人工排队中的房间
+pub async fn list_queue(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let data = ChatService::list_queue(&chat_site.id).await.extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 客服结束会话
+pub async fn close_room(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Agent).await?;
+    let room_id = str_from_map_required("room_id", &body)?;
+    let mut room_query = Query::from_entry("id", room_id);
+    room_query.add_filter("room_site_id", chat_site.id.to_string());
+    let mut room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
+        Ok(ro) => {
+            if ro.is_some() {
+                ro.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room forbidden")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let before = json!(room);
+    ChatService::close_room(&mut room).await.extract(&req)?;
+    audit(
+        &req,
+        Some(chat_site.id),
+        Some(*user_id),
+        "room.close",
+        ("ChatRoom", room.id.to_string()),
+        AuditService::diff(&before, &json!(room)),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(room));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 转接给站点内其他客服
+pub async fn transfer_room(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Agent).await?;
+    let to_user_id = Uuid::parse_str(&str_from_map_required("user_id", &body)?).extract(&req)?;
+    match PermissionService::role_of(&to_user_id, &chat_site).await {
+        Ok(Some(role)) if role >= SiteRole::Agent => (),
+        Ok(_) => return Err(Rejection::from_error(warn!("target user is not an agent of the site")).into()),
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    }
+    let room_id = str_from_map_required("room_id", &body)?;
+    let mut room_query = Query::from_entry("id", room_id);
+    room_query.add_filter("room_site_id", chat_site.id.to_string());
+    let mut room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
+        Ok(ro) => {
+            if ro.is_some() {
+                ro.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room forbidden")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let before = json!(room);
+    ChatService::transfer_room(&mut room, &to_user_id)
+        .await
+        .extract(&req)?;
+    audit(
+        &req,
+        Some(chat_site.id),
+        Some(*user_id),
+        "room.transfer",
+        ("ChatRoom", room.id.to_string()),
+        AuditService::diff(&before, &json!(room)),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(room));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 客服删除消息
+pub async fn delete_message(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Agent).await?;
+    let message_id = str_from_map_required("message_id", &body)?;
+    let message = ChatService::delete_message(&chat_site.id, &message_id)
+        .await
+        .extract(&req)?;
+    audit(
+        &req,
+        Some(chat_site.id),
+        Some(*user_id),
+        "message.delete",
+        ("ChatMessage", message.id.to_string()),
+        Map::from_entry(
+            "content",
+            json!({"before": &message.content, "after": JsonValue::Null}),
+        ),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 访客评价
+pub async fn rate_room(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let site_key = str_from_map_required("site_key", &body)?;
+    let room_key = str_from_map_required("room_key", &body)?;
+    let rating = usize_from_map_default("rating", &body, 0)? as i32;
+    let comment = str_from_map("comment", &body)?;
+    let query = Query::from_entry("site_key", site_key);
+    let chat_site = match ChatWebsite::find_one::<ChatWebsite>(&query).await {
+        Ok(site) => {
+            if site.is_some() {
+                site.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room site not found")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let mut room_query = Query::from_entry("room_key", room_key);
+    room_query.add_filter("room_site_id", chat_site.id.to_string());
+    let mut room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
+        Ok(ro) => {
+            if ro.is_some() {
+                ro.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room forbidden")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    ChatService::rate_room(&mut room, rating, comment)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub(crate) async fn find_user_site(
+    user_id: &Uuid,
+    site_id: &str,
+    required: SiteRole,
+) -> Result<ChatWebsite> {
+    match PermissionService::check(user_id, site_id, required).await {
+        Ok((site, _)) => Ok(site),
+        Err(e) => {
+            if e.message().starts_with("403") {
+                Err(Rejection::forbidden(e).into())
+            } else {
+                Err(Rejection::from_error(e).into())
+            }
+        }
+    }
+}
@@ -1,42 +1,119 @@
-use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
+use actix_web::http::StatusCode;
 use rand::Rng;
 use zino::{prelude::*, Cluster, Request, Response, Result};
+use zino_core::auth::UserSession;
+
+use crate::app_config::SETTINGS;
+use crate::model::{ChatMedia, ChatRoom};
+use crate::router::SERVER;
+use crate::wsserver::server::RoomBroadcast;
+use crate::controller::audit_ctl::audit;
+use crate::service::{
+    media_service::MediaService,
+    permission_service::{PermissionService, SiteRole},
+    upload_service::{UploadRejection, UploadService},
+};
+use crate::utils::{date_utils::{current_date_ymd, current_s}, storage_utils::STORAGE, str_from_map, str_from_map_required, str_to_usize};
 
-use crate::utils::{date_utils::{current_date_ymd, current_s}, oss_utils::OSS_CLIENT, str_from_map_required};
+// 聊天窗口保存 room_key 的 cookie
+const ROOM_KEY_COOKIE: &str = "bibirchat_ukey";
 
+// 上传聊天附件，表单需要带上 room_key，按房间所属站点的限制校验
+// 已登录的客服上传时记录上传人
 pub async fn upload(mut req: Request) -> Result {
     let (mut body, files) = req.parse_form_data::<Map>().await?;
+    let room_key = str_from_map_required("room_key", &body)?;
+    let Some((site, room)) = UploadService::find_target(&room_key).await.extract(&req)? else {
+        reject!(req, not_found, "room not found");
+    };
+    let uploader_id = req.get_data::<UserSession<_>>().map(|s| *s.user_id());
+    if let Some(user_id) = &uploader_id {
+        PermissionService::check(user_id, &site.id.to_string(), SiteRole::Agent)
+            .await
+            .extract(&req)?;
+    }
+    let uploader_type = if uploader_id.is_some() { "agent" } else { "visitor" };
+    let policy = UploadService::policy(&site);
     let mut uploads = Vec::new();
     for mut file in files {
         if let Some(file_name) = file.file_name() {
+            let bytes = file.bytes().to_vec();
+            let mime = match UploadService::check_file(&policy, &bytes) {
+                Ok(mime) => mime,
+                Err(rejection) => return upload_rejected(&req, &rejection, file_name),
+            };
+            // 图片去掉 EXIF 等元数据后再写入存储，不能解码的图片不接收
+            let bytes = if mime.starts_with("image/") {
+                match MediaService::strip_metadata(bytes).await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        tracing::warn!("{}", e);
+                        let rejection = UploadRejection::UnsupportedType { mime };
+                        return upload_rejected(&req, &rejection, file_name);
+                    }
+                }
+            } else {
+                bytes
+            };
+            // 先占用配额再写入存储，失败时退回
+            let size = bytes.len() as u64;
+            if let Err(rejection) = UploadService::reserve_quota(&policy, &site.id, &room.id, size)
+                .await
+                .extract(&req)?
+            {
+                return upload_rejected(&req, &rejection, file_name);
+            }
+            let safe_name = UploadService::sanitize_file_name(file_name, &mime);
             let current_date = current_date_ymd();
             let current_s = current_s();
             let random = rand::thread_rng().gen_range(1..101);
-            let new_file_name = format!("{}{}_{}",current_s,random, file_name);
+            let new_file_name = format!("{}{}_{}", current_s, random, safe_name);
             let key = format!("/ada_chat/files/{}/{}", current_date, new_file_name);
-            match OSS_CLIENT
-                .upload_file_bytes(&key, &file.bytes().to_vec())
+            // 站点开启加密时写入存储的是密文
+            let stored = async {
+                let (stored, key_id) = MediaService::seal(&site, &bytes)?;
+                STORAGE
+                    .upload_file_bytes(&key, &stored)
+                    .await
+                    .map_err(|e| warn!("upload file error: {}", e))?;
+                MediaService::create(
+                    &room,
+                    uploader_type,
+                    uploader_id,
+                    &safe_name,
+                    &key,
+                    &mime,
+                    bytes.len() as i64,
+                    key_id,
+                )
                 .await
-            {
-                Ok(file_url) => {
-                    let mut map = Map::new();
-                    tracing::info!("upload file: {:?}", file_url);
-                    map.upsert("url", file_url);
-                    map.upsert("field_name", file.field_name());
-                    map.upsert("file_name", file_name);
-                    map.upsert("file_key", key);
-                    uploads.push(map);
-                }
+            }
+            .await;
+            let media = match stored {
+                Ok(media) => media,
                 Err(e) => {
                     tracing::error!("{:#?}", e);
+                    UploadService::record_usage(&site.id, &room.id, -(size as i64), -1)
+                        .await
+                        .extract(&req)?;
                     return Err(
                         Rejection::with_message(format!("upload file error: {:?}", e)).into(),
                     );
                 }
-            }
+            };
+            spawn_process_upload(room.clone(), media.clone());
+            let mut map = Map::new();
+            tracing::info!("upload file: {:?}", key);
+            map.upsert("url", MediaService::link(&media.id, None));
+            map.upsert("field_name", file.field_name());
+            map.upsert("file_name", safe_name);
+            map.upsert("file_key", key);
+            map.upsert("file_type", mime);
+            map.upsert("file_size", bytes.len());
+            map.upsert("media_id", media.id.to_string());
+            map.upsert("scan_status", media.scan_status.clone());
+            uploads.push(map);
         }
     }
     body.upsert("files", uploads);
@@ -45,31 +122,236 @@ pub async fn upload(mut req: Request) -> Result {
     Ok(res.into())
 }
 
-pub async fn delete_file(mut req: Request) -> Result{
+// 后台扫描病毒、生成缩略图，完成后通知房间内的客户端更新附件
+fn spawn_process_upload(room: ChatRoom, media: ChatMedia) {
+    actix::spawn(async move {
+        let media = match MediaService::process_upload(media).await {
+            Ok(media) => media,
+            Err(e) => {
+                tracing::warn!("process upload error: {}", e);
+                return;
+            }
+        };
+        let update = json!({
+            "room_id": room.id.to_string(),
+            "media_update": MediaService::to_file(&media),
+        });
+        SERVER.do_send(RoomBroadcast {
+            room: room.id.to_string(),
+            msg: update.to_string(),
+        });
+    });
+}
+
+// 上传被拒绝时返回 {error, message, ...}，窗口直接展示 message
+fn upload_rejected(req: &Request, rejection: &UploadRejection, file_name: &str) -> Result {
+    let status = match rejection {
+        UploadRejection::UnsupportedType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => StatusCode::PAYLOAD_TOO_LARGE,
+    };
+    tracing::warn!("upload rejected: {} {}", rejection.code(), file_name);
+    let mut res = Response::default().context(req);
+    res.set_code(status);
+    res.set_json_data(rejection.to_json(file_name));
+    Ok(res.into())
+}
+
+// 删除附件，按 media_id 或 file_key 查找
+// 访客只能删除自己在本房间上传的附件（room_key），客服可以删除所在站点的附件
+pub async fn delete_file(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let media_id = str_from_map("media_id", &body)?;
+    let file_key = str_from_map("file_key", &body)?;
+    let Some(media) = MediaService::find(media_id.as_deref(), file_key.as_deref())
+        .await
+        .extract(&req)?
+    else {
+        reject!(req, not_found, "file not found");
+    };
+    let user_id = req.get_data::<UserSession<_>>().map(|s| *s.user_id());
+    match &user_id {
+        Some(user_id) => {
+            PermissionService::check(user_id, &media.site_id.to_string(), SiteRole::Agent)
+                .await
+                .extract(&req)?;
+        }
+        None => {
+            let room_key = str_from_map_required("room_key", &body)?;
+            let owned = match UploadService::find_target(&room_key).await.extract(&req)? {
+                Some((_, room)) => room.id == media.room_id && media.uploader_type == "visitor",
+                None => false,
+            };
+            if !owned {
+                reject!(req, forbidden, "the file is not uploaded by this room");
+            }
+        }
+    }
+    let tombstoned = MediaService::delete(&media).await.extract(&req)?;
+    if user_id.is_some() {
+        audit(
+            &req,
+            Some(media.site_id),
+            user_id,
+            "file.delete",
+            ("ChatMedia", media.id.to_string()),
+            Map::from_entry("file_key", json!({"before": &media.path, "after": JsonValue::Null})),
+        ).await;
+    }
     let mut res = Response::default().context(&req);
-    let mut data: HashMap<String, String> = HashMap::new();
-    data.insert("message".to_owned(), "删除成功".to_owned());
-    data.insert("success".to_owned(), "true".to_owned());
-    res.set_json_data(json!(data));
+    res.set_json_data(json!({
+        "media_id": media.id,
+        "file_key": media.path,
+        "tombstoned_messages": tombstoned,
+    }));
     Ok(res.into())
 }
 
+// 附件的短期签名链接，客服按站点权限，访客需要附件所在房间的 room_key
 pub async fn get_file_url(mut req: Request) -> Result {
     let body = req.parse_body::<Map>().await?;
+    let media_id = str_from_map("media_id", &body)?;
+    let file_key = str_from_map("file_key", &body)?;
+    let Some(media) = MediaService::find(media_id.as_deref(), file_key.as_deref())
+        .await
+        .extract(&req)?
+    else {
+        reject!(req, not_found, "file not found");
+    };
+    let user_id = req.get_data::<UserSession<_>>().map(|s| *s.user_id());
+    match &user_id {
+        Some(user_id) => {
+            PermissionService::check(user_id, &media.site_id.to_string(), SiteRole::Viewer)
+                .await
+                .extract(&req)?;
+        }
+        None => {
+            let room_key = str_from_map_required("room_key", &body)?;
+            if !MediaService::in_room(&media, &room_key).await.extract(&req)? {
+                reject!(req, forbidden, "the file does not belong to this room");
+            }
+        }
+    }
+    let size = str_from_map("size", &body)?;
+    let Some(url) = MediaService::signed_url(&media, size.as_deref())
+        .await
+        .extract(&req)?
+    else {
+        reject!(req, not_found, "file not found");
+    };
     let mut res = Response::default().context(&req);
-    let file_key = str_from_map_required("file_key", &body)?;
-    // let _shop_id_uid = Uuid::from_str(&shop_id_str).extract(&req)?;
-    let mut data: HashMap<String, String> = HashMap::new();
-    match OSS_CLIENT.generate_signed_url(&file_key, 60 * 30).await {
-        Ok(url) => {
-            data.insert("url".to_string(), url);
+    res.set_json_data(json!({
+        "url": url,
+        "media_id": media.id,
+        "file_key": media.path,
+        "expires_in": SETTINGS.storage_url_expire_secs,
+    }));
+    Ok(res.into())
+}
+
+// 附件链接：授权后跳转到短期签名链接，size=thumb|preview 取缩略图
+// 链接本身不带凭据，客服用 Authorization 登录令牌，访客用 X-Room-Key 请求头或窗口的 room_key cookie
+pub async fn media_file(req: Request) -> Result {
+    let media_id = req.parse_param::<String>("media_id")?;
+    let query = req.parse_query::<Map>()?;
+    let Some(media) = MediaService::find(Some(&media_id), None).await.extract(&req)? else {
+        reject!(req, not_found, "file not found");
+    };
+    match parse_user_id(&req) {
+        Some(user_id) => {
+            PermissionService::check(&user_id, &media.site_id.to_string(), SiteRole::Viewer)
+                .await
+                .extract(&req)?;
         }
-        Err(e) => {
-            return Err(Rejection::with_message(format!("get file url error: {:?}", e)).into());
+        None => {
+            let room_key = req
+                .get_header("x-room-key")
+                .map(|k| k.to_owned())
+                .or_else(|| req.get_cookie(ROOM_KEY_COOKIE).map(|c| c.value().to_owned()));
+            let in_room = match room_key {
+                Some(room_key) => MediaService::in_room(&media, &room_key).await.extract(&req)?,
+                None => false,
+            };
+            if !in_room {
+                reject!(req, forbidden, "the file does not belong to this room");
+            }
         }
     }
-    data.insert("file_key".to_owned(), file_key);
-    res.set_json_data(json!(data));
+    let Some(url) = MediaService::signed_url(&media, query.get_str("size"))
+        .await
+        .extract(&req)?
+    else {
+        reject!(req, not_found, "file not found");
+    };
+    let mut res = Response::default().context(&req);
+    res.set_code(StatusCode::FOUND);
+    res.insert_header("Location", &url);
+    res.insert_header("Cache-Control", "no-store");
+    Ok(res.into())
+}
+
+// /load 下没有登录中间件，带了有效的登录令牌时按客服处理
+fn parse_user_id(req: &Request) -> Option<Uuid> {
+    req.get_header("authorization")?;
+    let claims = req.parse_jwt_claims(JwtClaims::shared_key()).ok()?;
+    let user_session = UserSession::<Uuid>::try_from_jwt_claims(claims).ok()?;
+    Some(*user_session.user_id())
+}
+
+// fs memory 存储的签名链接
+pub async fn download(req: Request) -> Result {
+    let query = req.parse_query::<Map>()?;
+    let (Some(key), Some(expires), Some(signature)) = (
+        query.get_str("key"),
+        query.get_str("expires"),
+        query.get_str("signature"),
+    ) else {
+        reject!(req, forbidden, "the signed url is invalid");
+    };
+    let expires = str_to_usize(expires)? as u64;
+    if !STORAGE.verify_signed_url(key, expires, signature) {
+        reject!(req, forbidden, "the signed url is invalid or expired");
+    }
+    let bytes = match STORAGE.read(key).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("read file {} error: {}", key, e);
+            reject!(req, not_found, "file not found");
+        }
+    };
+    let content_type = mime_guess::from_path(key).first_or_octet_stream();
+    let mut res = Response::default().context(&req);
+    res.set_content_type(content_type.to_string());
+    res.set_bytes(bytes);
     Ok(res.into())
 }
 
+// 解密下载加密存储的附件，access_key_id 是附件 id，令牌由站点密钥签发
+pub async fn decrypt_file(req: Request) -> Result {
+    let query = req.parse_query::<Map>()?;
+    let access_key_id = req.parse_access_key_id()?;
+    let media_id = access_key_id.to_string();
+    let Some(media) = MediaService::find(Some(&media_id), None).await.extract(&req)? else {
+        reject!(req, not_found, "file not found");
+    };
+    let Some(key_id) = media.key_id.as_deref() else {
+        reject!(req, not_found, "file not found");
+    };
+    let secret_key = MediaService::secret_key(key_id);
+    let security_token = req.parse_security_token(secret_key.as_ref())?;
+    if security_token.is_expired() {
+        reject!(req, forbidden, "the security token has expired");
+    }
+    if !MediaService::downloadable(&media) {
+        reject!(req, not_found, "file not found");
+    }
+
+    let decryption_start_time = Instant::now();
+    let file = MediaService::decrypted_file(&media, query.get_str("size"))
+        .await
+        .extract(&req)?;
+    let mut res = Response::default().context(&req);
+    res.record_server_timing("dec", None, Some(decryption_start_time.elapsed()));
+    res.insert_header("Cache-Control", "private, no-store");
+    res.send_file(file);
+    Ok(res.into())
+}
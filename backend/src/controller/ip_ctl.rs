@@ -1,12 +1,24 @@
+use crate::controller::chat_ctl::find_user_site;
 use crate::service::ip_service::IpService;
+use crate::service::permission_service::SiteRole;
 use crate::utils::str_from_map_required;
 use zino::prelude::{Rejection, RequestContext};
 use zino::{Request, Response, Result};
+use zino_core::auth::UserSession;
 use zino_core::error::Error;
-use zino_core::{json, warn, Map};
+use zino_core::response::ExtractRejection;
+use zino_core::{json, warn, Map, Uuid};
 
+// 查询访客 IP 归属地，需要站点的查看权限
 pub async fn ip_detail(mut req: Request) -> Result {
     let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
     let mut res = Response::default().context(&req);
     let ip: String = str_from_map_required("ip", &body)?;
     match IpService::ip_detail(&ip).await {
@@ -0,0 +1,176 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    json,
+    response::{ExtractRejection, Rejection},
+    warn, Map, Uuid,
+};
+
+use crate::{
+    controller::{audit_ctl::audit, chat_ctl::find_user_site},
+    service::{
+        invitation_service::InvitationService,
+        permission_service::{PermissionService, SiteRole},
+    },
+    utils::{str_from_map, str_from_map_required},
+};
+
+// 站点成员
+pub async fn list_members(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let data = PermissionService::list_roster(&chat_site)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 添加成员或修改角色
+pub async fn save_member(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let member_user_id = Uuid::parse_str(&str_from_map_required("user_id", &body)?).extract(&req)?;
+    let role = str_from_map_required("role", &body)?;
+    let member = PermissionService::save_member(&chat_site, &member_user_id, &role)
+        .await
+        .extract(&req)?;
+    audit(
+        &req,
+        Some(chat_site.id),
+        Some(*user_id),
+        "member.save",
+        ("SiteMember", member.id.to_string()),
+        Map::from_entry("role", json!({"after": &member.role})),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(member));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn remove_member(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let member_user_id = Uuid::parse_str(&str_from_map_required("user_id", &body)?).extract(&req)?;
+    PermissionService::remove_member(&chat_site, &member_user_id)
+        .await
+        .extract(&req)?;
+    audit(
+        &req,
+        Some(chat_site.id),
+        Some(*user_id),
+        "member.remove",
+        ("User", member_user_id.to_string()),
+        Map::new(),
+    ).await;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 邮件邀请客服
+pub async fn invite_member(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let email = str_from_map_required("email", &body)?;
+    let role = str_from_map("role", &body)?.unwrap_or("agent".to_owned());
+    let mut invitation = InvitationService::invite(&chat_site, user_id, &email, &role)
+        .await
+        .extract(&req)?;
+    invitation.token_hash = String::new();
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(invitation));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn list_invitations(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let data = InvitationService::list_invitations(&chat_site.id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn revoke_invitation(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let invitation_id = str_from_map_required("id", &body)?;
+    InvitationService::revoke_invitation(&chat_site.id, &invitation_id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 以下为受邀人使用，无需登录
+pub async fn check_invitation(req: Request) -> Result {
+    let Some(token) = req.get_query("token") else {
+        return Err(Rejection::from_error(warn!("token should provied")).into());
+    };
+    let data = InvitationService::check(token).await.extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn accept_invitation(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let token = str_from_map_required("token", &body)?;
+    let name = str_from_map("name", &body)?;
+    let password = str_from_map("password", &body)?;
+    let member = InvitationService::accept(&token, name, password)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(member));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
@@ -1,7 +1,13 @@
+pub mod api_ctl;
+pub mod audit_ctl;
 pub(crate) mod auth;
+pub mod auto_reply_ctl;
 pub(crate) mod file;
 pub(crate) mod stats;
 pub(crate) mod user;
 pub(crate) mod chat_ctl;
 pub mod file_ctl;
-pub mod ip_ctl;
\ No newline at end of file
+pub mod ip_ctl;
+pub mod member_ctl;
+pub mod ticket_ctl;
+pub mod webhook_ctl;
@@ -1,5 +1,15 @@
+use actix_web::http::StatusCode;
 use zino::{prelude::*, Cluster, Request, Response, Result};
 
+use crate::{
+    controller::chat_ctl::find_user_site,
+    service::{analytics_service::AnalyticsService, permission_service::SiteRole},
+    utils::{
+        date_utils::{current_date_ymd, date_ymd_days_ago},
+        str_from_map, str_from_map_required,
+    },
+};
+
 pub async fn index(req: Request) -> Result {
     let res = Response::default().context(&req);
     let stats = json!({
@@ -13,3 +23,46 @@ pub async fn index(req: Request) -> Result {
     });
     Ok(res.render("output.html", data).into())
 }
+
+// 站点会话统计，默认最近 7 天
+pub async fn chat_stats(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let from = str_from_map("from", &body)?.unwrap_or(date_ymd_days_ago(6));
+    let to = str_from_map("to", &body)?.unwrap_or(current_date_ymd());
+    let agent_id = str_from_map("agent_id", &body)?;
+    let data = AnalyticsService::site_stats(&chat_site.id, agent_id, &from, &to)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 日期范围内各客服统计
+pub async fn agent_stats(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let from = str_from_map("from", &body)?.unwrap_or(date_ymd_days_ago(6));
+    let to = str_from_map("to", &body)?.unwrap_or(current_date_ymd());
+    let data = AnalyticsService::agent_stats(&chat_site.id, &from, &to)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
@@ -0,0 +1,151 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    json,
+    model::Query,
+    orm::Schema,
+    response::{ExtractRejection, Rejection},
+    warn, Map, Uuid,
+};
+use zino_model::User;
+
+use crate::{
+    controller::chat_ctl::find_user_site,
+    model::{ChatRoom, ChatWebsite},
+    service::{permission_service::SiteRole, ticket_service::TicketService},
+    utils::{is_valid_email, str_from_map, str_from_map_required, usize_from_map_default},
+};
+
+// 访客留言
+pub async fn leave_message(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let site_key = str_from_map_required("site_key", &body)?;
+    let room_key = str_from_map("room_key", &body)?;
+    let name = str_from_map_required("name", &body)?;
+    let email = str_from_map_required("email", &body)?;
+    let content = str_from_map_required("content", &body)?;
+    if !is_valid_email(&email) {
+        return Err(Rejection::from_error(warn!("invalid email")).into());
+    }
+    let ticket = TicketService::leave_message(&site_key, room_key, &name, &email, &content)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(ticket));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 访客再次打开窗口时获取留言回复
+pub async fn ticket_replies(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let site_key = str_from_map_required("site_key", &body)?;
+    let room_key = str_from_map_required("room_key", &body)?;
+    let query = Query::from_entry("site_key", site_key);
+    let chat_site = match ChatWebsite::find_one::<ChatWebsite>(&query).await {
+        Ok(site) => {
+            if site.is_some() {
+                site.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room site not found")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let mut room_query = Query::from_entry("room_key", room_key);
+    room_query.add_filter("room_site_id", chat_site.id.to_string());
+    let room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
+        Ok(ro) => {
+            if ro.is_some() {
+                ro.unwrap()
+            } else {
+                return Err(Rejection::from_error(warn!("room forbidden")).into());
+            }
+        }
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let data = TicketService::take_undelivered_replies(&room)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 客服留言箱
+pub async fn list_tickets(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Viewer).await?;
+    let status = str_from_map("status", &body)?;
+    let page = usize_from_map_default("page", &body, 1)?;
+    let page_size = usize_from_map_default("page_size", &body, 10)?;
+    let res = &mut Response::default().context(&req);
+    match TicketService::list_tickets(&chat_site.id, status, page, page_size).await {
+        Ok(data) => {
+            res.set_json_data(json!(data));
+            res.set_code(StatusCode::OK);
+        }
+        Err(e) => {
+            res.set_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.set_error_message(e);
+        }
+    }
+    Ok(res.clone().into())
+}
+
+pub async fn reply_ticket(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Agent).await?;
+    let ticket_id = str_from_map_required("ticket_id", &body)?;
+    let content = str_from_map_required("content", &body)?;
+    let user_name = match User::find_by_id::<User>(user_id).await {
+        Ok(Some(u)) => u.name().to_string(),
+        Ok(None) => "".to_owned(),
+        Err(e) => return Err(Rejection::from_error(e).into()),
+    };
+    let mut ticket = TicketService::find_ticket(&chat_site.id, &ticket_id)
+        .await
+        .extract(&req)?;
+    TicketService::reply_ticket(&mut ticket, user_id, &user_name, &content)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(ticket));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn close_ticket(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Agent).await?;
+    let ticket_id = str_from_map_required("ticket_id", &body)?;
+    let mut ticket = TicketService::find_ticket(&chat_site.id, &ticket_id)
+        .await
+        .extract(&req)?;
+    TicketService::close_ticket(&mut ticket).await.extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(ticket));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
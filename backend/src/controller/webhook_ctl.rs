@@ -0,0 +1,138 @@
+use actix_web::http::StatusCode;
+use zino::prelude::RequestContext;
+use zino::{Request, Response, Result};
+use zino_core::{
+    auth::UserSession,
+    extension::JsonObjectExt,
+    json,
+    response::ExtractRejection,
+    warn, Map, Uuid,
+};
+
+use crate::{
+    controller::chat_ctl::find_user_site,
+    model::WebhookSubscription,
+    service::{permission_service::SiteRole, webhook_service::WebhookService},
+    utils::{str_from_map, str_from_map_required, usize_from_map_default},
+};
+
+pub async fn list_webhooks(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let mut data = WebhookService::list_subscriptions(&chat_site.id)
+        .await
+        .extract(&req)?;
+    // 密钥只在创建时返回
+    for sub in data.iter_mut() {
+        sub.secret = "******".to_owned();
+    }
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(data));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn save_webhook(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+
+    let mut sub = WebhookSubscription::default();
+    if let Some(id) = str_from_map("id", &body)? {
+        sub.id = Uuid::parse_str(&id).extract(&req)?;
+    }
+    sub.site_id = chat_site.id;
+    sub.url = str_from_map_required("url", &body)?;
+    sub.secret = str_from_map("secret", &body)?.unwrap_or_default();
+    sub.events = body
+        .parse_str_array("events")
+        .map(|v| v.into_iter().map(|e| e.to_owned()).collect())
+        .unwrap_or_default();
+    sub.status = str_from_map("status", &body)?.unwrap_or("active".to_owned());
+    WebhookService::save_subscription(&mut sub)
+        .await
+        .extract(&req)?;
+
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(sub));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+pub async fn delete_webhook(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let sub_id = str_from_map_required("id", &body)?;
+    WebhookService::delete_subscription(&chat_site.id, &sub_id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
+
+// 推送记录
+pub async fn list_deliveries(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let subscription_id = str_from_map("subscription_id", &body)?;
+    let status = str_from_map("status", &body)?;
+    let page = usize_from_map_default("page", &body, 1)?;
+    let page_size = usize_from_map_default("page_size", &body, 10)?;
+    let res = &mut Response::default().context(&req);
+    match WebhookService::list_deliveries(&chat_site.id, subscription_id, status, page, page_size)
+        .await
+    {
+        Ok(data) => {
+            res.set_json_data(json!(data));
+            res.set_code(StatusCode::OK);
+        }
+        Err(e) => {
+            res.set_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.set_error_message(e);
+        }
+    }
+    Ok(res.clone().into())
+}
+
+pub async fn replay_delivery(mut req: Request) -> Result {
+    let body = req.parse_body::<Map>().await?;
+    let user_session = req
+        .get_data::<UserSession<_>>()
+        .ok_or_else(|| warn!("401 Unauthorized: the user session is invalid"))
+        .extract(&req)?;
+    let user_id: &Uuid = user_session.user_id();
+    let site_id = str_from_map_required("site_id", &body)?;
+    let chat_site = find_user_site(user_id, &site_id, SiteRole::Admin).await?;
+    let delivery_id = str_from_map_required("delivery_id", &body)?;
+    let delivery = WebhookService::replay(&chat_site.id, &delivery_id)
+        .await
+        .extract(&req)?;
+    let res = &mut Response::default().context(&req);
+    res.set_json_data(json!(delivery));
+    res.set_code(StatusCode::OK);
+    Ok(res.clone().into())
+}
@@ -13,6 +13,14 @@ pub struct WebsiteConfig {
     pub title: Option<String>,
     pub welcome_slogan: Option<String>,
     pub site_key: Option<String>,
+    pub domain: Option<String>,
     pub user_id: Uuid,
-    pub position: Option<String>
+    pub position: Option<String>,
+    pub bot_type: Option<String>,
+    pub bot_webhook_url: Option<String>,
+    pub upload_max_bytes: Option<i64>,
+    pub upload_mime_types: Option<String>,
+    pub room_quota_bytes: Option<i64>,
+    pub site_quota_bytes: Option<i64>,
+    pub encrypt_attachments: Option<bool>,
 }
@@ -1,11 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
-use chrono::Duration;
 use serde::{Deserialize, Serialize};
-use zino::prelude::{DateTime, Query, Schema};
-use zino_core::json;
+use zino::prelude::DateTime;
 
-use crate::{model::{ChatRoom, ChatWebsite}, service::room_message_state::MessageStatusManager, utils::date_utils::{current_date, date_ymdhms, format_date_ymdhms}};
+use crate::{model::chat_files::ChatFiles, service::room_message_state::MessageStatusManager, utils::date_utils::{current_date, current_ms, date_ymdhms, format_date_ymdhms}};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ChatMessageDto {
@@ -13,9 +11,13 @@ pub struct ChatMessageDto {
     pub time: String,
     pub user: bool,
     pub user_name: Option<String>,
-    pub str_files: Option<String>,
+    pub files: Vec<ChatFiles>,
     pub notify: String,
     pub room_id: Option<String>,
+    // 客服不在线，访客可留言
+    pub offline: bool,
+    // bot system，其余为空
+    pub sender_type: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -40,9 +42,11 @@ impl ChatMessageDto {
             time: time,
             user: user,
             user_name: user_name,
-            str_files: Some("".to_string()),
+            files: Vec::new(),
             notify: "".to_string(),
             room_id,
+            offline: false,
+            sender_type: None,
         }
     }
 
@@ -54,13 +58,45 @@ impl ChatMessageDto {
             time: time,
             user: from_user,
             user_name: user_name,
-            str_files: Some("".to_string()),
+            files: Vec::new(),
             notify: notify.to_string(),
             room_id,
+            offline: false,
+            sender_type: None,
         }
     }
 
-    pub fn new_text_str_files_msg(text:&str, files: Option<String>, from_user: bool, user_name: Option<String>, room_id: Option<String>) -> Self {
+    pub fn new_offline_msg(notify:&str, room_id: Option<String>) -> Self {
+        let time = date_ymdhms(current_date());
+        Self {
+            text: "".to_string(),
+            time: time,
+            user: false,
+            user_name: None,
+            files: Vec::new(),
+            notify: notify.to_string(),
+            room_id,
+            offline: true,
+            sender_type: Some("system".to_string()),
+        }
+    }
+
+    pub fn new_bot_msg(text:&str, bot_name: Option<String>, room_id: Option<String>) -> Self {
+        let time = date_ymdhms(current_date());
+        Self {
+            text: text.to_string(),
+            time: time,
+            user: false,
+            user_name: bot_name,
+            files: Vec::new(),
+            notify: "".to_string(),
+            room_id,
+            offline: false,
+            sender_type: Some("bot".to_string()),
+        }
+    }
+
+    pub fn new_text_files_msg(text:&str, files: Vec<ChatFiles>, from_user: bool, user_name: Option<String>, room_id: Option<String>) -> Self {
         let time = date_ymdhms(current_date());
         let mut user = from_user;
         if user_name.is_none() {
@@ -71,9 +107,11 @@ impl ChatMessageDto {
             time: time,
             user: user,
             user_name: user_name,
-            str_files: files,
+            files,
             notify: "".to_string(),
             room_id,
+            offline: false,
+            sender_type: None,
         }
     }
 
@@ -85,42 +123,120 @@ pub struct ChatNotify {
     pub message: ChatNotifyMessageDto,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ChatNotifyMessageDto {
     pub total_unread: i32,
     pub new_message: bool,
     pub message_counts: HashMap<String, i32>,
+    // 请求转人工的房间
+    pub handoff_room_id: Option<String>,
+    // 为 true 时 message_counts 只包含有变化的房间
+    pub delta: bool,
 }
 
 impl ChatNotify {
+    // 未读数只从 Redis 一次取回，不再查询站点和房间
     pub async fn new_from_redis(site_key: &str) -> Self {
-        let total_unread = MessageStatusManager::get_total_unread(site_key).await.unwrap_or(0);
-        // let room_set = MessageStatusManager::get_site_rooms(site_key).await.unwrap_or(HashSet::new());
-        let mut room_counts: HashMap<String,i32> = HashMap::new();
-        let query = Query::from_entry("site_key", site_key);
-        if let Ok(site)  = ChatWebsite::find_one::<ChatWebsite>(&query).await {
-            if let Some(s) = site {
-                let mut rooms_query = Query::from_entry("room_site_id", s.id.to_string());
-                // rooms_query.add_filter("status", "active");
-                let date = current_date() - Duration::hours(24);
-                rooms_query.add_filter("update_at", json!({"$gt": date}));
-                if let Ok(rooms) = ChatRoom::find::<ChatRoom>(&rooms_query).await{
-                    tracing::info!("site {} room_set: {:?}", &site_key, &rooms);
-                    for room in rooms.iter() {
-                        let (unread_counts, _) = MessageStatusManager::get_room_message_counts(site_key, &room.id.to_string()).await.unwrap_or((0,0));
-                        room_counts.insert(room.id.to_string(), unread_counts as i32);
-                    }
-                }
-            }
-        }
+        let since = current_ms() as i64 - 24 * 3600 * 1000;
+        let (total_unread, room_counts) = MessageStatusManager::site_unread_counts(site_key, since)
+            .await
+            .unwrap_or_default();
         let message = ChatNotifyMessageDto {
             total_unread: total_unread as i32,
             new_message: true,
-            message_counts: room_counts,
+            message_counts: room_counts
+                .into_iter()
+                .map(|(room, count)| (room, count as i32))
+                .collect(),
+            handoff_room_id: None,
+            delta: false,
         };
         Self {
             to_server: true,
             message: message,
         }
     }
-}
\ No newline at end of file
+
+    pub async fn new_handoff(site_key: &str, room_id: &str) -> Self {
+        let mut notify = Self::new_from_redis(site_key).await;
+        notify.message.handoff_room_id = Some(room_id.to_string());
+        notify
+    }
+
+    // 只保留与上次发送相比有变化的房间，没有任何变化时返回 false
+    pub fn into_delta(&mut self, last: &ChatNotifyMessageDto) -> bool {
+        let message = &mut self.message;
+        // 上次有未读、本次已不在列表中的房间清零，要在去掉未变化的房间之前找出来
+        let removed = last
+            .message_counts
+            .iter()
+            .filter(|(room, count)| **count != 0 && !message.message_counts.contains_key(*room))
+            .map(|(room, _)| room.clone())
+            .collect::<Vec<String>>();
+        message
+            .message_counts
+            .retain(|room, count| last.message_counts.get(room) != Some(count));
+        for room in removed {
+            message.message_counts.insert(room, 0);
+        }
+        message.delta = true;
+        !message.message_counts.is_empty()
+            || message.total_unread != last.total_unread
+            || message.handoff_room_id.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(entries: &[(&str, i32)]) -> HashMap<String, i32> {
+        entries.iter().map(|(room, count)| (room.to_string(), *count)).collect()
+    }
+
+    fn notify(entries: &[(&str, i32)]) -> ChatNotify {
+        ChatNotify {
+            to_server: true,
+            message: ChatNotifyMessageDto {
+                total_unread: entries.iter().map(|(_, count)| count).sum(),
+                new_message: true,
+                message_counts: counts(entries),
+                handoff_room_id: None,
+                delta: false,
+            },
+        }
+    }
+
+    #[test]
+    fn it_skips_unchanged_rooms() {
+        let last = notify(&[("a", 3), ("b", 1)]).message;
+        let mut current = notify(&[("a", 3), ("b", 1)]);
+        assert!(!current.into_delta(&last));
+        assert!(current.message.message_counts.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_only_changed_rooms() {
+        let last = notify(&[("a", 3), ("b", 1)]).message;
+        let mut current = notify(&[("a", 3), ("b", 2)]);
+        assert!(current.into_delta(&last));
+        assert!(current.message.delta);
+        assert_eq!(current.message.message_counts, counts(&[("b", 2)]));
+    }
+
+    #[test]
+    fn it_zeroes_removed_rooms() {
+        let last = notify(&[("a", 3), ("b", 1), ("c", 0)]).message;
+        let mut current = notify(&[("a", 3)]);
+        assert!(current.into_delta(&last));
+        assert_eq!(current.message.message_counts, counts(&[("b", 0)]));
+    }
+
+    #[test]
+    fn it_adds_new_rooms() {
+        let last = notify(&[("a", 3)]).message;
+        let mut current = notify(&[("a", 3), ("d", 1)]);
+        assert!(current.into_delta(&last));
+        assert_eq!(current.message.message_counts, counts(&[("d", 1)]));
+    }
+}
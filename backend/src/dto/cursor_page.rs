@@ -0,0 +1,213 @@
+use base64::{engine::general_purpose, Engine};
+use serde::{Deserialize, Serialize};
+use zino::prelude::Validation;
+use zino_core::{json, model::Query, response::Rejection, warn, Map, Uuid};
+
+use crate::utils::{str_from_map, str_to_usize};
+
+// 单页最大条数
+const MAX_LIMIT: usize = 100;
+
+/// 按 UUIDv7 id 翻页的游标条件，before 取更早的记录，after 取更新的记录
+#[derive(Debug, Clone, Default)]
+pub struct PageCursor {
+    pub before: Option<Uuid>,
+    pub after: Option<Uuid>,
+    pub limit: usize,
+}
+
+/// 一页数据，按 id 从新到旧排列
+/// next_cursor 继续当前方向，没有更多数据时为空；prev_cursor 用于反方向翻页
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CursorPage<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl PageCursor {
+    pub fn from_map(map: &Map) -> zino::Result<Self> {
+        let before = match str_from_map("before", map)? {
+            Some(c) => Some(decode_cursor(&c)?),
+            None => None,
+        };
+        let after = match str_from_map("after", map)? {
+            Some(c) => Some(decode_cursor(&c)?),
+            None => None,
+        };
+        if before.is_some() && after.is_some() {
+            let validation = Validation::from_entry("err_msg", warn!("before and after can not be used together"));
+            return Err(Rejection::bad_request(validation).into());
+        }
+        // 查询参数中的数字也是字符串
+        let limit = match str_from_map("page_size", map)? {
+            Some(size) => str_to_usize(&size)?.clamp(1, MAX_LIMIT),
+            None => 10,
+        };
+        Ok(Self { before, after, limit })
+    }
+
+    // 查询条件：多取一条判断是否还有下一页
+    pub fn apply(&self, query: &mut Query) {
+        match (self.before, self.after) {
+            (_, Some(after)) => {
+                query.add_filter("id", json!({"$gt": after.to_string()}));
+                query.order_asc("id");
+            }
+            (Some(before), None) => {
+                query.add_filter("id", json!({"$lt": before.to_string()}));
+                query.order_desc("id");
+            }
+            (None, None) => query.order_desc("id"),
+        }
+        query.set_limit(self.limit + 1);
+    }
+
+    pub fn page<T>(&self, mut rows: Vec<T>, id_of: impl Fn(&T) -> Uuid) -> CursorPage<T> {
+        let has_more = rows.len() > self.limit;
+        rows.truncate(self.limit);
+        let forward = self.after.is_some();
+        if forward {
+            rows.reverse();
+        }
+        let newest = rows.first().map(|r| encode_cursor(&id_of(r)));
+        let oldest = rows.last().map(|r| encode_cursor(&id_of(r)));
+        // 取更新的记录时，最新一条之后可能随时有新数据，总是返回 next_cursor
+        let (next_cursor, prev_cursor) = if forward {
+            (newest.or(self.after.map(|c| encode_cursor(&c))), oldest)
+        } else {
+            (oldest.filter(|_| has_more), newest)
+        };
+        CursorPage {
+            data: rows,
+            next_cursor,
+            prev_cursor,
+        }
+    }
+}
+
+pub fn encode_cursor(id: &Uuid) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(id.as_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> zino::Result<Uuid> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| Uuid::from_slice(&bytes).ok())
+        .ok_or_else(|| {
+            let validation = Validation::from_entry("err_msg", warn!("invalid cursor: {}", cursor));
+            Rejection::bad_request(validation).into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use zino_core::extension::JsonObjectExt;
+
+    use super::*;
+
+    // 按生成顺序从旧到新的 id
+    fn ids(n: usize) -> Vec<Uuid> {
+        (0..n).map(|_| Uuid::now_v7()).collect()
+    }
+
+    fn cursor(before: Option<Uuid>, after: Option<Uuid>, limit: usize) -> PageCursor {
+        PageCursor { before, after, limit }
+    }
+
+    #[test]
+    fn it_round_trips_cursors() {
+        let id = Uuid::now_v7();
+        let encoded = encode_cursor(&id);
+        assert_eq!(decode_cursor(&encoded).unwrap(), id);
+
+        let map = Map::from_entry("before", encoded);
+        let cursor = PageCursor::from_map(&map).unwrap();
+        assert_eq!(cursor.before, Some(id));
+        assert_eq!(cursor.after, None);
+        assert_eq!(cursor.limit, 10);
+    }
+
+    #[test]
+    fn it_rejects_invalid_cursors() {
+        assert!(decode_cursor("not a cursor!").is_err());
+        // 能解码但长度不是 16 字节
+        assert!(decode_cursor("AAAA").is_err());
+        assert!(PageCursor::from_map(&Map::from_entry("after", "???")).is_err());
+
+        let mut map = Map::from_entry("before", encode_cursor(&Uuid::now_v7()));
+        map.upsert("after", encode_cursor(&Uuid::now_v7()));
+        assert!(PageCursor::from_map(&map).is_err());
+    }
+
+    #[test]
+    fn it_clamps_page_size() {
+        let cursor = PageCursor::from_map(&Map::from_entry("page_size", "1000")).unwrap();
+        assert_eq!(cursor.limit, MAX_LIMIT);
+        let cursor = PageCursor::from_map(&Map::from_entry("page_size", "0")).unwrap();
+        assert_eq!(cursor.limit, 1);
+    }
+
+    #[test]
+    fn it_queries_one_more_row_in_the_cursor_direction() {
+        let id = Uuid::now_v7();
+        let mut query = Query::default();
+        cursor(Some(id), None, 10).apply(&mut query);
+        assert_eq!(query.filters().get("id"), Some(&json!({"$lt": id.to_string()})));
+        assert_eq!(query.limit(), 11);
+
+        let mut query = Query::default();
+        cursor(None, Some(id), 10).apply(&mut query);
+        assert_eq!(query.filters().get("id"), Some(&json!({"$gt": id.to_string()})));
+        assert_eq!(query.limit(), 11);
+    }
+
+    #[test]
+    fn it_pages_backward_from_newest() {
+        let ids = ids(5);
+        // 数据库按 id 倒序返回 limit + 1 条
+        let rows = vec![ids[4], ids[3], ids[2]];
+        let page = cursor(None, None, 2).page(rows, |id| *id);
+        assert_eq!(page.data, [ids[4], ids[3]]);
+        assert_eq!(page.next_cursor, Some(encode_cursor(&ids[3])));
+        assert_eq!(page.prev_cursor, Some(encode_cursor(&ids[4])));
+
+        let rows = vec![ids[2], ids[1], ids[0]];
+        let page = cursor(Some(ids[3]), None, 2).page(rows, |id| *id);
+        assert_eq!(page.data, [ids[2], ids[1]]);
+        assert_eq!(page.next_cursor, Some(encode_cursor(&ids[1])));
+    }
+
+    #[test]
+    fn it_stops_when_exactly_limit_rows_remain() {
+        let ids = ids(3);
+        let rows = vec![ids[1], ids[0]];
+        let page = cursor(Some(ids[2]), None, 2).page(rows, |id| *id);
+        assert_eq!(page.data, [ids[1], ids[0]]);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.prev_cursor, Some(encode_cursor(&ids[1])));
+
+        let page = cursor(Some(ids[0]), None, 2).page(Vec::<Uuid>::new(), |id| *id);
+        assert!(page.data.is_empty());
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.prev_cursor, None);
+    }
+
+    #[test]
+    fn it_pages_forward_and_returns_newest_first() {
+        let ids = ids(5);
+        // 数据库按 id 正序返回 limit + 1 条，返回时仍按从新到旧排列
+        let rows = vec![ids[1], ids[2], ids[3]];
+        let page = cursor(None, Some(ids[0]), 2).page(rows, |id| *id);
+        assert_eq!(page.data, [ids[2], ids[1]]);
+        assert_eq!(page.next_cursor, Some(encode_cursor(&ids[2])));
+        assert_eq!(page.prev_cursor, Some(encode_cursor(&ids[1])));
+
+        // 没有更新的记录时 next_cursor 保持不变，之后可以继续取新数据
+        let page = cursor(None, Some(ids[4]), 2).page(Vec::<Uuid>::new(), |id| *id);
+        assert!(page.data.is_empty());
+        assert_eq!(page.next_cursor, Some(encode_cursor(&ids[4])));
+        assert_eq!(page.prev_cursor, None);
+    }
+}
@@ -1 +1,2 @@
-pub mod chat_message_entity;
\ No newline at end of file
+pub mod chat_message_entity;
+pub mod cursor_page;
@@ -25,6 +25,7 @@ fn main() -> anyhow::Result<()> {
         .register_debug(router::debug_routes())
         .register_with(ServerTag::Main, router::main_routes())
         .spawn(schedule::job_scheduler());    
+    utils::metrics_utils::describe();
     app.run_with(schedule::async_job_scheduler());
     
     Ok(())
@@ -0,0 +1,92 @@
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::{ErrorPayloadTooLarge, PayloadError},
+    http::header::CONTENT_LENGTH,
+    Error,
+};
+use futures::StreamExt;
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+};
+
+/// 限制请求体大小，Content-Length 超出时直接拒绝，分块上传时边接收边计数
+/// 表单解析会一次读完请求体，上传接口要在解析前限制
+pub struct BodyLimit {
+    max_bytes: usize,
+}
+
+impl BodyLimit {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes: max_bytes as usize,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLimitMiddleware {
+            service,
+            max_bytes: self.max_bytes,
+        }))
+    }
+}
+
+pub struct BodyLimitMiddleware<S> {
+    service: S,
+    max_bytes: usize,
+}
+
+impl<S, B> Service<ServiceRequest> for BodyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let max_bytes = self.max_bytes;
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if content_length.is_some_and(|len| len > max_bytes) {
+            return Box::pin(async move {
+                Err(ErrorPayloadTooLarge(format!("request body exceeds {} bytes", max_bytes)))
+            });
+        }
+
+        let mut received = 0;
+        let payload = req.take_payload().map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len();
+            if received > max_bytes {
+                return Err(PayloadError::Overflow);
+            }
+            Ok(chunk)
+        });
+        req.set_payload(Payload::from(payload.boxed_local()));
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res)
+        })
+    }
+}
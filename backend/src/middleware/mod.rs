@@ -1,4 +1,6 @@
 mod access;
+mod body_limit;
 pub mod redis;
 
-pub(crate) use access::UserSessionInitializer;
\ No newline at end of file
+pub(crate) use access::UserSessionInitializer;
+pub(crate) use body_limit::BodyLimit;
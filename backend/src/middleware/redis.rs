@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 
 use anyhow::Result;
 use deadpool_redis::{redis::AsyncCommands, Pool, Runtime};
@@ -40,6 +42,20 @@ impl RedisManager {
         Ok(())
     }
 
+    /// SET NX EX，键不存在时设置并返回 true，用作多个实例之间的锁
+    pub async fn set_nx_ex(&self, key: &str, value: &str, seconds: u64) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let result: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(seconds)
+            .query_async(&mut conn)
+            .await?;
+        Ok(result.is_some())
+    }
+
     pub async fn expire(&self, key: &str, seconds: usize) -> Result<bool> {
         let mut conn = self.pool.get().await?;
         let result = conn.expire(key, seconds as i64).await?;
@@ -52,4 +68,100 @@ impl RedisManager {
         Ok(result)
     }
 
+    /// 执行 Lua 脚本，脚本内的多个命令原子执行
+    pub async fn eval<T: redis::FromRedisValue>(
+        &self,
+        script: &redis::Script,
+        keys: &[&str],
+        args: &[String],
+    ) -> Result<T> {
+        let mut conn = self.pool.get().await?;
+        let mut invocation = script.prepare_invoke();
+        for key in keys {
+            invocation.key(*key);
+        }
+        for arg in args {
+            invocation.arg(arg);
+        }
+        let result = invocation.invoke_async(&mut conn).await?;
+        Ok(result)
+    }
+
+    /// 使用 SCAN 遍历匹配的键，不阻塞 Redis
+    pub async fn scan_keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut conn = self.pool.get().await?;
+        let mut iter: redis::AsyncIter<String> = conn.scan_match(pattern).await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    pub async fn key_type(&self, key: &str) -> Result<String> {
+        let mut conn = self.pool.get().await?;
+        let result: String = redis::cmd("TYPE").arg(key).query_async(&mut conn).await?;
+        Ok(result)
+    }
+
+    pub async fn sadd(&self, key: &str, member: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.sadd(key, member).await?;
+        Ok(())
+    }
+
+    pub async fn srem(&self, key: &str, member: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.srem(key, member).await?;
+        Ok(())
+    }
+
+    pub async fn smembers(&self, key: &str) -> Result<HashSet<String>> {
+        let mut conn = self.pool.get().await?;
+        let result = conn.smembers(key).await?;
+        Ok(result)
+    }
+
+    pub async fn hset<T: redis::ToRedisArgs + Send + Sync>(&self, key: &str, field: &str, value: T) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.hset(key, field, value).await?;
+        Ok(())
+    }
+
+    pub async fn hget<T: redis::FromRedisValue>(&self, key: &str, field: &str) -> Result<Option<T>> {
+        let mut conn = self.pool.get().await?;
+        let result = conn.hget(key, field).await?;
+        Ok(result)
+    }
+
+    pub async fn hgetall<T: redis::FromRedisValue>(&self, key: &str) -> Result<HashMap<String, T>> {
+        let mut conn = self.pool.get().await?;
+        let result = conn.hgetall(key).await?;
+        Ok(result)
+    }
+
+    pub async fn hdel(&self, key: &str, field: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.hdel(key, field).await?;
+        Ok(())
+    }
+
+    pub async fn zadd(&self, key: &str, member: &str, score: i64) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.zadd(key, member, score).await?;
+        Ok(())
+    }
+
+    pub async fn zrem(&self, key: &str, member: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        conn.zrem(key, member).await?;
+        Ok(())
+    }
+
+    /// 一次往返执行多个命令，pipe.atomic() 时包在 MULTI/EXEC 中
+    pub async fn pipeline<T: redis::FromRedisValue>(&self, pipe: &redis::Pipeline) -> Result<T> {
+        let mut conn = self.pool.get().await?;
+        let result = pipe.query_async(&mut conn).await?;
+        Ok(result)
+    }
 }
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 管理和客服操作日志，只追加不修改
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct AuditEvent {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(reference = "ChatWebsite", index_type = "btree")]
+    pub site_id: Option<Uuid>,
+    #[schema(reference = "User", comment = "operator", index_type = "btree")]
+    pub actor_id: Option<Uuid>,
+    // auth.login auth.login_failed auth.logout site.update site.archive room.close room.assign room.transfer
+    // message.delete member.save member.remove
+    #[schema(not_null, index_type = "hash")]
+    pub action: String,
+    #[schema(not_null, comment = "target model name")]
+    pub target_model: String,
+    #[schema(index_type = "btree")]
+    pub target_id: String,
+    #[schema(comment = "changed fields with before and after values")]
+    pub diff: Map,
+    pub ip: Option<String>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 站点自动回复规则
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct AutoReplyRule {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        comment = "rule site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(not_null, comment = "rule name")]
+    pub name: String,
+    #[schema(default_value = "keyword", index_type = "hash")] // keyword regex first_message
+    pub match_type: String,
+    // keyword 为逗号分隔的关键词，regex 为正则表达式，first_message 可为空
+    pub pattern: String,
+    #[schema(not_null)]
+    pub reply: String,
+    // 回复者名称，为空时使用站点标题
+    pub sender_name: Option<String>,
+    // 生效时间段 HH:MM，可跨零点，均为空时全天生效
+    pub hours_start: Option<String>,
+    pub hours_end: Option<String>,
+    // 数值越小越优先
+    pub priority: i32,
+    #[schema(default_value = "active", index_type = "hash")] // active inactive
+    pub status: String,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
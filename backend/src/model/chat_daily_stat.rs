@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 按站点、客服、天汇总的会话统计，agent_id 为空 uuid 的一条是整个站点
+/// 时长保存总秒数和次数，任意日期范围都可以准确计算平均值
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+#[schema(unique_on = "site_id, agent_id, day")]
+pub struct ChatDailyStat {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(reference = "ChatWebsite", index_type = "btree")]
+    pub site_id: Uuid,
+    #[schema(index_type = "btree")]
+    pub agent_id: Uuid,
+    #[schema(not_null, index_type = "btree", comment = "YYYY-MM-DD in configured time zone")]
+    pub day: String,
+    pub conversations: u64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub missed: u64,
+    pub first_response_count: u64,
+    pub first_response_secs: u64,
+    pub response_count: u64,
+    pub response_secs: u64,
+    pub duration_count: u64,
+    pub duration_secs: u64,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
+use zino_core::Uuid;
 
+/// 消息中展开的附件，url 为带有效期的签名链接
 #[derive(
     Debug,
     Clone,
@@ -8,9 +10,18 @@ use serde::{Deserialize, Serialize};
     Deserialize,
 )]
 #[serde(default)]
-pub struct 
-ChatFiles {
-    file_key: String,
-    url: String,
-    file_name: String,
+pub struct ChatFiles {
+    pub id: Uuid,
+    pub file_key: String,
+    pub url: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub file_size: i64,
+    // 图片生成缩略图后才有
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub thumb_url: Option<String>,
+    pub preview_url: Option<String>,
+    // pending clean infected
+    pub scan_status: String,
 }
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
 use zino::prelude::*;
 use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatRoom;
 
+/// 上传的附件，消息通过 file_ids 引用
 #[derive(
     Debug,
     Clone,
@@ -17,13 +21,45 @@ use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
 #[serde(default)]
 pub struct ChatMedia {
     #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
-    id: Uuid,
-    file_name: String,
-    path: String,
-    file_type: String,
+    pub id: Uuid,
+    #[schema(index_type = "btree")]
+    pub site_id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatRoom",
+        fetch_as = "room",
+        index_type = "btree"
+    )]
+    pub room_id: Uuid,
+    #[schema(default_value = "uploaded", index_type = "hash")] // uploaded attached
+    pub status: String,
+    #[schema(default_value = "visitor", index_type = "hash")] // visitor agent bot
+    pub uploader_type: String,
+    #[schema(reference = "User", comment = "agent who uploaded the file")]
+    pub uploader_id: Option<Uuid>,
+    pub file_name: String,
+    // 存储中的 key
+    pub path: String,
+    // 按内容识别的 MIME 类型
+    pub file_type: String,
+    pub file_size: i64,
+    // 图片的宽高和缩略图 key，缩略图异步生成
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub thumb_key: Option<String>,
+    pub preview_key: Option<String>,
+    // 病毒扫描结果，infected 的文件移到隔离区，不再提供下载
+    #[schema(default_value = "pending", index_type = "hash")] // pending scanning clean infected
+    pub scan_status: String,
+    // 扫描出的病毒名称
+    pub scan_result: Option<String>,
+    // 加密存储时的站点密钥 id，为空时是明文
+    pub key_id: Option<String>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
     #[schema(read_only, default_value = "now", index_type = "btree")]
-    create_at: DateTime,
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
     #[schema(default_value = "now", index_type = "btree")]
-    update_at: DateTime,
-    version: u64,
+    pub update_at: DateTime,
+    pub version: u64,
 }
@@ -34,6 +34,8 @@ pub struct ChatMessage {
     pub user_id: Option<Uuid>,
     #[schema(default_value = "sended", index_type = "hash")]// sended readed recall delete
     pub status: String,
+    #[schema(default_value = "visitor", index_type = "hash")]// visitor agent bot system
+    pub sender_type: String,
     #[schema(
         snapshot,
         reference = "ChatMessage",
@@ -56,7 +58,9 @@ pub struct ChatMessage {
         index_type = "btree"
     )]
     pub room_id: Uuid,
+    // 旧格式的附件 JSON，由迁移任务转为 file_ids
     pub str_files: Option<String>,
+    // 按 file_ids 展开的附件
     #[schema(ignore)]
     pub files: Vec<ChatFiles>,
     #[serde(serialize_with = "serialize_datetime_with_timezone")]
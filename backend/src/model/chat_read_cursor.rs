@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatRoom;
+
+/// 房间已读位置，每个房间的客服端和访客端各一条
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+#[schema(unique_on = "room_id, side")]
+pub struct ChatReadCursor {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatRoom",
+        fetch_as = "room",
+        index_type = "btree"
+    )]
+    pub room_id: Uuid,
+    #[schema(default_value = "agent", index_type = "hash")] // agent visitor
+    pub side: String,
+    // 已读到的消息 id，消息 id 按时间递增
+    pub last_read_id: Uuid,
+    #[schema(reference = "User")]
+    pub user_id: Option<Uuid>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now")]
+    pub read_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use zino::prelude::*;
 use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
 use crate::utils::date_utils::serialize_datetime_with_timezone;
+use zino_model::User;
 use super::ChatWebsite;
 
 #[derive(
@@ -23,7 +24,7 @@ pub struct ChatRoom {
     pub id: Uuid,
     #[schema(not_null, unique, comment = "uniq key for users to join")]
     pub room_key: String,
-    #[schema(default_value = "active", index_type = "hash")] // active disconnect
+    #[schema(default_value = "active", index_type = "hash")] // active disconnect offline closed
     pub status: String,
     #[schema(
         snapshot,
@@ -33,6 +34,14 @@ pub struct ChatRoom {
     )]
     pub room_site_id: Uuid,
     pub client_info: Option<String>,
+    #[schema(default_value = "none", index_type = "hash")] // none queued serving
+    pub queue_status: String,
+    pub queued_at: Option<DateTime>,
+    #[schema(reference = "User", comment = "agent serving the room")]
+    pub assigned_user_id: Option<Uuid>,
+    // 访客评价 1-5
+    pub rating: Option<i32>,
+    pub rating_comment: Option<String>,
     #[serde(serialize_with = "serialize_datetime_with_timezone")]
     #[schema(read_only, default_value = "now", index_type = "btree")]
     pub create_at: DateTime,
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::{ChatRoom, ChatWebsite};
+
+/// 客服离线时访客的留言
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct ChatTicket {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        comment = "ticket site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatRoom",
+        fetch_as = "room",
+        comment = "room of the visitor",
+        index_type = "btree"
+    )]
+    pub room_id: Uuid,
+    #[schema(not_null, comment = "visitor name")]
+    pub name: String,
+    #[schema(not_null, comment = "visitor email")]
+    pub email: String,
+    #[schema(not_null)]
+    pub content: String,
+    #[schema(default_value = "open", index_type = "hash")] // open replied closed
+    pub status: String,
+    pub reply_content: Option<String>,
+    #[schema(reference = "User")]
+    pub reply_user_id: Option<Uuid>,
+    pub reply_at: Option<DateTime>,
+    // 回复已通过邮件发送
+    pub reply_mailed: bool,
+    // 回复已在访客再次打开窗口时展示
+    pub reply_delivered: bool,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
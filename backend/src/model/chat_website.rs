@@ -22,12 +22,25 @@ pub struct ChatWebsite {
     pub id: Uuid,
     #[schema(not_null, unique, comment = "uniq string for frontend")]
     pub site_key: String,
-    #[schema(default_value = "inited", index_type = "hash")] // inited confirmed
+    #[schema(default_value = "inited", index_type = "hash")] // inited confirmed archived
     pub status: String,
+    // 归档前的状态，取消归档时恢复
+    pub archived_status: Option<String>,
     pub domain: Option<String>,
     pub title: Option<String>,
     pub welcome_slogan: Option<String>,
     pub position: Option<String>,
+    // 机器人: none rules webhook
+    pub bot_type: Option<String>,
+    pub bot_webhook_url: Option<String>,
+    // 上传限制，为空时使用全局配置
+    pub upload_max_bytes: Option<i64>,
+    pub upload_mime_types: Option<String>,
+    pub room_quota_bytes: Option<i64>,
+    pub site_quota_bytes: Option<i64>,
+    // 附件加密存储，密钥由 attachment_key_id 派生，开启后生成且不再改变
+    pub encrypt_attachments: Option<bool>,
+    pub attachment_key_id: Option<String>,
     #[schema(
         snapshot,
         reference = "User",
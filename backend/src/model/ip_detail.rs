@@ -23,6 +23,8 @@ pub struct IpDetail {
     pub country: Option<String>,
     pub loc: Option<String>,
     pub org: Option<String>,
+    // 数据来源: mmdb ipinfo，内网和保留地址为 reserved
+    pub source: Option<String>,
     #[schema(read_only, default_value = "now", index_type = "btree")]
     pub create_at: DateTime,
     #[schema(default_value = "now", index_type = "btree")]
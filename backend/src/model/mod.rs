@@ -1,13 +1,35 @@
+mod audit_event;
+mod auto_reply_rule;
+mod chat_daily_stat;
 mod chat_media;
 mod chat_message;
+mod chat_read_cursor;
 mod chat_room;
+mod chat_ticket;
 mod chat_website;
+mod site_api_key;
+mod site_invitation;
+mod site_member;
+mod storage_usage;
 mod tag;
+mod webhook_delivery;
+mod webhook_subscription;
 
+pub(crate) use audit_event::AuditEvent;
+pub(crate) use auto_reply_rule::AutoReplyRule;
+pub(crate) use chat_daily_stat::ChatDailyStat;
 pub(crate) use chat_media::ChatMedia;
 pub(crate) use chat_message::ChatMessage;
+pub(crate) use chat_read_cursor::ChatReadCursor;
 pub(crate) use chat_room::ChatRoom;
+pub(crate) use chat_ticket::ChatTicket;
 pub(crate) use chat_website::ChatWebsite;
+pub(crate) use site_api_key::SiteApiKey;
+pub(crate) use site_invitation::SiteInvitation;
+pub(crate) use site_member::SiteMember;
+pub(crate) use storage_usage::StorageUsage;
 pub(crate) use tag::Tag;
+pub(crate) use webhook_delivery::WebhookDelivery;
+pub(crate) use webhook_subscription::WebhookSubscription;
 pub mod chat_files;
 pub mod ip_detail;
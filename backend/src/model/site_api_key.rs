@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 站点 API 密钥，密钥本身由 `SecretAccessKey::new(access_key_id)` 推导，不落库
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+#[schema(unique_on="access_key_id")]
+pub struct SiteApiKey {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        comment = "key site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(not_null, comment = "key name")]
+    pub name: String,
+    #[schema(not_null, unique)]
+    pub access_key_id: String,
+    // 通过该密钥发送消息时默认的机器人名称
+    pub bot_name: String,
+    #[schema(default_value = "active", index_type = "hash")] // active revoked
+    pub status: String,
+    pub last_used_at: Option<DateTime>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
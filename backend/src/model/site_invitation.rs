@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 站点成员邀请，令牌只保存摘要，一次有效
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct SiteInvitation {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(not_null, index_type = "btree")]
+    pub email: String,
+    #[schema(default_value = "agent")] // admin agent viewer
+    pub role: String,
+    #[schema(unique, not_null)]
+    pub token_hash: String,
+    #[schema(reference = "User", comment = "inviter")]
+    pub invited_by: Uuid,
+    #[schema(default_value = "pending", index_type = "hash")] // pending accepted revoked failed
+    pub status: String,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    pub expires_at: DateTime,
+    pub accepted_at: Option<DateTime>,
+    #[schema(reference = "User")]
+    pub accepted_user_id: Option<Uuid>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use zino_model::User;
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 站点成员及角色
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct SiteMember {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "User",
+        fetch_as = "user",
+        index_type = "btree"
+    )]
+    pub user_id: Uuid,
+    #[schema(default_value = "agent", index_type = "hash")] // owner admin agent viewer
+    pub role: String,
+    #[schema(default_value = "active", index_type = "hash")] // active removed
+    pub status: String,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::WebhookSubscription;
+
+/// 事件推送记录，同时作为后台推送任务队列
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct WebhookDelivery {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "WebhookSubscription",
+        fetch_as = "subscription",
+        index_type = "btree"
+    )]
+    pub subscription_id: Uuid,
+    #[schema(index_type = "btree")]
+    pub site_id: Uuid,
+    #[schema(not_null, index_type = "hash")]
+    pub event: String,
+    // 推送的 JSON 内容，重放时原样发送
+    pub payload: String,
+    #[schema(default_value = "pending", index_type = "hash")] // pending delivering delivered failed
+    pub status: String,
+    pub attempts: u32,
+    #[schema(index_type = "btree")]
+    pub next_attempt_at: DateTime,
+    // 推送中的记录被某个实例认领，超过该时间仍未完成时可被重新认领
+    pub locked_until: Option<DateTime>,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime>,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use zino::prelude::*;
+use zino_derive::{DecodeRow, Model, ModelAccessor, ModelHooks, Schema};
+use crate::utils::date_utils::serialize_datetime_with_timezone;
+use super::ChatWebsite;
+
+/// 站点事件订阅，事件发生时推送到 `url`
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    DecodeRow,
+    Schema,
+    ModelAccessor,
+    ModelHooks,
+    Model,
+)]
+#[serde(default)]
+pub struct WebhookSubscription {
+    #[schema(primary_key, read_only, constructor = "Uuid::now_v7")]
+    pub id: Uuid,
+    #[schema(
+        snapshot,
+        reference = "ChatWebsite",
+        fetch_as = "site",
+        comment = "subscription site",
+        index_type = "btree"
+    )]
+    pub site_id: Uuid,
+    #[schema(not_null)]
+    pub url: String,
+    // HMAC-SHA256 签名密钥，接口返回时需脱敏
+    #[schema(not_null)]
+    pub secret: String,
+    // room.created visitor.connected visitor.disconnected message.created room.closed rating.submitted
+    pub events: Vec<String>,
+    #[schema(default_value = "active", index_type = "hash")] // active inactive
+    pub status: String,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(read_only, default_value = "now", index_type = "btree")]
+    pub create_at: DateTime,
+    #[serde(serialize_with = "serialize_datetime_with_timezone")]
+    #[schema(default_value = "now", index_type = "btree")]
+    pub update_at: DateTime,
+    pub version: u64,
+}
@@ -1,7 +1,8 @@
 use std::sync::{atomic::AtomicUsize, Arc};
 
 use crate::{
-    controller::{auth, chat_ctl, file, file_ctl, ip_ctl, stats, user},
+    app_config::SETTINGS,
+    controller::{api_ctl, audit_ctl, auth, auto_reply_ctl, chat_ctl, file, file_ctl, ip_ctl, member_ctl, stats, ticket_ctl, user, webhook_ctl},
     middleware,
     model::Tag,
     wsserver::{
@@ -16,7 +17,7 @@ use zino_model::User;
 
 lazy_static! {
     static ref APP_STATE: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
-    static ref SERVER: Addr<ChatServer> = server::ChatServer::new().start();
+    pub(crate) static ref SERVER: Addr<ChatServer> = server::ChatServer::new().start();
     static ref CURRENT_VISITORS: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
 }
 
@@ -28,6 +29,7 @@ pub fn main_routes() -> Vec<RouterConfigure> {
         chat_router as RouterConfigure,
         chat_outer_router as RouterConfigure,
         public_router as RouterConfigure,
+        api_router as RouterConfigure,
     ]
 }
 
@@ -43,6 +45,8 @@ pub fn debug_routes() -> Vec<RouterConfigure> {
 
 fn auth_router(cfg: &mut ServiceConfig) {
     cfg.route("/auth/login", post().to(auth::login));
+    cfg.route("/auth/invitation", get().to(member_ctl::check_invitation));
+    cfg.route("/auth/accept-invitation", post().to(member_ctl::accept_invitation));
     cfg.service(
         scope("/auth")
             .route("/refresh", get().to(auth::refresh))
@@ -69,9 +73,10 @@ fn ws_router(cfg: &mut ServiceConfig) {
 fn file_router(cfg: &mut ServiceConfig) {
     cfg.service(
         scope("/file")
-            .route("/upload", post().to(file::upload))
+            .route("/upload", post().to(file::upload).wrap(upload_limit()))
             .route("/decrypt", get().to(file::decrypt))
-            .route("/upload/chat", post().to(file_ctl::upload))
+            .route("/upload/chat", post().to(file_ctl::upload).wrap(upload_limit()))
+            .route("/upload/chat", delete().to(file_ctl::delete_file))
             .route("/url", post().to(file_ctl::get_file_url))
             .wrap(middleware::UserSessionInitializer),
     );
@@ -80,12 +85,19 @@ fn file_router(cfg: &mut ServiceConfig) {
 fn public_router(cfg: &mut ServiceConfig) {
     cfg.service(
         scope("/pub/file")
-            .route("/upload", post().to(file_ctl::upload))
+            .route("/upload", post().to(file_ctl::upload).wrap(upload_limit()))
             .route("/upload", delete().to(file_ctl::delete_file))
-            .route("/url", post().to(file_ctl::get_file_url)),
+            .route("/url", post().to(file_ctl::get_file_url))
+            .route("/download", get().to(file_ctl::download))
+            .route("/decrypt", get().to(file_ctl::decrypt_file)),
     );
 }
 
+// 上传接口在解析表单前限制请求体大小
+fn upload_limit() -> middleware::BodyLimit {
+    middleware::BodyLimit::new(SETTINGS.upload_request_max_bytes)
+}
+
 fn user_router(cfg: &mut ServiceConfig) {
     cfg.route("/user/new", post().to(user::new))
         .route("/user/{id}/delete", post().to(User::soft_delete))
@@ -126,9 +138,40 @@ fn chat_router(cfg: &mut ServiceConfig) {
         scope("/service")
             .route("/config-site", post().to(chat_ctl::admin_config_website))
             .route("/save-site", post().to(chat_ctl::save_site_config))
+            .route("/create-site", post().to(chat_ctl::create_site))
+            .route("/list-sites", get().to(chat_ctl::list_sites))
+            .route("/archive-site", post().to(chat_ctl::archive_site))
             .route("/list-rooms", get().to(chat_ctl::list_rooms))
             .route("/list-chatmessage", post().to(chat_ctl::list_chatmessage))
+            .route("/list-queue", post().to(chat_ctl::list_queue))
+            .route("/close-room", post().to(chat_ctl::close_room))
+            .route("/transfer-room", post().to(chat_ctl::transfer_room))
+            .route("/delete-message", post().to(chat_ctl::delete_message))
             .route("/ip-info", post().to(ip_ctl::ip_detail))
+            .route("/list-tickets", post().to(ticket_ctl::list_tickets))
+            .route("/reply-ticket", post().to(ticket_ctl::reply_ticket))
+            .route("/close-ticket", post().to(ticket_ctl::close_ticket))
+            .route("/list-auto-replies", post().to(auto_reply_ctl::list_rules))
+            .route("/save-auto-reply", post().to(auto_reply_ctl::save_rule))
+            .route("/delete-auto-reply", post().to(auto_reply_ctl::delete_rule))
+            .route("/test-auto-reply", post().to(auto_reply_ctl::dry_run))
+            .route("/list-webhooks", post().to(webhook_ctl::list_webhooks))
+            .route("/save-webhook", post().to(webhook_ctl::save_webhook))
+            .route("/delete-webhook", post().to(webhook_ctl::delete_webhook))
+            .route("/list-webhook-deliveries", post().to(webhook_ctl::list_deliveries))
+            .route("/replay-webhook-delivery", post().to(webhook_ctl::replay_delivery))
+            .route("/list-api-keys", post().to(api_ctl::list_api_keys))
+            .route("/create-api-key", post().to(api_ctl::create_api_key))
+            .route("/revoke-api-key", post().to(api_ctl::revoke_api_key))
+            .route("/list-members", post().to(member_ctl::list_members))
+            .route("/save-member", post().to(member_ctl::save_member))
+            .route("/remove-member", post().to(member_ctl::remove_member))
+            .route("/invite-member", post().to(member_ctl::invite_member))
+            .route("/list-invitations", post().to(member_ctl::list_invitations))
+            .route("/revoke-invitation", post().to(member_ctl::revoke_invitation))
+            .route("/list-audit-events", post().to(audit_ctl::list_audit_events))
+            .route("/chat-stats", post().to(stats::chat_stats))
+            .route("/agent-stats", post().to(stats::agent_stats))
             .wrap(middleware::UserSessionInitializer),
     );
     
@@ -140,9 +183,24 @@ fn chat_outer_router(cfg: &mut ServiceConfig) {
             .route("/load.js", get().to(chat_ctl::load_site_js))
             .route("/direct", get().to(chat_ctl::direct_chat))
             .route("/messages", post().to(chat_ctl::list_chatmessage_from_chat))
-            .route("/upload", post().to(file_ctl::upload))
+            .route("/upload", post().to(file_ctl::upload).wrap(upload_limit()))
             .route("/upload", delete().to(file_ctl::delete_file))
+            .route("/file/{media_id}", get().to(file_ctl::media_file))
+            .route("/file-url", post().to(file_ctl::get_file_url))
             .route("/site", post().to(chat_ctl::load_site))
+            .route("/leave-message", post().to(ticket_ctl::leave_message))
+            .route("/ticket-replies", post().to(ticket_ctl::ticket_replies))
+            .route("/rate", post().to(chat_ctl::rate_room))
 
     );
 }
+
+// 外部系统接口，使用站点 API 密钥认证
+fn api_router(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/api/v1")
+            .route("/rooms", get().to(api_ctl::list_rooms))
+            .route("/rooms/{room_id}/messages", get().to(api_ctl::list_messages))
+            .route("/rooms/{room_id}/messages", post().to(api_ctl::post_message)),
+    );
+}
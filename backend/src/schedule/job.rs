@@ -1,9 +1,19 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, future::Future};
 
 use zino::prelude::*;
 use zino_model::User;
 
-use crate::{model::ChatWebsite, service::room_message_state::MessageStatusManager};
+use crate::{
+    middleware::redis::REDIS_MANAGER,
+    model::ChatWebsite,
+    service::{
+        analytics_service::AnalyticsService, chat_service::ChatService, media_service::MediaService,
+        room_message_state::MessageStatusManager, webhook_service::WebhookService,
+    },
+};
+
+// 迁移锁的过期时间，实例在迁移中退出时锁自动释放
+const MIGRATION_LOCK_SECS: u64 = 3600;
 
 pub fn every_15s(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) {
     let counter = job_data
@@ -59,3 +69,113 @@ pub fn every_hour(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxF
         }
     })
 }
+
+pub fn deliver_webhooks(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match WebhookService::deliver_pending().await {
+            Ok(count) => {
+                if count > 0 {
+                    tracing::info!("webhook deliveries processed: {}", count);
+                }
+            }
+            Err(e) => tracing::error!("deliver webhooks error: {}", e),
+        }
+    })
+}
+
+pub fn rollup_chat_stats(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match AnalyticsService::rollup_recent().await {
+            Ok(count) => tracing::info!("chat stats rolled up: {}", count),
+            Err(e) => tracing::error!("rollup chat stats error: {}", e),
+        }
+    })
+}
+
+pub fn reconcile_unread_counts(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match ChatService::reconcile_unread_counts().await {
+            Ok(count) => {
+                if count > 0 {
+                    tracing::warn!("unread counts repaired: {}", count);
+                }
+            }
+            Err(e) => tracing::error!("reconcile unread counts error: {}", e),
+        }
+    })
+}
+
+pub fn migrate_redis_keys(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        let migrated = run_exclusive("redis_keys", MessageStatusManager::migrate_legacy_keys()).await;
+        match migrated {
+            Some(Ok(count)) => tracing::info!("legacy redis keys migrated: {}", count),
+            Some(Err(e)) => tracing::error!("migrate redis keys error: {}", e),
+            None => {}
+        }
+    })
+}
+
+// 启动时的迁移任务每个实例都会执行，用 Redis 锁保证同一时间只有一个实例在迁移
+// 没拿到锁时返回 None
+async fn run_exclusive<T>(name: &str, task: impl Future<Output = T>) -> Option<T> {
+    let lock_key = format!("migration:{}:lock", name);
+    let owner = Uuid::now_v7().to_string();
+    match REDIS_MANAGER.set_nx_ex(&lock_key, &owner, MIGRATION_LOCK_SECS).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::info!("migration {} is running on another instance", name);
+            return None;
+        }
+        Err(e) => {
+            tracing::error!("lock migration {} error: {}", name, e);
+            return None;
+        }
+    }
+    let result = task.await;
+    // 锁过期后可能已被其他实例拿到，只释放自己的锁
+    if let Ok(Some(current)) = REDIS_MANAGER.get::<String>(&lock_key).await {
+        if current == owner {
+            let _ = REDIS_MANAGER.del(&lock_key).await;
+        }
+    }
+    Some(result)
+}
+
+pub fn backfill_sender_type(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match run_exclusive("sender_type", ChatService::backfill_sender_type()).await {
+            Some(Ok(count)) => tracing::info!("message sender_type backfilled: {}", count),
+            Some(Err(e)) => tracing::error!("backfill sender_type error: {}", e),
+            None => {}
+        }
+    })
+}
+
+pub fn migrate_str_files(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match run_exclusive("str_files", MediaService::migrate_str_files()).await {
+            Some(Ok(count)) => tracing::info!("message str_files migrated: {}", count),
+            Some(Err(e)) => tracing::error!("migrate str_files error: {}", e),
+            None => {}
+        }
+    })
+}
+
+pub fn scan_pending_uploads(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match MediaService::scan_pending().await {
+            Ok(count) => tracing::info!("pending uploads scanned: {}", count),
+            Err(e) => tracing::error!("scan pending uploads error: {}", e),
+        }
+    })
+}
+
+pub fn gc_orphan_uploads(job_id: Uuid, job_data: &mut Map, last_tick: DateTime) -> BoxFuture {
+    Box::pin(async {
+        match MediaService::gc_orphans().await {
+            Ok(count) => tracing::info!("orphan uploads removed: {}", count),
+            Err(e) => tracing::error!("gc orphan uploads error: {}", e),
+        }
+    })
+}
@@ -22,5 +22,38 @@ pub fn async_job_scheduler() -> AsyncJobScheduler {
 
     let job = AsyncJob::new("0/10 * * * * *", job::every_10s as AsyncCronJob);
     scheduler.add(job);
+
+    let job = AsyncJob::new("0/5 * * * * *", job::deliver_webhooks as AsyncCronJob);
+    scheduler.add(job);
+
+    let job = AsyncJob::new("0 5 * * * *", job::rollup_chat_stats as AsyncCronJob);
+    scheduler.add(job);
+
+    let job = AsyncJob::new("0 */10 * * * *", job::reconcile_unread_counts as AsyncCronJob);
+    scheduler.add(job);
+
+    let job = AsyncJob::new("0 30 * * * *", job::gc_orphan_uploads as AsyncCronJob);
+    scheduler.add(job);
+
+    let job = AsyncJob::new("0 */5 * * * *", job::scan_pending_uploads as AsyncCronJob);
+    scheduler.add(job);
+
+    // 启动时迁移一次旧格式的 Redis 键
+    let job = AsyncJob::new("0 0 0 * * *", job::migrate_redis_keys as AsyncCronJob)
+        .immediate(true)
+        .max_ticks(1);
+    scheduler.add(job);
+
+    // 启动时把有 user_id 的旧消息标记为客服发送
+    let job = AsyncJob::new("0 0 0 * * *", job::backfill_sender_type as AsyncCronJob)
+        .immediate(true)
+        .max_ticks(1);
+    scheduler.add(job);
+
+    // 启动时把旧消息的 str_files 转为附件记录
+    let job = AsyncJob::new("0 0 0 * * *", job::migrate_str_files as AsyncCronJob)
+        .immediate(true)
+        .max_ticks(1);
+    scheduler.add(job);
     scheduler
 }
@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+use zino_core::{
+    error::Error, extension::JsonObjectExt, json, model::Query, orm::Schema, warn, Map, Uuid,
+};
+
+use crate::{
+    model::{ChatDailyStat, ChatMessage, ChatRoom},
+    utils::date_utils::{date_ymd_days_ago, day_range_s},
+};
+
+pub struct AnalyticsService;
+
+// 当天未删除的消息，参数 start、end 为时间戳（秒）
+const DAY_MESSAGES: &str = "m.create_at >= to_timestamp(#{start}) \
+    AND m.create_at < to_timestamp(#{end}) AND m.status <> 'delete'";
+
+#[derive(Deserialize)]
+struct MessageCount {
+    site_id: Uuid,
+    assigned_user_id: Option<Uuid>,
+    sender_type: String,
+    user_id: Option<Uuid>,
+    total: u64,
+}
+
+#[derive(Deserialize)]
+struct ResponseSum {
+    site_id: Uuid,
+    user_id: Option<Uuid>,
+    is_first: bool,
+    total: u64,
+    secs: u64,
+}
+
+#[derive(Deserialize)]
+struct ConversationSum {
+    site_id: Uuid,
+    assigned_user_id: Option<Uuid>,
+    total: u64,
+    secs: u64,
+    missed: u64,
+}
+
+/**
+ * 1.按天汇总 ChatMessage、ChatRoom 时间数据，写入 ChatDailyStat（站点和客服两个维度）
+ *   - 会话数：当天第一次收到访客消息的房间
+ *   - 进出消息数：访客消息为进，客服和机器人消息为出
+ *   - 首次响应：访客第一条消息到客服第一条回复
+ *   - 平均响应：每轮访客消息到客服回复
+ *   - 会话时长：访客第一条消息到当天最后一条消息
+ *   - 漏接：有访客消息但客服没有回复
+ * 2.定时任务重新汇总昨天和今天的数据
+ * 3.按日期范围查询统计
+ */
+
+impl AnalyticsService {
+    // 1 按房间分组的统计都在数据库里完成，只取回按站点、客服汇总后的行
+    pub async fn rollup_day(day: &str) -> Result<usize, Error> {
+        let (start, end) = day_range_s(day).map_err(|e| warn!("invalid day {}: {}", day, e))?;
+        let mut params = Map::new();
+        params.upsert("start", start);
+        params.upsert("end", end);
+        params.upsert("day", day);
+
+        let mut stats: HashMap<(Uuid, Uuid), ChatDailyStat> = HashMap::new();
+        Self::rollup_messages(&params, day, &mut stats).await?;
+        Self::rollup_responses(&params, day, &mut stats).await?;
+        Self::rollup_conversations(&params, day, &mut stats).await?;
+
+        let count = stats.len();
+        Self::replace_day(&params, stats.into_values().collect()).await?;
+        Ok(count)
+    }
+
+    // 1.1 进出消息数：访客消息为进，客服和机器人消息为出
+    async fn rollup_messages(
+        params: &Map,
+        day: &str,
+        stats: &mut HashMap<(Uuid, Uuid), ChatDailyStat>,
+    ) -> Result<(), Error> {
+        let sql = format!(
+            "SELECT r.room_site_id AS site_id, r.assigned_user_id, m.sender_type, m.user_id, \
+                COUNT(*)::bigint AS total \
+            FROM {message} m JOIN {room} r ON r.id = m.room_id \
+            WHERE {range} \
+            GROUP BY r.room_site_id, r.assigned_user_id, m.sender_type, m.user_id",
+            message = ChatMessage::table_name(),
+            room = ChatRoom::table_name(),
+            range = DAY_MESSAGES,
+        );
+        let rows = ChatMessage::query_as::<MessageCount>(&sql, Some(params)).await?;
+        for row in rows {
+            if row.sender_type == "visitor" {
+                for agent_id in with_agent(row.assigned_user_id) {
+                    stat_entry(stats, row.site_id, agent_id, day).messages_in += row.total;
+                }
+                continue;
+            }
+            stat_entry(stats, row.site_id, Uuid::nil(), day).messages_out += row.total;
+            if let (Some(user_id), "agent") = (row.user_id, row.sender_type.as_str()) {
+                stat_entry(stats, row.site_id, user_id, day).messages_out += row.total;
+            }
+        }
+        Ok(())
+    }
+
+    // 1.2 响应时间：连续的访客消息为一轮，从这一轮第一条访客消息到之后第一条客服消息
+    //     每个房间当天第一轮的回复同时计入首次响应
+    async fn rollup_responses(
+        params: &Map,
+        day: &str,
+        stats: &mut HashMap<(Uuid, Uuid), ChatDailyStat>,
+    ) -> Result<(), Error> {
+        let sql = format!(
+            "WITH ordered AS ( \
+                SELECT m.room_id, m.sender_type, m.user_id, m.create_at, \
+                    LAG(m.sender_type) OVER (PARTITION BY m.room_id ORDER BY m.create_at) AS prev_type \
+                FROM {message} m \
+                WHERE {range} AND m.sender_type IN ('visitor', 'agent') \
+            ), turns AS ( \
+                SELECT room_id, sender_type, user_id, create_at, \
+                    SUM(CASE WHEN sender_type = 'visitor' AND prev_type IS DISTINCT FROM 'visitor' \
+                        THEN 1 ELSE 0 END) OVER (PARTITION BY room_id ORDER BY create_at) AS turn \
+                FROM ordered \
+            ), waits AS ( \
+                SELECT room_id, turn, MIN(create_at) AS since \
+                FROM turns WHERE sender_type = 'visitor' GROUP BY room_id, turn \
+            ), replies AS ( \
+                SELECT DISTINCT ON (room_id, turn) room_id, turn, user_id, create_at \
+                FROM turns WHERE sender_type = 'agent' AND turn > 0 \
+                ORDER BY room_id, turn, create_at \
+            ), answered AS ( \
+                SELECT p.room_id, p.user_id, \
+                    p.turn = MIN(p.turn) OVER (PARTITION BY p.room_id) AS is_first, \
+                    GREATEST(FLOOR(EXTRACT(EPOCH FROM p.create_at)) \
+                        - FLOOR(EXTRACT(EPOCH FROM w.since)), 0) AS secs \
+                FROM replies p JOIN waits w ON w.room_id = p.room_id AND w.turn = p.turn \
+            ) \
+            SELECT r.room_site_id AS site_id, a.user_id, a.is_first, \
+                COUNT(*)::bigint AS total, SUM(a.secs)::bigint AS secs \
+            FROM answered a JOIN {room} r ON r.id = a.room_id \
+            GROUP BY r.room_site_id, a.user_id, a.is_first",
+            message = ChatMessage::table_name(),
+            room = ChatRoom::table_name(),
+            range = DAY_MESSAGES,
+        );
+        let rows = ChatMessage::query_as::<ResponseSum>(&sql, Some(params)).await?;
+        for row in rows {
+            for agent_id in with_agent(row.user_id) {
+                let stat = stat_entry(stats, row.site_id, agent_id, day);
+                stat.response_count += row.total;
+                stat.response_secs += row.secs;
+                if row.is_first {
+                    stat.first_response_count += row.total;
+                    stat.first_response_secs += row.secs;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 1.3 会话数、会话时长、漏接：当天第一次收到访客消息的房间，之前已有访客消息的不算新会话
+    async fn rollup_conversations(
+        params: &Map,
+        day: &str,
+        stats: &mut HashMap<(Uuid, Uuid), ChatDailyStat>,
+    ) -> Result<(), Error> {
+        let sql = format!(
+            "WITH rooms AS ( \
+                SELECT m.room_id, \
+                    MIN(m.create_at) FILTER (WHERE m.sender_type = 'visitor') AS first_at, \
+                    MAX(m.create_at) FILTER (WHERE m.sender_type = 'agent') AS replied_at, \
+                    MAX(m.create_at) AS last_at \
+                FROM {message} m \
+                WHERE {range} \
+                GROUP BY m.room_id \
+            ) \
+            SELECT r.room_site_id AS site_id, r.assigned_user_id, COUNT(*)::bigint AS total, \
+                SUM(GREATEST(FLOOR(EXTRACT(EPOCH FROM d.last_at)) \
+                    - FLOOR(EXTRACT(EPOCH FROM d.first_at)), 0))::bigint AS secs, \
+                COUNT(*) FILTER (WHERE d.replied_at IS NULL OR d.replied_at < d.first_at)::bigint \
+                    AS missed \
+            FROM rooms d JOIN {room} r ON r.id = d.room_id \
+            WHERE d.first_at IS NOT NULL AND NOT EXISTS ( \
+                SELECT 1 FROM {message} b \
+                WHERE b.room_id = d.room_id AND b.sender_type = 'visitor' \
+                    AND b.status <> 'delete' AND b.create_at < to_timestamp(#{{start}}) \
+            ) \
+            GROUP BY r.room_site_id, r.assigned_user_id",
+            message = ChatMessage::table_name(),
+            room = ChatRoom::table_name(),
+            range = DAY_MESSAGES,
+        );
+        let rows = ChatMessage::query_as::<ConversationSum>(&sql, Some(params)).await?;
+        for row in rows {
+            for agent_id in with_agent(row.assigned_user_id) {
+                let stat = stat_entry(stats, row.site_id, agent_id, day);
+                stat.conversations += row.total;
+                stat.duration_count += row.total;
+                stat.duration_secs += row.secs;
+                stat.missed += row.missed;
+            }
+        }
+        Ok(())
+    }
+
+    // 1.4 一条语句内 upsert 当天的统计并删除不再出现的行，重复或并发执行结果一致
+    async fn replace_day(params: &Map, stats: Vec<ChatDailyStat>) -> Result<(), Error> {
+        let table = ChatDailyStat::table_name();
+        if stats.is_empty() {
+            let sql = format!("DELETE FROM {table} WHERE day = #{{day}}");
+            ChatDailyStat::execute(&sql, Some(params)).await?;
+            return Ok(());
+        }
+        let values = stats
+            .iter()
+            .map(|s| {
+                format!(
+                    "('{}', '{}', '{}', #{{day}}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, now())",
+                    s.id,
+                    s.site_id,
+                    s.agent_id,
+                    s.conversations,
+                    s.messages_in,
+                    s.messages_out,
+                    s.missed,
+                    s.first_response_count,
+                    s.first_response_secs,
+                    s.response_count,
+                    s.response_secs,
+                    s.duration_count,
+                    s.duration_secs,
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let sql = format!(
+            "WITH saved AS ( \
+                INSERT INTO {table} (id, site_id, agent_id, day, conversations, messages_in, \
+                    messages_out, missed, first_response_count, first_response_secs, \
+                    response_count, response_secs, duration_count, duration_secs, create_at) \
+                VALUES {values} \
+                ON CONFLICT (site_id, agent_id, day) DO UPDATE SET \
+                    conversations = EXCLUDED.conversations, \
+                    messages_in = EXCLUDED.messages_in, \
+                    messages_out = EXCLUDED.messages_out, \
+                    missed = EXCLUDED.missed, \
+                    first_response_count = EXCLUDED.first_response_count, \
+                    first_response_secs = EXCLUDED.first_response_secs, \
+                    response_count = EXCLUDED.response_count, \
+                    response_secs = EXCLUDED.response_secs, \
+                    duration_count = EXCLUDED.duration_count, \
+                    duration_secs = EXCLUDED.duration_secs \
+                RETURNING id \
+            ) \
+            DELETE FROM {table} WHERE day = #{{day}} AND id NOT IN (SELECT id FROM saved)"
+        );
+        ChatDailyStat::execute(&sql, Some(params)).await?;
+        Ok(())
+    }
+
+    // 2
+    pub async fn rollup_recent() -> Result<usize, Error> {
+        let mut count = 0;
+        for days in [1, 0] {
+            count += Self::rollup_day(&date_ymd_days_ago(days)).await?;
+        }
+        Ok(count)
+    }
+
+    // 3 站点每日数据和汇总，指定 agent_id 时为该客服的数据
+    pub async fn site_stats(
+        site_id: &Uuid,
+        agent_id: Option<String>,
+        from: &str,
+        to: &str,
+    ) -> Result<Map, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.add_filter("day", json!({"$ge": from, "$le": to}));
+        match agent_id {
+            Some(agent_id) => query.add_filter("agent_id", agent_id),
+            None => query.add_filter("agent_id", Uuid::nil().to_string()),
+        }
+        query.order_asc("day");
+        let rows = ChatDailyStat::find::<ChatDailyStat>(&query).await?;
+        let days = rows.iter().map(summary).collect::<Vec<Map>>();
+        let mut res = Map::new();
+        res.upsert("total", summary(&sum(&rows)));
+        res.upsert("days", days);
+        Ok(res)
+    }
+
+    // 3.1 日期范围内每个客服的汇总
+    pub async fn agent_stats(site_id: &Uuid, from: &str, to: &str) -> Result<Vec<Map>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.add_filter("day", json!({"$ge": from, "$le": to}));
+        query.add_filter("agent_id", json!({"$ne": Uuid::nil().to_string()}));
+        let rows = ChatDailyStat::find::<ChatDailyStat>(&query).await?;
+        let mut by_agent: BTreeMap<Uuid, Vec<ChatDailyStat>> = BTreeMap::new();
+        for row in rows {
+            by_agent.entry(row.agent_id).or_default().push(row);
+        }
+        Ok(by_agent.values().map(|rows| summary(&sum(rows))).collect())
+    }
+}
+
+fn stat_entry<'a>(
+    stats: &'a mut HashMap<(Uuid, Uuid), ChatDailyStat>,
+    site_id: Uuid,
+    agent_id: Uuid,
+    day: &str,
+) -> &'a mut ChatDailyStat {
+    stats.entry((site_id, agent_id)).or_insert_with(|| {
+        let mut stat = ChatDailyStat::default();
+        stat.id = Uuid::now_v7();
+        stat.site_id = site_id;
+        stat.agent_id = agent_id;
+        stat.day = day.to_owned();
+        stat
+    })
+}
+
+// 站点维度（空 uuid）总是统计，客服维度在有客服时统计
+fn with_agent(agent_id: Option<Uuid>) -> Vec<Uuid> {
+    match agent_id {
+        Some(agent_id) => vec![Uuid::nil(), agent_id],
+        None => vec![Uuid::nil()],
+    }
+}
+
+fn sum(rows: &[ChatDailyStat]) -> ChatDailyStat {
+    let mut total = ChatDailyStat::default();
+    for row in rows {
+        total.site_id = row.site_id;
+        total.agent_id = row.agent_id;
+        total.conversations += row.conversations;
+        total.messages_in += row.messages_in;
+        total.messages_out += row.messages_out;
+        total.missed += row.missed;
+        total.first_response_count += row.first_response_count;
+        total.first_response_secs += row.first_response_secs;
+        total.response_count += row.response_count;
+        total.response_secs += row.response_secs;
+        total.duration_count += row.duration_count;
+        total.duration_secs += row.duration_secs;
+    }
+    total
+}
+
+fn average(secs: u64, count: u64) -> Option<f64> {
+    if count > 0 {
+        Some(secs as f64 / count as f64)
+    } else {
+        None
+    }
+}
+
+fn summary(stat: &ChatDailyStat) -> Map {
+    let mut map = Map::new();
+    map.upsert("site_id", stat.site_id.to_string());
+    map.upsert(
+        "agent_id",
+        (!stat.agent_id.is_nil()).then(|| stat.agent_id.to_string()),
+    );
+    map.upsert("day", stat.day.clone());
+    map.upsert("conversations", stat.conversations);
+    map.upsert("messages_in", stat.messages_in);
+    map.upsert("messages_out", stat.messages_out);
+    map.upsert("missed", stat.missed);
+    map.upsert(
+        "avg_first_response_secs",
+        average(stat.first_response_secs, stat.first_response_count),
+    );
+    map.upsert("avg_response_secs", average(stat.response_secs, stat.response_count));
+    map.upsert("avg_duration_secs", average(stat.duration_secs, stat.duration_count));
+    map
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use zino::prelude::{AccessKeyId, SecretAccessKey};
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, model::{Mutation, Query},
+    orm::Schema, warn, Map, Uuid,
+};
+
+use crate::model::SiteApiKey;
+
+// last_used_at 最多每分钟更新一次
+const LAST_USED_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct ApiKeyService;
+
+/**
+ * 1.客服为站点创建 API 密钥，密钥只在创建时返回一次
+ * 2.外部系统使用 `Authorization: Bearer {access_key_id}:{secret_access_key}` 调用接口
+ * 3.吊销密钥
+ */
+
+impl ApiKeyService {
+    // 1
+    pub async fn create_key(
+        site_id: &Uuid,
+        name: &str,
+        bot_name: &str,
+    ) -> Result<(SiteApiKey, String), Error> {
+        let access_key_id = AccessKeyId::new();
+        let secret = Self::secret_for(&access_key_id);
+        let mut key = SiteApiKey::default();
+        key.id = Uuid::now_v7();
+        key.site_id = *site_id;
+        key.name = name.to_owned();
+        key.bot_name = bot_name.to_owned();
+        key.access_key_id = access_key_id.to_string();
+        key.status = "active".to_owned();
+        let result = key.clone();
+        key.insert().await?;
+        Ok((result, secret))
+    }
+
+    pub async fn list_keys(site_id: &Uuid) -> Result<Vec<SiteApiKey>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.order_by("create_at", true);
+        Ok(SiteApiKey::find::<SiteApiKey>(&query).await?)
+    }
+
+    // 3
+    pub async fn revoke_key(site_id: &Uuid, key_id: &str) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", key_id);
+        query.add_filter("site_id", site_id.to_string());
+        let Some(mut key) = SiteApiKey::find_one::<SiteApiKey>(&query).await? else {
+            return Err(warn!("api key not found"));
+        };
+        key.status = "revoked".to_owned();
+        key.update_at = DateTime::now();
+        key.update().await?;
+        Ok(())
+    }
+
+    // 2
+    pub async fn authenticate(authorization: &str) -> Result<SiteApiKey, Error> {
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .unwrap_or(authorization)
+            .trim();
+        let Some((access_key_id, secret)) = token.split_once(':') else {
+            return Err(warn!("401 Unauthorized: invalid api key"));
+        };
+        let expected = Self::secret_for(&AccessKeyId::from(access_key_id.to_owned()));
+        if !constant_time_eq(expected.as_bytes(), secret.as_bytes()) {
+            return Err(warn!("401 Unauthorized: invalid api key"));
+        }
+        let mut query = Query::from_entry("access_key_id", access_key_id);
+        query.add_filter("status", "active");
+        let Some(mut key) = SiteApiKey::find_one::<SiteApiKey>(&query).await? else {
+            return Err(warn!("401 Unauthorized: api key revoked"));
+        };
+        let now = DateTime::now();
+        if key.last_used_at.map_or(true, |t| t < now - LAST_USED_INTERVAL) {
+            Self::touch(&key.id, now).await?;
+            key.last_used_at = Some(now);
+        }
+        Ok(key)
+    }
+
+    // 2.1 只更新 last_used_at，不会覆盖并发吊销的状态
+    async fn touch(id: &Uuid, now: DateTime) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", id.to_string());
+        query.add_filter("status", "active");
+        let mut updates = Map::new();
+        updates.upsert("last_used_at", now);
+        SiteApiKey::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(())
+    }
+
+    fn secret_for(access_key_id: &AccessKeyId) -> String {
+        let secret_key = SecretAccessKey::new(access_key_id);
+        hex::encode(secret_key.as_ref())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::app_config::SETTINGS;
+
+// INSTREAM 每次发送的块大小，需小于 clamd 的 StreamMaxLength
+const CHUNK_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    pub static ref SCANNER: Box<dyn AttachmentScanner> = {
+        match SETTINGS.attachment_scanner.as_str() {
+            "clamd" => Box::new(ClamdScanner {
+                host: SETTINGS.clamd_host.clone(),
+                port: SETTINGS.clamd_port,
+                timeout: Duration::from_secs(SETTINGS.clamd_timeout_secs),
+            }),
+            _ => Box::new(NoopScanner),
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    // 病毒名称
+    Infected(String),
+}
+
+/// 附件病毒扫描，根据配置 `attachment_scanner` 选择具体实现
+pub trait AttachmentScanner: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn scan<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, Result<ScanVerdict>>;
+}
+
+/// 不扫描，所有文件都视为安全
+pub struct NoopScanner;
+
+impl AttachmentScanner for NoopScanner {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn scan<'a>(&'a self, _bytes: &'a [u8]) -> BoxFuture<'a, Result<ScanVerdict>> {
+        Box::pin(async { Ok(ScanVerdict::Clean) })
+    }
+}
+
+/// clamd TCP 客户端，使用 INSTREAM 命令发送文件内容
+pub struct ClamdScanner {
+    pub host: String,
+    pub port: u16,
+    pub timeout: Duration,
+}
+
+impl ClamdScanner {
+    async fn instream(&self, bytes: &[u8]) -> Result<String> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        stream.write_all(b"zINSTREAM\0").await?;
+        // 每块前是 4 字节大端长度，长度为 0 的块表示结束
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+            stream.write_all(chunk).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+        stream.flush().await?;
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+        let reply = String::from_utf8_lossy(&reply);
+        Ok(reply.trim_end_matches(['\0', '\n']).to_owned())
+    }
+}
+
+impl AttachmentScanner for ClamdScanner {
+    fn name(&self) -> &'static str {
+        "clamd"
+    }
+
+    fn scan<'a>(&'a self, bytes: &'a [u8]) -> BoxFuture<'a, Result<ScanVerdict>> {
+        Box::pin(async move {
+            let reply = timeout(self.timeout, self.instream(bytes))
+                .await
+                .map_err(|_| anyhow!("clamd {}:{} timeout", &self.host, self.port))??;
+            parse_reply(&reply)
+        })
+    }
+}
+
+// 响应格式: "stream: OK" "stream: Eicar-Test-Signature FOUND" "INSTREAM size limit exceeded. ERROR"
+fn parse_reply(reply: &str) -> Result<ScanVerdict> {
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        return Ok(ScanVerdict::Clean);
+    }
+    match result.strip_suffix(" FOUND") {
+        Some(signature) => Ok(ScanVerdict::Infected(signature.trim().to_owned())),
+        None => Err(anyhow!("clamd unexpected reply: {}", reply)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, task::JoinHandle};
+
+    use super::*;
+
+    // 本地的假 clamd：校验 INSTREAM 格式，收完后回复 reply，返回收到的各个块
+    async fn fake_clamd(reply: &'static str) -> (ClamdScanner, JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).await.unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+            let mut chunks = Vec::new();
+            loop {
+                let len = stream.read_u32().await.unwrap() as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                stream.read_exact(&mut chunk).await.unwrap();
+                chunks.push(chunk);
+            }
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            chunks
+        });
+        let scanner = ClamdScanner {
+            host: "127.0.0.1".to_owned(),
+            port,
+            timeout: Duration::from_secs(5),
+        };
+        (scanner, handle)
+    }
+
+    #[test]
+    fn it_parses_clamd_replies() {
+        assert_eq!(parse_reply("stream: OK").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Eicar-Test-Signature FOUND").unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_owned())
+        );
+        assert!(parse_reply("INSTREAM size limit exceeded. ERROR").is_err());
+        assert!(parse_reply("").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_streams_chunks_to_clamd() {
+        let (scanner, handle) = fake_clamd("stream: OK\0").await;
+        let bytes = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect::<Vec<u8>>();
+        assert_eq!(scanner.scan(&bytes).await.unwrap(), ScanVerdict::Clean);
+        let chunks = handle.await.unwrap();
+        let sizes = chunks.iter().map(|c| c.len()).collect::<Vec<usize>>();
+        assert_eq!(sizes, [CHUNK_SIZE, CHUNK_SIZE, 10]);
+        assert_eq!(chunks.concat(), bytes);
+    }
+
+    #[tokio::test]
+    async fn it_reports_infected_files() {
+        let (scanner, handle) = fake_clamd("stream: Eicar-Test-Signature FOUND\0").await;
+        let verdict = scanner.scan(b"X5O!P%@AP").await.unwrap();
+        assert_eq!(verdict, ScanVerdict::Infected("Eicar-Test-Signature".to_owned()));
+        assert_eq!(handle.await.unwrap().concat(), b"X5O!P%@AP");
+    }
+}
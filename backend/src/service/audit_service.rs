@@ -0,0 +1,112 @@
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::Query, orm::Schema,
+    JsonValue, Map, Uuid,
+};
+
+use crate::{
+    model::{AuditEvent, ChatWebsite},
+    utils::metrics_utils,
+};
+
+use super::permission_service::PermissionService;
+
+// 不记录在变更内容中的字段
+const IGNORED_FIELDS: [&str; 3] = ["update_at", "version", "script_home"];
+
+pub struct AuditService;
+
+/**
+ * 1.记录操作日志：操作人、动作、目标、变更前后内容、IP
+ * 2.站点管理员按条件分页查询，登录登出记录没有站点，按站点成员过滤
+ */
+
+impl AuditService {
+    // 1
+    pub async fn record(event: AuditEvent) -> Result<(), Error> {
+        let mut event = event;
+        event.id = Uuid::now_v7();
+        event.create_at = DateTime::now();
+        event.insert().await?;
+        Ok(())
+    }
+
+    // 1.1 操作已经完成，写入失败不影响请求结果，记录错误日志和失败计数
+    pub async fn record_or_log(event: AuditEvent) {
+        let action = event.action.clone();
+        if let Err(e) = Self::record(event).await {
+            tracing::error!("record audit event {} error: {}", action, e);
+            metrics_utils::audit_failed(&action);
+        }
+    }
+
+    // 1.2 对比两个对象的顶层字段，返回 {field: {"before": .., "after": ..}}
+    pub fn diff(before: &JsonValue, after: &JsonValue) -> Map {
+        let empty = Map::new();
+        let before = before.as_object().unwrap_or(&empty);
+        let after = after.as_object().unwrap_or(&empty);
+        let mut diff = Map::new();
+        for (key, value) in after.iter() {
+            if IGNORED_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            let old = before.get(key).cloned().unwrap_or(JsonValue::Null);
+            if &old != value {
+                diff.upsert(key, json!({"before": old, "after": value}));
+            }
+        }
+        for (key, value) in before.iter() {
+            if !after.contains_key(key) && !IGNORED_FIELDS.contains(&key.as_str()) {
+                diff.upsert(key, json!({"before": value, "after": JsonValue::Null}));
+            }
+        }
+        diff
+    }
+
+    // 2 站点日志，以及站点成员的登录、登录失败和登出记录
+    // 不存在的账号登录失败时没有 actor_id，不会出现在任何站点的日志中
+    pub async fn list_events(
+        site: &ChatWebsite,
+        action: Option<String>,
+        actor_id: Option<String>,
+        target_id: Option<String>,
+        page: usize,
+        page_num: usize,
+    ) -> Result<Map, Error> {
+        let mut member_ids = PermissionService::list_members(&site.id)
+            .await?
+            .iter()
+            .map(|m| m.user_id.to_string())
+            .collect::<Vec<String>>();
+        member_ids.push(site.user_id.to_string());
+        let mut query = Query::from_entry(
+            "$or",
+            json!([
+                {"site_id": site.id.to_string()},
+                {
+                    "site_id": JsonValue::Null,
+                    "actor_id": {"$in": member_ids},
+                    "action": {"$in": ["auth.login", "auth.login_failed", "auth.logout"]},
+                },
+            ]),
+        );
+        if let Some(a) = action {
+            query.add_filter("action", a);
+        }
+        if let Some(a) = actor_id {
+            query.add_filter("actor_id", a);
+        }
+        if let Some(t) = target_id {
+            query.add_filter("target_id", t);
+        }
+        let count_query = query.clone();
+        query.order_by("create_at", true);
+        query.set_limit(page_num);
+        query.set_offset((page - 1) * page_num);
+        let total = AuditEvent::count(&count_query).await?;
+        let data = AuditEvent::find::<AuditEvent>(&query).await?;
+        let mut res = Map::new();
+        res.append(&mut Map::from_entry("data", json!(data)));
+        res.append(&mut Map::from_entry("total", total));
+        Ok(res)
+    }
+}
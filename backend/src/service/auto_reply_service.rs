@@ -0,0 +1,295 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use chrono::Timelike;
+use regex::Regex;
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::Query, orm::Schema,
+    warn, Map, Uuid,
+};
+
+use crate::{
+    model::{AutoReplyRule, ChatMessage, ChatWebsite},
+    utils::date_utils::current_date,
+};
+
+use super::chat_service::ChatService;
+
+lazy_static! {
+    // 编译好的正则，按规则 id 缓存，规则修改后按 pattern 重新编译
+    static ref REGEX_CACHE: RwLock<HashMap<Uuid, (String, Regex)>> = RwLock::new(HashMap::new());
+}
+
+pub struct AutoReplyService;
+
+/**
+ * 1.客服配置站点自动回复规则（关键词、正则、首条消息）
+ * 2.访客消息保存后匹配规则，命中后以机器人身份回复
+ * 3.规则试运行，不保存任何消息
+ */
+
+impl AutoReplyService {
+    // 1
+    pub async fn list_rules(site_id: &Uuid) -> Result<Vec<AutoReplyRule>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.order_asc("priority");
+        Ok(AutoReplyRule::find::<AutoReplyRule>(&query).await?)
+    }
+
+    pub async fn save_rule(rule: &mut AutoReplyRule) -> Result<(), Error> {
+        Self::validate_rule(rule)?;
+        rule.update_at = DateTime::now();
+        let mut query = Query::from_entry("id", rule.id.to_string());
+        query.add_filter("site_id", rule.site_id.to_string());
+        if AutoReplyRule::find_one::<AutoReplyRule>(&query).await?.is_some() {
+            rule.clone().update().await?;
+        } else {
+            rule.id = Uuid::now_v7();
+            rule.create_at = DateTime::now();
+            rule.clone().insert().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_rule(site_id: &Uuid, rule_id: &str) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", rule_id);
+        query.add_filter("site_id", site_id.to_string());
+        AutoReplyRule::delete_many(&query).await?;
+        if let Ok(id) = rule_id.parse::<Uuid>() {
+            REGEX_CACHE.write().unwrap_or_else(|e| e.into_inner()).remove(&id);
+        }
+        Ok(())
+    }
+
+    fn validate_rule(rule: &AutoReplyRule) -> Result<(), Error> {
+        match rule.match_type.as_str() {
+            "keyword" => {
+                if rule.pattern.trim().is_empty() {
+                    return Err(warn!("keyword should not be empty"));
+                }
+            }
+            "regex" => {
+                if let Err(e) = Regex::new(&rule.pattern) {
+                    return Err(warn!("invalid regex: {}", e));
+                }
+            }
+            "first_message" => (),
+            _ => return Err(warn!("unknown match type: {}", &rule.match_type)),
+        }
+        for hm in [&rule.hours_start, &rule.hours_end].into_iter().flatten() {
+            if parse_minutes(hm).is_none() {
+                return Err(warn!("invalid time: {}", hm));
+            }
+        }
+        Ok(())
+    }
+
+    // 2 返回第一条命中的规则
+    pub fn match_rules<'a>(
+        rules: &'a [AutoReplyRule],
+        content: &str,
+        first_message: bool,
+    ) -> Option<&'a AutoReplyRule> {
+        let now = current_date();
+        Self::match_rules_at(rules, content, first_message, now.hour() * 60 + now.minute())
+    }
+
+    // 按一天中的分钟数判断生效时间段
+    fn match_rules_at<'a>(
+        rules: &'a [AutoReplyRule],
+        content: &str,
+        first_message: bool,
+        minutes: u32,
+    ) -> Option<&'a AutoReplyRule> {
+        rules
+            .iter()
+            .filter(|r| r.status == "active")
+            .filter(|r| in_hours(r, minutes))
+            .find(|r| match r.match_type.as_str() {
+                "keyword" => {
+                    let text = content.to_lowercase();
+                    r.pattern
+                        .split(',')
+                        .map(|k| k.trim().to_lowercase())
+                        .any(|k| !k.is_empty() && text.contains(&k))
+                }
+                "regex" => cached_regex(r).is_some_and(|re| re.is_match(content)),
+                "first_message" => first_message,
+                _ => false,
+            })
+    }
+
+    // 2.1 访客消息保存之后调用，命中时保存并返回机器人回复
+    pub async fn reply_for(
+        site: &ChatWebsite,
+        message: &ChatMessage,
+    ) -> Result<Option<ChatMessage>, Error> {
+        let rules = Self::list_rules(&site.id).await?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let mut count_query = Query::from_entry("room_id", message.room_id.to_string());
+        count_query.add_filter("sender_type", "visitor");
+        let first_message = ChatMessage::count(&count_query).await? <= 1;
+        let Some(rule) = Self::match_rules(&rules, &message.content, first_message) else {
+            return Ok(None);
+        };
+        let mut reply = ChatMessage::default();
+        reply.id = Uuid::now_v7();
+        reply.name = rule
+            .sender_name
+            .clone()
+            .or(site.title.clone())
+            .unwrap_or_default();
+        reply.content = rule.reply.clone();
+        reply.room_id = message.room_id;
+        reply.reply_to_id = Some(message.id);
+        reply.status = "sended".to_owned();
+        reply.sender_type = "bot".to_owned();
+        reply.create_at = DateTime::now();
+        reply.update_at = DateTime::now();
+        ChatService::save_message(&reply).await?;
+        tracing::info!("auto reply rule {} matched message {}", &rule.id, &message.id);
+        Ok(Some(reply))
+    }
+
+    // 3
+    pub async fn dry_run(site_id: &Uuid, content: &str, first_message: bool) -> Result<Map, Error> {
+        let rules = Self::list_rules(site_id).await?;
+        let mut res = Map::new();
+        match Self::match_rules(&rules, content, first_message) {
+            Some(rule) => {
+                res.upsert("matched", true);
+                res.upsert("rule", json!(rule));
+                res.upsert("reply", rule.reply.clone());
+            }
+            None => {
+                res.upsert("matched", false);
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn cached_regex(rule: &AutoReplyRule) -> Option<Regex> {
+    if let Some((pattern, re)) = REGEX_CACHE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&rule.id)
+    {
+        if *pattern == rule.pattern {
+            return Some(re.clone());
+        }
+    }
+    let re = Regex::new(&rule.pattern).ok()?;
+    REGEX_CACHE
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(rule.id, (rule.pattern.clone(), re.clone()));
+    Some(re)
+}
+
+fn parse_minutes(hm: &str) -> Option<u32> {
+    let (h, m) = hm.trim().split_once(':')?;
+    let h = h.parse::<u32>().ok()?;
+    let m = m.parse::<u32>().ok()?;
+    if h > 23 || m > 59 {
+        return None;
+    }
+    Some(h * 60 + m)
+}
+
+fn in_hours(rule: &AutoReplyRule, minutes: u32) -> bool {
+    let start = rule.hours_start.as_deref().and_then(parse_minutes);
+    let end = rule.hours_end.as_deref().and_then(parse_minutes);
+    match (start, end) {
+        (Some(s), Some(e)) if s <= e => minutes >= s && minutes < e,
+        // 跨零点，例如 18:00 - 09:00
+        (Some(s), Some(e)) => minutes >= s || minutes < e,
+        (Some(s), None) => minutes >= s,
+        (None, Some(e)) => minutes < e,
+        (None, None) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(match_type: &str, pattern: &str) -> AutoReplyRule {
+        AutoReplyRule {
+            id: Uuid::now_v7(),
+            match_type: match_type.to_owned(),
+            pattern: pattern.to_owned(),
+            reply: format!("reply to {}", pattern),
+            status: "active".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn hours(start: Option<&str>, end: Option<&str>) -> AutoReplyRule {
+        AutoReplyRule {
+            hours_start: start.map(|s| s.to_owned()),
+            hours_end: end.map(|s| s.to_owned()),
+            ..rule("first_message", "")
+        }
+    }
+
+    #[test]
+    fn it_matches_keywords_case_insensitively() {
+        let rules = [rule("keyword", "price, Refund")];
+        assert!(AutoReplyService::match_rules_at(&rules, "How do I get a REFUND?", false, 600).is_some());
+        assert!(AutoReplyService::match_rules_at(&rules, "hello", false, 600).is_none());
+        // 空关键词不匹配任何内容
+        let rules = [rule("keyword", " , ")];
+        assert!(AutoReplyService::match_rules_at(&rules, "anything", false, 600).is_none());
+    }
+
+    #[test]
+    fn it_matches_regex_and_first_message() {
+        let rules = [rule("regex", r"^order\s+\d+$"), rule("first_message", "")];
+        let matched = AutoReplyService::match_rules_at(&rules, "order 42", false, 600).unwrap();
+        assert_eq!(matched.match_type, "regex");
+        let matched = AutoReplyService::match_rules_at(&rules, "hi", true, 600).unwrap();
+        assert_eq!(matched.match_type, "first_message");
+        assert!(AutoReplyService::match_rules_at(&rules, "hi", false, 600).is_none());
+    }
+
+    #[test]
+    fn it_returns_the_first_active_rule() {
+        let mut inactive = rule("keyword", "hello");
+        inactive.status = "inactive".to_owned();
+        let rules = [inactive, rule("keyword", "hello"), rule("keyword", "hello world")];
+        let matched = AutoReplyService::match_rules_at(&rules, "hello world", false, 600).unwrap();
+        assert_eq!(matched.id, rules[1].id);
+    }
+
+    #[test]
+    fn it_checks_hours() {
+        let day = hours(Some("09:00"), Some("18:00"));
+        assert!(!in_hours(&day, 8 * 60 + 59));
+        assert!(in_hours(&day, 9 * 60));
+        assert!(in_hours(&day, 17 * 60 + 59));
+        assert!(!in_hours(&day, 18 * 60));
+
+        assert!(in_hours(&hours(None, None), 0));
+        assert!(in_hours(&hours(Some("12:00"), None), 23 * 60));
+        assert!(!in_hours(&hours(None, Some("12:00")), 12 * 60));
+        // 无效的时间按未设置处理
+        assert!(in_hours(&hours(Some("25:00"), None), 0));
+    }
+
+    #[test]
+    fn it_checks_hours_across_midnight() {
+        let night = hours(Some("18:00"), Some("09:00"));
+        assert!(in_hours(&night, 18 * 60));
+        assert!(in_hours(&night, 23 * 60 + 59));
+        assert!(in_hours(&night, 0));
+        assert!(in_hours(&night, 8 * 60 + 59));
+        assert!(!in_hours(&night, 9 * 60));
+        assert!(!in_hours(&night, 12 * 60));
+
+        let rules = [night];
+        assert!(AutoReplyService::match_rules_at(&rules, "hi", true, 2 * 60).is_some());
+        assert!(AutoReplyService::match_rules_at(&rules, "hi", true, 12 * 60).is_none());
+    }
+}
@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use zino_core::{datetime::DateTime, json, model::Query, orm::Schema, Uuid};
+
+use crate::model::{ChatMessage, ChatRoom, ChatWebsite};
+
+use super::{
+    auto_reply_service::AutoReplyService, chat_service::ChatService,
+    webhook_service::WebhookService,
+};
+
+// 访客输入这些词时转人工
+const HANDOFF_KEYWORDS: [&str; 4] = ["人工", "转人工", "human", "agent"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BotRequest {
+    pub site_key: String,
+    pub room_id: String,
+    pub room_key: String,
+    pub message_id: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BotReply {
+    pub reply: Option<String>,
+    // 机器人请求转人工
+    pub handoff: bool,
+}
+
+/// 无客服接待的房间由机器人回复
+pub trait ChatBot: Send + Sync {
+    fn name(&self) -> String;
+    fn reply<'a>(&'a self, request: &'a BotRequest) -> BoxFuture<'a, Result<BotReply>>;
+}
+
+/// 将访客消息 POST 到配置的地址，响应 `{"reply": "...", "handoff": false}`
+pub struct WebhookBot {
+    pub url: String,
+    pub name: String,
+}
+
+impl ChatBot for WebhookBot {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn reply<'a>(&'a self, request: &'a BotRequest) -> BoxFuture<'a, Result<BotReply>> {
+        Box::pin(async move {
+            let resp = reqwest::Client::new()
+                .post(&self.url)
+                .timeout(Duration::from_secs(10))
+                .json(request)
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(anyhow!("bot webhook {} response {}", &self.url, resp.status()));
+            }
+            Ok(resp.json::<BotReply>().await?)
+        })
+    }
+}
+
+/// 使用站点自动回复规则应答，命中转人工关键词时转人工
+pub struct RulesBot {
+    pub site_id: Uuid,
+    pub name: String,
+}
+
+impl ChatBot for RulesBot {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn reply<'a>(&'a self, request: &'a BotRequest) -> BoxFuture<'a, Result<BotReply>> {
+        Box::pin(async move {
+            let text = request.content.trim().to_lowercase();
+            if HANDOFF_KEYWORDS.iter().any(|k| text == *k) {
+                return Ok(BotReply {
+                    reply: Some("正在为您转接人工客服，请稍候".to_owned()),
+                    handoff: true,
+                });
+            }
+            let rules = AutoReplyService::list_rules(&self.site_id)
+                .await
+                .map_err(|e| anyhow!("{e}"))?;
+            let reply = AutoReplyService::match_rules(&rules, &request.content, false)
+                .map(|r| r.reply.clone());
+            Ok(BotReply {
+                reply,
+                handoff: false,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BotOutcome {
+    pub reply: Option<ChatMessage>,
+    pub handoff: bool,
+}
+
+pub struct BotService;
+
+impl BotService {
+    pub fn bot_for(site: &ChatWebsite) -> Option<Box<dyn ChatBot>> {
+        let name = site.title.clone().unwrap_or("bot".to_owned());
+        match site.bot_type.as_deref() {
+            Some("rules") => Some(Box::new(RulesBot {
+                site_id: site.id,
+                name,
+            })),
+            Some("webhook") => site.bot_webhook_url.as_ref().map(|url| {
+                Box::new(WebhookBot {
+                    url: url.clone(),
+                    name,
+                }) as Box<dyn ChatBot>
+            }),
+            _ => None,
+        }
+    }
+
+    // 访客消息保存之后调用：有机器人且房间无人接待时由机器人回复，否则走自动回复规则
+    // 回复与其他消息一样发出 message.created 事件
+    pub async fn on_visitor_message(site_key: &str, message: &ChatMessage) -> Result<BotOutcome> {
+        let query = Query::from_entry("site_key", site_key);
+        let Some(site) = ChatWebsite::find_one::<ChatWebsite>(&query)
+            .await
+            .map_err(|e| anyhow!("{e}"))?
+        else {
+            return Ok(BotOutcome::default());
+        };
+        let room = ChatRoom::find_by_id::<ChatRoom>(&message.room_id)
+            .await
+            .map_err(|e| anyhow!("{e}"))?;
+        let bot = Self::bot_for(&site);
+        let outcome = match (bot, room) {
+            (Some(bot), Some(mut room))
+                if room.assigned_user_id.is_none() && room.queue_status == "none" =>
+            {
+                let request = BotRequest {
+                    site_key: site_key.to_owned(),
+                    room_id: room.id.to_string(),
+                    room_key: room.room_key.clone(),
+                    message_id: message.id.to_string(),
+                    content: message.content.clone(),
+                };
+                let bot_reply = match bot.reply(&request).await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        // 机器人不可用时直接转人工
+                        tracing::warn!("bot {} reply error: {}", bot.name(), e);
+                        BotReply {
+                            reply: None,
+                            handoff: true,
+                        }
+                    }
+                };
+                let mut outcome = BotOutcome::default();
+                if let Some(text) = bot_reply.reply {
+                    let reply = Self::bot_message(&bot.name(), &text, message);
+                    ChatService::save_message(&reply)
+                        .await
+                        .map_err(|e| anyhow!("{e}"))?;
+                    outcome.reply = Some(reply);
+                }
+                if bot_reply.handoff {
+                    ChatService::escalate_room(&mut room)
+                        .await
+                        .map_err(|e| anyhow!("{e}"))?;
+                    outcome.handoff = true;
+                }
+                outcome
+            }
+            _ => {
+                let reply = AutoReplyService::reply_for(&site, message)
+                    .await
+                    .map_err(|e| anyhow!("{e}"))?;
+                BotOutcome {
+                    reply,
+                    handoff: false,
+                }
+            }
+        };
+        if let Some(reply) = &outcome.reply {
+            WebhookService::emit_later(site.id, "message.created", json!(reply));
+        }
+        Ok(outcome)
+    }
+
+    fn bot_message(bot_name: &str, text: &str, message: &ChatMessage) -> ChatMessage {
+        let mut reply = ChatMessage::default();
+        reply.id = Uuid::now_v7();
+        reply.name = bot_name.to_owned();
+        reply.content = text.to_owned();
+        reply.room_id = message.room_id;
+        reply.reply_to_id = Some(message.id);
+        reply.status = "sended".to_owned();
+        reply.sender_type = "bot".to_owned();
+        reply.create_at = DateTime::now();
+        reply.update_at = DateTime::now();
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+
+    // 本地的机器人接口，返回给定的状态码和响应体，收到的请求体通过 JoinHandle 返回
+    async fn bot_server(status: u16, body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/bot", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                reader.read_line(&mut line).await.unwrap();
+                let header = line.trim_end().to_lowercase();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("content-length: ") {
+                    length = value.parse::<usize>().unwrap();
+                }
+            }
+            let mut request = vec![0; length];
+            reader.read_exact(&mut request).await.unwrap();
+            let resp = format!(
+                "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            reader.get_mut().write_all(resp.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    fn request() -> BotRequest {
+        BotRequest {
+            site_key: "site".to_owned(),
+            room_id: Uuid::now_v7().to_string(),
+            room_key: "room".to_owned(),
+            message_id: Uuid::now_v7().to_string(),
+            content: "where is my order".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_posts_messages_to_webhook_bot() {
+        let (url, handle) = bot_server(200, r#"{"reply": "let me check", "handoff": true}"#).await;
+        let bot = WebhookBot { url, name: "bot".to_owned() };
+        let request = request();
+        let reply = bot.reply(&request).await.unwrap();
+        assert_eq!(reply.reply.as_deref(), Some("let me check"));
+        assert!(reply.handoff);
+
+        let received = serde_json::from_str::<serde_json::Value>(&handle.await.unwrap()).unwrap();
+        assert_eq!(received["content"], "where is my order");
+        assert_eq!(received["room_key"], "room");
+        assert_eq!(received["message_id"], request.message_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn it_defaults_missing_reply_fields() {
+        let (url, _handle) = bot_server(200, "{}").await;
+        let bot = WebhookBot { url, name: "bot".to_owned() };
+        let reply = bot.reply(&request()).await.unwrap();
+        assert!(reply.reply.is_none());
+        assert!(!reply.handoff);
+    }
+
+    #[tokio::test]
+    async fn it_fails_on_error_status() {
+        let (url, _handle) = bot_server(500, "{}").await;
+        let bot = WebhookBot { url, name: "bot".to_owned() };
+        assert!(bot.reply(&request()).await.is_err());
+    }
+}
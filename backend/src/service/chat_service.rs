@@ -1,11 +1,22 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::{
-    app_config::SETTINGS, domain::website_config::WebsiteConfig, model::{ChatMessage, ChatRoom, ChatWebsite}, utils::{self, generate_random_string}
+    app_config::SETTINGS,
+    domain::website_config::WebsiteConfig, dto::cursor_page::{CursorPage, PageCursor}, model::{AuditEvent, ChatMessage, ChatReadCursor, ChatRoom, ChatWebsite}, utils::{self, generate_random_string}
 };
+use zino::prelude::AccessKeyId;
 use zino_core::{
     datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::Query, orm::{ModelAccessor, Schema}, warn, JsonValue, Map, Uuid
 };
 
-use super::room_message_state::MessageStatusManager;
+use super::{
+    audit_service::AuditService,
+    media_service::MediaService,
+    permission_service::{PermissionService, SiteRole},
+    read_cursor_service::{ReadCursorService, AGENT_SIDE},
+    room_message_state::MessageStatusManager,
+    webhook_service::WebhookService,
+};
 
 pub struct ChatService;
 
@@ -16,6 +27,8 @@ pub struct ChatService;
  * 4.选择客服列表拉取聊天消息
  * 5.发送聊天消息并保存到数据库
  * 6.聊天消息撤回，聊天消息删除
+ * 7.定时按未读消息修正 Redis 未读数
+ * 8.补全旧消息的 sender_type
  */
 
 impl ChatService {
@@ -25,41 +38,139 @@ impl ChatService {
             "user_id",
             website_config.user_id.to_string(),
         ));
+        query.add_filter("status", json!({"$ne": "archived"}));
+        query.order_asc("create_at");
         if let Some(mut chat_website) = ChatWebsite::find_one::<ChatWebsite>(&query).await? {
             chat_website.script_home = SETTINGS.script_home.clone();
             return Ok(Some(chat_website));
+        }
+        // 作为成员加入的站点，不再为其新建站点
+        if let Some(site) = Self::list_sites(&website_config.user_id, false)
+            .await?
+            .into_iter()
+            .next()
+        {
+            return Ok(Some(site));
+        }
+        Self::create_site(website_config).await.map(Some)
+    }
+
+    // 1.0 新建站点，一个账号可以有多个站点
+    pub async fn create_site(website_config: &WebsiteConfig) -> Result<ChatWebsite, Error> {
+        let mut chat_website = ChatWebsite::default();
+        chat_website.id = Uuid::now_v7();
+        chat_website.site_key = Self::gen_site_key().await?;
+        chat_website.status = "inited".to_owned();
+        chat_website.domain = website_config.domain.clone();
+        chat_website.title = website_config.title.clone();
+        chat_website.welcome_slogan = website_config.welcome_slogan.clone();
+        chat_website.position = website_config.position.clone();
+        chat_website.user_id = website_config.user_id;
+        chat_website.script_home = SETTINGS.script_home.clone();
+        let result = chat_website.clone();
+        chat_website.insert().await?;
+        Ok(result)
+    }
+
+    // 1.0 账号下的站点列表，包括作为成员加入的站点
+    pub async fn list_sites(user_id: &Uuid, include_archived: bool) -> Result<Vec<ChatWebsite>, Error> {
+        let mut site_ids = PermissionService::member_site_ids(user_id)
+            .await?
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>();
+        let mut query: Query = if site_ids.is_empty() {
+            Query::new(Map::from_entry("user_id", user_id.to_string()))
         } else {
-            let mut chat_website = ChatWebsite::default();
-            chat_website.id = Uuid::now_v7();
-            chat_website.site_key = Self::gen_site_key().await?;
-            chat_website.title = website_config.title.clone();
-            chat_website.welcome_slogan = website_config.welcome_slogan.clone();
-            chat_website.position = website_config.position.clone();
-            chat_website.user_id = website_config.user_id;
-            chat_website.script_home = SETTINGS.script_home.clone();
-            let result = chat_website.clone();
-            chat_website.insert().await?;
-            return Ok(Some(result));
+            let owned = Query::new(Map::from_entry("user_id", user_id.to_string()));
+            site_ids.extend(
+                ChatWebsite::find::<ChatWebsite>(&owned)
+                    .await?
+                    .iter()
+                    .map(|s| s.id.to_string()),
+            );
+            Query::new(Map::from_entry("id", json!({"$in": site_ids})))
+        };
+        if !include_archived {
+            query.add_filter("status", json!({"$ne": "archived"}));
+        }
+        query.order_asc("create_at");
+        let mut sites = ChatWebsite::find::<ChatWebsite>(&query).await?;
+        for site in sites.iter_mut() {
+            site.script_home = SETTINGS.script_home.clone();
         }
+        Ok(sites)
+    }
+
+    // 1.2 归档站点，归档后不再接入新的访客
+    pub async fn archive_site(user_id: &Uuid, site_id: &str, archived: bool) -> Result<ChatWebsite, Error> {
+        let (mut chat_website, _) = PermissionService::check(user_id, site_id, SiteRole::Owner).await?;
+        if archived && chat_website.status != "archived" {
+            chat_website.archived_status = Some(chat_website.status.clone());
+            chat_website.status = "archived".to_owned();
+        } else if !archived && chat_website.status == "archived" {
+            // 取消归档时恢复归档前的状态，没有记录的按未接入处理，前台加载后会重新确认
+            chat_website.status = chat_website
+                .archived_status
+                .take()
+                .unwrap_or_else(|| "inited".to_owned());
+        }
+        chat_website.update_at = DateTime::now();
+        chat_website.clone().update().await?;
+        chat_website.script_home = SETTINGS.script_home.clone();
+        Ok(chat_website)
     }
     // 1.1
     pub async fn save_site(website_config: &WebsiteConfig) -> Result<Option<ChatWebsite>, Error> {
-        let mut query: Query = Query::new(Map::from_entry(
-            "user_id",
-            website_config.user_id.to_string(),
-        ));
-        query.add_filter("id", website_config.id.clone().unwrap().to_string());
-        if let Some(mut chat_website) = ChatWebsite::find_one::<ChatWebsite>(&query).await? {
-            chat_website.title = website_config.title.clone();
-            chat_website.welcome_slogan = website_config.welcome_slogan.clone();
-            chat_website.position = website_config.position.clone();
-            chat_website.update_at = DateTime::now();
-            chat_website.clone().update().await?;
-            chat_website.script_home = SETTINGS.script_home.clone();
-            return Ok(Some(chat_website));
-        } else {
-            return Err(warn!("error save website config"));
+        let Some(site_id) = website_config.id.clone() else {
+            return Err(warn!("site id should provied"));
+        };
+        // 只有管理员可以修改站点配置
+        let (mut chat_website, _) =
+            PermissionService::check(&website_config.user_id, &site_id, SiteRole::Admin).await?;
+        if website_config.domain.is_some() {
+            chat_website.domain = website_config.domain.clone();
+        }
+        chat_website.title = website_config.title.clone();
+        chat_website.welcome_slogan = website_config.welcome_slogan.clone();
+        chat_website.position = website_config.position.clone();
+        if let Some(bot_type) = &website_config.bot_type {
+            if !["none", "rules", "webhook"].contains(&bot_type.as_str()) {
+                return Err(warn!("unknown bot type: {}", bot_type));
+            }
+            if bot_type == "webhook" && website_config.bot_webhook_url.is_none() {
+                return Err(warn!("bot webhook url should provied"));
+            }
+            chat_website.bot_type = Some(bot_type.clone());
+            chat_website.bot_webhook_url = website_config.bot_webhook_url.clone();
+        }
+        // 上传限制：传 0 或空字符串恢复为全局配置
+        if let Some(max_bytes) = website_config.upload_max_bytes {
+            chat_website.upload_max_bytes = Some(max_bytes).filter(|b| *b > 0);
         }
+        if let Some(mime_types) = &website_config.upload_mime_types {
+            if mime_types.split(',').any(|m| !m.trim().is_empty() && !m.contains('/')) {
+                return Err(warn!("invalid mime types: {}", mime_types));
+            }
+            chat_website.upload_mime_types = Some(mime_types.clone()).filter(|m| !m.trim().is_empty());
+        }
+        if let Some(quota) = website_config.room_quota_bytes {
+            chat_website.room_quota_bytes = Some(quota).filter(|q| *q > 0);
+        }
+        if let Some(quota) = website_config.site_quota_bytes {
+            chat_website.site_quota_bytes = Some(quota).filter(|q| *q > 0);
+        }
+        // 关闭加密时保留密钥 id，已加密的附件仍按附件记录的密钥解密
+        if let Some(encrypt) = website_config.encrypt_attachments {
+            chat_website.encrypt_attachments = Some(encrypt);
+            if encrypt && chat_website.attachment_key_id.is_none() {
+                chat_website.attachment_key_id = Some(AccessKeyId::new().to_string());
+            }
+        }
+        chat_website.update_at = DateTime::now();
+        chat_website.clone().update().await?;
+        chat_website.script_home = SETTINGS.script_home.clone();
+        Ok(Some(chat_website))
     }
 
     async fn gen_site_key() -> Result<String, Error> {
@@ -88,6 +199,9 @@ impl ChatService {
     pub async fn load_site(site_key: &String) -> Result<bool, Error> {
         let query: Query = Query::new(Map::from_entry("site_key", site_key.clone()));
         if let Some(mut chat_website) = ChatWebsite::find_one::<ChatWebsite>(&query).await? {
+            if chat_website.status == "archived" {
+                return Ok(false);
+            }
             chat_website.status = "confirmed".to_string();
             chat_website.update().await?;
             return Ok(true);
@@ -126,6 +240,7 @@ impl ChatService {
                     chat_room.room_key = rkey;
                     let room = chat_room.clone();
                     chat_room.insert().await?;
+                    WebhookService::emit_later(room.room_site_id, "room.created", json!(room));
                     Ok(Some(room))
                 }
             } else {
@@ -167,40 +282,107 @@ impl ChatService {
         Ok(())
     }
 
-    // 3 获取所有聊天房间列表（客服人员）
-    pub async fn list_rooms(
-        site_id: &Uuid,
-        page: usize,
-        page_num: usize,
-    ) -> Result<Map, Error> {
+    // 2.2 客服结束会话
+    pub async fn close_room(room: &mut ChatRoom) -> Result<(), Error> {
+        room.status = "closed".to_owned();
+        room.queue_status = "none".to_owned();
+        room.update_at = DateTime::now();
+        room.clone().update().await?;
+        WebhookService::emit_later(room.room_site_id, "room.closed", json!(room));
+        Ok(())
+    }
+
+    // 2.3 访客评价
+    pub async fn rate_room(room: &mut ChatRoom, rating: i32, comment: Option<String>) -> Result<(), Error> {
+        if !(1..=5).contains(&rating) {
+            return Err(warn!("rating should between 1 and 5"));
+        }
+        room.rating = Some(rating);
+        room.rating_comment = comment;
+        room.update_at = DateTime::now();
+        room.clone().update().await?;
+        WebhookService::emit_later(
+            room.room_site_id,
+            "rating.submitted",
+            json!({
+                "room_id": room.id.to_string(),
+                "room_key": &room.room_key,
+                "rating": rating,
+                "comment": &room.rating_comment,
+            }),
+        );
+        Ok(())
+    }
+
+    // 2.4 转人工，进入客服排队队列
+    pub async fn escalate_room(room: &mut ChatRoom) -> Result<(), Error> {
+        if room.queue_status == "queued" {
+            return Ok(());
+        }
+        room.queue_status = "queued".to_owned();
+        room.queued_at = Some(DateTime::now());
+        room.assigned_user_id = None;
+        room.update_at = DateTime::now();
+        room.clone().update().await?;
+        Ok(())
+    }
+
+    // 2.5 客服接入，离开排队队列
+    pub async fn assign_room(room: &mut ChatRoom, user_id: &Uuid) -> Result<(), Error> {
+        if room.assigned_user_id == Some(*user_id) && room.queue_status == "serving" {
+            return Ok(());
+        }
+        let before = json!(room);
+        room.queue_status = "serving".to_owned();
+        room.assigned_user_id = Some(*user_id);
+        room.update_at = DateTime::now();
+        room.clone().update().await?;
+        let mut event = AuditEvent::default();
+        event.site_id = Some(room.room_site_id);
+        event.actor_id = Some(*user_id);
+        event.action = "room.assign".to_owned();
+        event.target_model = "ChatRoom".to_owned();
+        event.target_id = room.id.to_string();
+        event.diff = AuditService::diff(&before, &json!(room));
+        AuditService::record_or_log(event).await;
+        Ok(())
+    }
+
+    // 2.5.1 转接给其他客服
+    pub async fn transfer_room(room: &mut ChatRoom, user_id: &Uuid) -> Result<(), Error> {
+        room.queue_status = "serving".to_owned();
+        room.assigned_user_id = Some(*user_id);
+        room.update_at = DateTime::now();
+        room.clone().update().await?;
+        Ok(())
+    }
+
+    // 2.6 排队中的房间，先到先服务
+    pub async fn list_queue(site_id: &Uuid) -> Result<Vec<ChatRoom>, Error> {
+        let mut query: Query = Query::new(Map::from_entry("room_site_id", site_id.to_string()));
+        query.add_filter("queue_status", "queued");
+        query.order_asc("queued_at");
+        Ok(ChatRoom::find::<ChatRoom>(&query).await?)
+    }
+
+    // 3 获取所有聊天房间列表（客服人员），按 id 游标翻页
+    pub async fn list_rooms(site_id: &Uuid, cursor: &PageCursor) -> Result<Map, Error> {
         let mut query: Query = Query::new(Map::from_entry("room_site_id", site_id.to_string()));
-        let count_query = query.clone();
-        query.order_by("create_at", true);
-        query.set_limit(page_num);
-        query.set_offset((page - 1) * page_num);
-        let total = ChatRoom::count(&count_query).await?;
-        let data = ChatRoom::find::<ChatRoom>(&query).await?;
-        let mut res  = Map::new();
-        tracing::info!("list rooms 1: {data:?}");
-        let md = data.iter().map(|r| -> JsonValue {
-            serde_json::to_value(r).unwrap()
-        }).collect::<Vec<JsonValue>>();
-        tracing::info!("list rooms 2: {md:?}");
-        res.append(&mut Map::from_entry("data", md));
-        res.append(&mut Map::from_entry("total", total));
+        let total = ChatRoom::count(&query).await?;
+        cursor.apply(&mut query);
+        let rows = ChatRoom::find::<ChatRoom>(&query).await?;
+        let page = cursor.page(rows, |r| r.id);
+        let mut res = Map::new();
+        res.upsert("data", json!(page.data));
+        res.upsert("total", total);
+        res.upsert("next_cursor", page.next_cursor);
+        res.upsert("prev_cursor", page.prev_cursor);
         Ok(res)
     }
 
-    // 3.1 （客服人员） 加入房间，消息变成已读。
-    pub async fn join_room(room: &ChatRoom) -> Result<(), Error> {
-        let mut query: Query = Query::new(Map::from_entry("room_id", room.id.to_string()));
-        query.add_filter("status", "sended");
-        let mut messages = ChatMessage::find::<ChatMessage>(&query).await?;
-        for message in messages.iter_mut() {
-            message.status = "readed".to_owned();
-            message.update_at = DateTime::now();
-            message.clone().update().await?;
-        }
+    // 3.1 （客服人员） 加入房间，消息变成已读。只更新客服端已读位置
+    pub async fn join_room(room: &ChatRoom, user_id: Option<Uuid>) -> Result<(), Error> {
+        ReadCursorService::mark_read(room, AGENT_SIDE, user_id).await?;
         let site_query: Query = Query::new(Map::from_entry("id", room.room_site_id.to_string()));
         let site = ChatWebsite::find_one::<ChatWebsite>(&site_query).await?;
         if site.is_some() {// 读取消息后重置消息状态
@@ -222,21 +404,110 @@ impl ChatService {
         Ok(())
     }
 
-    // 3.2 获取消息列表
+    // 3.1 删除消息，只标记状态
+    pub async fn delete_message(site_id: &Uuid, message_id: &str) -> Result<ChatMessage, Error> {
+        let Some(mut message) = ChatMessage::find_one::<ChatMessage>(&Query::from_entry("id", message_id)).await? else {
+            return Err(warn!("message not found"));
+        };
+        let mut room_query = Query::from_entry("id", message.room_id.to_string());
+        room_query.add_filter("room_site_id", site_id.to_string());
+        if ChatRoom::find_one::<ChatRoom>(&room_query).await?.is_none() {
+            return Err(warn!("room forbidden"));
+        }
+        message.status = "delete".to_owned();
+        message.update_at = DateTime::now();
+        message.clone().update().await?;
+        Ok(message)
+    }
+
+    // 3.2 获取消息列表，按 id 游标翻页
     pub async fn list_messages(
         room: &ChatRoom,
-        page: usize,
-        page_num: usize,
-        ts: usize,
-    ) -> Result<Vec<ChatMessage>, Error> {
+        cursor: &PageCursor,
+    ) -> Result<CursorPage<ChatMessage>, Error> {
         let mut query = Query::new(Map::from_entry("room_id", room.id.to_string()));
-        query.order_desc("create_at");
-        let start = DateTime::from_timestamp(ts as  i64);
-        query.add_filter("create_at", json!({"$le": start}));
-        query.set_limit(page_num);
-        query.set_offset((page - 1) * page_num);
-        // Self::join_room(room).await?;
-        tracing::info!("list messages condition: start:{}: {:?}", &start, &query);
-        Ok(ChatMessage::find(&query).await?)
+        query.add_filter("status", json!({"$ne": "delete"}));
+        cursor.apply(&mut query);
+        tracing::info!("list messages condition: {:?}", &query);
+        let rows = ChatMessage::find::<ChatMessage>(&query).await?;
+        let mut page = cursor.page(rows, |m| m.id);
+        ReadCursorService::apply_read_state(room, &mut page.data).await?;
+        MediaService::expand(room, &mut page.data).await?;
+        Ok(page)
+    }
+
+    // 8 加 sender_type 之前的消息都是默认的 visitor，有 user_id 的是客服发送的，返回更新的条数
+    pub async fn backfill_sender_type() -> Result<u64, Error> {
+        let sql = format!(
+            "UPDATE {table} SET sender_type = 'agent' \
+            WHERE user_id IS NOT NULL AND sender_type = 'visitor'",
+            table = ChatMessage::table_name(),
+        );
+        let ctx = ChatMessage::execute(&sql, None).await?;
+        Ok(ctx.rows_affected().unwrap_or_default())
+    }
+
+    // 3.3 站点各房间客服未读的访客消息数，在数据库中按房间分组计数
+    async fn unread_counts(site_id: &Uuid) -> Result<HashMap<String, i64>, Error> {
+        let sql = format!(
+            "SELECT m.room_id, COUNT(*)::bigint AS total \
+            FROM {message} m \
+            JOIN {room} r ON r.id = m.room_id \
+            LEFT JOIN {cursor} c ON c.room_id = m.room_id AND c.side = #{{side}} \
+            WHERE r.room_site_id = #{{site_id}} AND m.status = 'sended' \
+                AND m.sender_type = 'visitor' \
+                AND (c.last_read_id IS NULL OR m.id > c.last_read_id) \
+            GROUP BY m.room_id",
+            message = ChatMessage::table_name(),
+            room = ChatRoom::table_name(),
+            cursor = ChatReadCursor::table_name(),
+        );
+        let mut params = Map::new();
+        params.upsert("site_id", site_id.to_string());
+        params.upsert("side", AGENT_SIDE);
+        let rows = ChatMessage::query_as::<Map>(&sql, Some(&params)).await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| Some((row.get_str("room_id")?.to_owned(), row.get_i64("total")?)))
+            .collect())
+    }
+
+    // 3.3 按未读消息重新计算 Redis 未读数，返回修正的计数个数
+    pub async fn reconcile_unread_counts() -> Result<usize, Error> {
+        let sites = ChatWebsite::find::<ChatWebsite>(&Query::from_entry("status", "confirmed")).await?;
+        let mut repaired = 0;
+        for site in sites.iter() {
+            let expected = Self::unread_counts(&site.id).await?;
+            // Redis 中有记录但已没有未读消息的房间也要清零
+            let counted = MessageStatusManager::counted_rooms(&site.site_key)
+                .await
+                .map_err(|e| warn!("scan unread counts error: {}", e))?;
+            let rooms = counted
+                .into_iter()
+                .chain(expected.keys().cloned())
+                .collect::<HashSet<String>>();
+            for room_id in rooms.iter() {
+                let count = expected.get(room_id).copied().unwrap_or(0);
+                let before = MessageStatusManager::repair_room_count(&site.site_key, room_id, count)
+                    .await
+                    .map_err(|e| warn!("repair unread count error: {}", e))?;
+                if before != count {
+                    tracing::warn!("unread count drift {}:{} {} -> {}", &site.site_key, room_id, before, count);
+                    repaired += 1;
+                }
+            }
+            let total = expected.values().sum::<i64>();
+            let total_unread = MessageStatusManager::get_total_unread(&site.site_key)
+                .await
+                .map_err(|e| warn!("get total unread error: {}", e))?;
+            if total_unread != total {
+                tracing::warn!("total unread drift {} {} -> {}", &site.site_key, total_unread, total);
+                MessageStatusManager::set_total_unread(&site.site_key, total)
+                    .await
+                    .map_err(|e| warn!("set total unread error: {}", e))?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
     }
 }
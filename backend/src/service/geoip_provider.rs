@@ -0,0 +1,158 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Deserialize;
+
+use crate::app_config::SETTINGS;
+
+lazy_static! {
+    pub static ref GEOIP: Vec<Box<dyn GeoIpProvider>> = {
+        SETTINGS
+            .geoip_providers
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| match p {
+                "mmdb" => Some(Box::new(MmdbProvider::open(
+                    &SETTINGS.geoip_mmdb_path,
+                    &SETTINGS.geoip_asn_mmdb_path,
+                )) as Box<dyn GeoIpProvider>),
+                "ipinfo" => Some(Box::new(IpinfoProvider {
+                    token: SETTINGS.ipinfo_token.clone(),
+                }) as Box<dyn GeoIpProvider>),
+                _ => {
+                    tracing::warn!("unknown geoip provider: {}", p);
+                    None
+                }
+            })
+            .collect()
+    };
+}
+
+/// 与 ipinfo 的字段一致，country 为 ISO 代码，loc 为 "纬度,经度"
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GeoLocation {
+    pub city: Option<String>,
+    pub region: Option<String>,
+    pub country: Option<String>,
+    pub loc: Option<String>,
+    pub org: Option<String>,
+}
+
+/// IP 归属地查询，根据配置 `geoip_providers` 按顺序查询，查到即止
+pub trait GeoIpProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn lookup<'a>(&'a self, ip: IpAddr) -> BoxFuture<'a, Result<Option<GeoLocation>>>;
+}
+
+/// 本地 MaxMind 格式数据库（GeoLite2-City，可选 GeoLite2-ASN），不访问外部服务
+pub struct MmdbProvider {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl MmdbProvider {
+    // 数据库文件不存在时只记录日志，查询结果为空
+    pub fn open(city_path: &str, asn_path: &str) -> Self {
+        let open = |path: &str| {
+            if path.is_empty() {
+                return None;
+            }
+            Reader::open_readfile(path)
+                .map_err(|e| tracing::warn!("open mmdb {} error: {}", path, e))
+                .ok()
+        };
+        Self {
+            city: open(city_path),
+            asn: open(asn_path),
+        }
+    }
+
+    fn lookup_city(&self, ip: IpAddr) -> Result<Option<GeoLocation>> {
+        let Some(reader) = &self.city else {
+            return Ok(None);
+        };
+        let city = match reader.lookup::<geoip2::City>(ip) {
+            Ok(city) => city,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return Ok(None),
+            Err(e) => return Err(anyhow!("mmdb lookup {} error: {}", ip, e)),
+        };
+        let location = city.location.as_ref();
+        Ok(Some(GeoLocation {
+            city: city.city.and_then(|c| english_name(c.names)),
+            region: city
+                .subdivisions
+                .and_then(|s| s.into_iter().next())
+                .and_then(|s| english_name(s.names)),
+            country: city.country.and_then(|c| c.iso_code).map(|c| c.to_owned()),
+            loc: location
+                .and_then(|l| l.latitude.zip(l.longitude))
+                .map(|(lat, lon)| format!("{:.4},{:.4}", lat, lon)),
+            org: None,
+        }))
+    }
+
+    // ipinfo 的 org 格式: "AS15169 Google LLC"
+    fn lookup_org(&self, ip: IpAddr) -> Option<String> {
+        let asn = self.asn.as_ref()?.lookup::<geoip2::Asn>(ip).ok()?;
+        match (asn.autonomous_system_number, asn.autonomous_system_organization) {
+            (Some(number), Some(org)) => Some(format!("AS{} {}", number, org)),
+            (Some(number), None) => Some(format!("AS{}", number)),
+            (None, org) => org.map(|o| o.to_owned()),
+        }
+    }
+}
+
+impl GeoIpProvider for MmdbProvider {
+    fn name(&self) -> &'static str {
+        "mmdb"
+    }
+
+    fn lookup<'a>(&'a self, ip: IpAddr) -> BoxFuture<'a, Result<Option<GeoLocation>>> {
+        Box::pin(async move {
+            let org = self.lookup_org(ip);
+            match self.lookup_city(ip)? {
+                Some(location) => Ok(Some(GeoLocation { org, ..location })),
+                None if org.is_some() => Ok(Some(GeoLocation {
+                    org,
+                    ..Default::default()
+                })),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+/// 调用 ipinfo.io，会把访客 IP 发给第三方，只作为可选的兜底
+pub struct IpinfoProvider {
+    pub token: String,
+}
+
+impl GeoIpProvider for IpinfoProvider {
+    fn name(&self) -> &'static str {
+        "ipinfo"
+    }
+
+    fn lookup<'a>(&'a self, ip: IpAddr) -> BoxFuture<'a, Result<Option<GeoLocation>>> {
+        Box::pin(async move {
+            let mut request = reqwest::Client::new()
+                .get(format!("https://ipinfo.io/{}/json", ip))
+                .timeout(Duration::from_secs(5));
+            if !self.token.is_empty() {
+                request = request.bearer_auth(&self.token);
+            }
+            let resp = request.send().await?;
+            if !resp.status().is_success() {
+                return Err(anyhow!("ipinfo {} response {}", ip, resp.status()));
+            }
+            Ok(Some(resp.json::<GeoLocation>().await?))
+        })
+    }
+}
+
+fn english_name(names: Option<std::collections::BTreeMap<&str, &str>>) -> Option<String> {
+    names?.get("en").map(|n| (*n).to_owned())
+}
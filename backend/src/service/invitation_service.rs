@@ -0,0 +1,317 @@
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use zino::prelude::Model;
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::{Mutation, Query},
+    orm::{ModelAccessor, Schema}, warn, Map, Uuid,
+};
+use zino_model::User;
+
+use crate::{
+    app_config::SETTINGS,
+    model::{ChatWebsite, SiteInvitation, SiteMember},
+    utils::{generate_random_string, is_valid_email},
+};
+
+use super::{
+    mailer::{Mail, MAILER},
+    permission_service::{PermissionService, SiteRole},
+};
+
+pub struct InvitationService;
+
+/**
+ * 1.站点管理员通过邮件邀请客服，令牌有过期时间且只能使用一次
+ * 2.受邀人打开链接查看邀请，设置密码后创建账号（已有账号则直接加入）
+ * 3.接受邀请后按邀请角色加入站点成员
+ * 4.邀请列表与撤销
+ */
+
+impl InvitationService {
+    // 1
+    pub async fn invite(
+        site: &ChatWebsite,
+        inviter_id: &Uuid,
+        email: &str,
+        role: &str,
+    ) -> Result<SiteInvitation, Error> {
+        let email = email.trim().to_lowercase();
+        if !is_valid_email(&email) {
+            return Err(warn!("invalid email: {}", email));
+        }
+        match SiteRole::parse(role) {
+            Some(SiteRole::Owner) | None => return Err(warn!("unknown role: {}", role)),
+            _ => (),
+        }
+        // 同一邮箱只保留最新的邀请
+        let mut query = Query::from_entry("site_id", site.id.to_string());
+        query.add_filter("email", email.clone());
+        query.add_filter("status", "pending");
+        for mut old in SiteInvitation::find::<SiteInvitation>(&query).await? {
+            old.status = "revoked".to_owned();
+            old.update_at = DateTime::now();
+            old.update().await?;
+        }
+
+        let token = generate_random_string(40);
+        let mut invitation = SiteInvitation::default();
+        invitation.id = Uuid::now_v7();
+        invitation.site_id = site.id;
+        invitation.email = email.clone();
+        invitation.role = role.to_owned();
+        invitation.token_hash = hash_token(&token);
+        invitation.invited_by = *inviter_id;
+        invitation.status = "pending".to_owned();
+        invitation.expires_at =
+            DateTime::now() + Duration::from_secs(SETTINGS.invite_expire_hours * 3600);
+        invitation.clone().insert().await?;
+
+        let mail = Mail {
+            to: email,
+            subject: format!(
+                "邀请你加入 {} 客服团队",
+                site.title.clone().unwrap_or_default()
+            ),
+            body: format!(
+                "你被邀请以 {} 身份加入站点客服团队，请在 {} 小时内打开以下链接完成注册：\r\n\r\n{}?token={}\r\n",
+                role, SETTINGS.invite_expire_hours, &SETTINGS.invite_url, token
+            ),
+        };
+        // 邮件发送失败时邀请作废，令牌没有送达任何人
+        if let Err(e) = MAILER.send(&mail).await {
+            tracing::warn!("mail invitation {} error: {}", &invitation.id, e);
+            invitation.status = "failed".to_owned();
+            invitation.update_at = DateTime::now();
+            invitation.update().await?;
+            return Err(warn!("send invitation mail error: {}", e));
+        }
+        Ok(invitation)
+    }
+
+    // 2
+    pub async fn check(token: &str) -> Result<Map, Error> {
+        let invitation = Self::find_pending(token).await?;
+        let site = ChatWebsite::find_by_id::<ChatWebsite>(&invitation.site_id).await?;
+        let account = User::find_one::<User>(&Query::from_entry("account", invitation.email.clone()))
+            .await?;
+        let mut res = Map::new();
+        res.upsert("email", invitation.email);
+        res.upsert("role", invitation.role);
+        res.upsert("site_title", site.and_then(|s| s.title));
+        res.upsert("expires_at", invitation.expires_at);
+        res.upsert("has_account", account.is_some());
+        Ok(res)
+    }
+
+    // 2.1 / 3
+    pub async fn accept(
+        token: &str,
+        name: Option<String>,
+        password: Option<String>,
+    ) -> Result<SiteMember, Error> {
+        let invitation = Self::find_pending(token).await?;
+        let Some(site) = ChatWebsite::find_by_id::<ChatWebsite>(&invitation.site_id).await? else {
+            return Err(warn!("site not found"));
+        };
+        let account_query = Query::from_entry("account", invitation.email.clone());
+        let account = User::find_one::<User>(&account_query).await?;
+        let password = match &account {
+            Some(_) => None,
+            None => match password.filter(|p| p.len() >= 8) {
+                Some(password) => Some(password),
+                None => return Err(warn!("password should be at least 8 characters")),
+            },
+        };
+        // 先占用令牌，保证只能使用一次：只有状态仍为 pending 的那次请求能更新成功
+        // 之后任何一步失败都恢复为 pending，受邀人可以重试
+        Self::consume(&invitation).await?;
+        match Self::join_site(&invitation, &site, account, name, password).await {
+            Ok(member) => {
+                tracing::info!("invitation {} accepted by {}", &invitation.id, &member.user_id);
+                Ok(member)
+            }
+            Err(e) => {
+                Self::restore(&invitation).await?;
+                Err(e)
+            }
+        }
+    }
+
+    // 3 创建账号（已有账号则直接使用）并加入站点，记录接受邀请的账号
+    async fn join_site(
+        invitation: &SiteInvitation,
+        site: &ChatWebsite,
+        account: Option<User>,
+        name: Option<String>,
+        password: Option<String>,
+    ) -> Result<SiteMember, Error> {
+        let user_id = match account {
+            Some(user) => *user.id(),
+            None => Self::create_account(invitation, name, password.unwrap_or_default()).await?,
+        };
+        let member = PermissionService::save_member(site, &user_id, &invitation.role).await?;
+        let mut query = Query::from_entry("id", invitation.id.to_string());
+        query.add_filter("status", "accepted");
+        let mut updates = Map::new();
+        updates.upsert("accepted_user_id", user_id.to_string());
+        updates.upsert("update_at", DateTime::now());
+        SiteInvitation::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(member)
+    }
+
+    // 3.1 条件更新 status，影响行数为 0 说明已被并发的请求使用
+    async fn consume(invitation: &SiteInvitation) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", invitation.id.to_string());
+        query.add_filter("status", "pending");
+        let mut updates = Map::new();
+        updates.upsert("status", "accepted");
+        updates.upsert("accepted_at", DateTime::now());
+        updates.upsert("update_at", DateTime::now());
+        updates.upsert("version", invitation.version + 1);
+        let ctx = SiteInvitation::update_many(&query, &mut Mutation::new(updates)).await?;
+        if ctx.rows_affected() != Some(1) {
+            return Err(warn!("invitation not found or already used"));
+        }
+        Ok(())
+    }
+
+    // 3.2 接受邀请失败时恢复邀请
+    async fn restore(invitation: &SiteInvitation) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", invitation.id.to_string());
+        query.add_filter("status", "accepted");
+        let mut updates = Map::new();
+        updates.upsert("status", "pending");
+        updates.upsert("accepted_at", json!(null));
+        updates.upsert("accepted_user_id", json!(null));
+        updates.upsert("update_at", DateTime::now());
+        SiteInvitation::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(())
+    }
+
+    async fn create_account(
+        invitation: &SiteInvitation,
+        name: Option<String>,
+        password: String,
+    ) -> Result<Uuid, Error> {
+        let mut user = User::new();
+        let data = json!({
+            "name": name.unwrap_or(invitation.email.clone()),
+            "account": invitation.email.clone(),
+            "password": password,
+            "roles": ["worker"],
+        });
+        let validation = user.read_map(data.as_object().unwrap());
+        if !validation.is_success() {
+            return Err(warn!("invalid account data: {}", &invitation.email));
+        }
+        if !user.check_constraints().await?.is_success() {
+            return Err(warn!("account already exists: {}", &invitation.email));
+        }
+        let user_id = *user.id();
+        user.insert().await?;
+        Ok(user_id)
+    }
+
+    async fn find_pending(token: &str) -> Result<SiteInvitation, Error> {
+        let query = Query::from_entry("token_hash", hash_token(token));
+        let Some(invitation) = SiteInvitation::find_one::<SiteInvitation>(&query).await? else {
+            return Err(warn!("invitation not found"));
+        };
+        check_usable(&invitation, DateTime::now())?;
+        Ok(invitation)
+    }
+
+    // 4
+    pub async fn list_invitations(site_id: &Uuid) -> Result<Vec<SiteInvitation>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.order_by("create_at", true);
+        let mut invitations = SiteInvitation::find::<SiteInvitation>(&query).await?;
+        for invitation in invitations.iter_mut() {
+            invitation.token_hash = String::new();
+        }
+        Ok(invitations)
+    }
+
+    pub async fn revoke_invitation(site_id: &Uuid, invitation_id: &str) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", invitation_id);
+        query.add_filter("site_id", site_id.to_string());
+        query.add_filter("status", "pending");
+        let Some(mut invitation) = SiteInvitation::find_one::<SiteInvitation>(&query).await? else {
+            return Err(warn!("invitation not found"));
+        };
+        invitation.status = "revoked".to_owned();
+        invitation.update_at = DateTime::now();
+        invitation.update().await?;
+        Ok(())
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// 只有未过期的 pending 邀请可以使用
+fn check_usable(invitation: &SiteInvitation, now: DateTime) -> Result<(), Error> {
+    match invitation.status.as_str() {
+        "pending" if invitation.expires_at < now => Err(warn!("invitation expired")),
+        "pending" => Ok(()),
+        "accepted" => Err(warn!("invitation already used")),
+        "revoked" | "failed" => Err(warn!("invitation revoked")),
+        _ => Err(warn!("invitation not found")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invitation(status: &str, expires_in: i64) -> SiteInvitation {
+        let mut invitation = SiteInvitation::default();
+        invitation.status = status.to_owned();
+        let offset = Duration::from_secs(expires_in.unsigned_abs());
+        invitation.expires_at = if expires_in >= 0 {
+            DateTime::now() + offset
+        } else {
+            DateTime::now() - offset
+        };
+        invitation
+    }
+
+    fn rejection(invitation: &SiteInvitation) -> String {
+        check_usable(invitation, DateTime::now()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn it_accepts_pending_invitations() {
+        assert!(check_usable(&invitation("pending", 3600), DateTime::now()).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_expired_invitations() {
+        assert!(rejection(&invitation("pending", -1)).contains("expired"));
+    }
+
+    #[test]
+    fn it_rejects_revoked_invitations() {
+        assert!(rejection(&invitation("revoked", 3600)).contains("revoked"));
+        // 邮件没有发出的邀请同样作废
+        assert!(rejection(&invitation("failed", 3600)).contains("revoked"));
+    }
+
+    #[test]
+    fn it_rejects_used_invitations() {
+        assert!(rejection(&invitation("accepted", 3600)).contains("already used"));
+        // 已使用的邀请过期后仍提示已使用
+        assert!(rejection(&invitation("accepted", -1)).contains("already used"));
+    }
+
+    #[test]
+    fn it_hashes_tokens() {
+        let hash = hash_token("token");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token("token"));
+        assert_ne!(hash, hash_token("token2"));
+        assert!(!hash.contains("token"));
+    }
+}
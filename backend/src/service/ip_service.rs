@@ -1,30 +1,174 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
 use anyhow::anyhow;
-use zino::prelude::{Query, Schema};
+use zino::prelude::{DateTime, Query, Schema};
+
+use crate::{app_config::SETTINGS, model::ip_detail::IpDetail};
 
-use crate::model::ip_detail::IpDetail;
+use super::geoip_provider::{GeoLocation, GEOIP};
 
 pub struct IpService;
 
+/**
+ * 1.内网和保留地址不查询也不保存
+ * 2.优先使用本地缓存，超过 ip_detail_ttl_days 后重新查询
+ * 3.按配置顺序查询归属地，默认只用本地 mmdb 数据库
+ */
+
 impl IpService {
     pub async fn ip_detail(ip: &str) -> anyhow::Result<IpDetail> {
-        let query = Query::from_entry("ip", ip);
-        match IpDetail::find_one::<IpDetail>(&query).await {
-            Ok(resp) => {
-                if resp.is_some() {
-                    return Ok(resp.unwrap());
-                } else {
-                    let url = format!("https://ipinfo.io/{}/json", ip);
-                    let response: IpDetail = reqwest::get(&url).await?.json().await?;
-                    let rc = response.clone();
-                    match response.insert().await {
-                        Ok(_r) => {
-                            return Ok(rc);
-                        }
-                        Err(e) => Err(anyhow!("{e:#?}")),
-                    }
-                }
+        let addr = ip
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow!("invalid ip: {}", ip))?;
+        // 1
+        if Self::is_reserved(&addr) {
+            return Ok(IpDetail {
+                ip: addr.to_string(),
+                source: Some("reserved".to_owned()),
+                ..Default::default()
+            });
+        }
+        // 2
+        let query = Query::from_entry("ip", addr.to_string());
+        let cached = IpDetail::find_one::<IpDetail>(&query)
+            .await
+            .map_err(|e| anyhow!("{e:#?}"))?;
+        let ttl = Duration::from_secs(SETTINGS.ip_detail_ttl_days * 24 * 3600);
+        if let Some(detail) = &cached {
+            if detail.update_at > DateTime::now() - ttl {
+                return Ok(detail.clone());
+            }
+        }
+        // 3 查询不到时返回过期的缓存
+        let Some((source, location)) = Self::lookup(addr).await else {
+            return Ok(cached.unwrap_or_else(|| IpDetail {
+                ip: addr.to_string(),
+                ..Default::default()
+            }));
+        };
+        let mut detail = cached.clone().unwrap_or_default();
+        detail.ip = addr.to_string();
+        detail.city = location.city;
+        detail.region = location.region;
+        detail.country = location.country;
+        detail.loc = location.loc;
+        detail.org = location.org;
+        detail.source = Some(source.to_owned());
+        detail.update_at = DateTime::now();
+        let result = match cached {
+            Some(_) => detail.clone().update().await.map(|_| ()),
+            None => detail.clone().insert().await.map(|_| ()),
+        };
+        result.map_err(|e| anyhow!("{e:#?}"))?;
+        Ok(detail)
+    }
+
+    // 3.1 返回查到结果的数据源
+    async fn lookup(ip: IpAddr) -> Option<(&'static str, GeoLocation)> {
+        for provider in GEOIP.iter() {
+            match provider.lookup(ip).await {
+                Ok(Some(location)) => return Some((provider.name(), location)),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("geoip {} lookup {} error: {}", provider.name(), ip, e),
             }
-            Err(e) => Err(anyhow!("{e:#?}")),
+        }
+        None
+    }
+
+    // 1.1 内网、回环、链路本地、组播、文档示例、运营商 NAT 等地址
+    pub fn is_reserved(ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(v4) => Self::is_reserved_v4(v4),
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => Self::is_reserved_v4(&v4),
+                None => Self::is_reserved_v6(v6),
+            },
+        }
+    }
+
+    fn is_reserved_v4(ip: &Ipv4Addr) -> bool {
+        let [a, b, ..] = ip.octets();
+        ip.is_private()
+            || ip.is_loopback()
+            || ip.is_link_local()
+            || ip.is_broadcast()
+            || ip.is_documentation()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            || a == 0
+            // 100.64.0.0/10 运营商 NAT
+            || (a == 100 && (b & 0xc0) == 64)
+            // 198.18.0.0/15 基准测试
+            || (a == 198 && (b & 0xfe) == 18)
+            // 240.0.0.0/4 保留
+            || a >= 240
+    }
+
+    fn is_reserved_v6(ip: &Ipv6Addr) -> bool {
+        let first = ip.segments()[0];
+        ip.is_loopback()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            // fc00::/7 唯一本地地址
+            || (first & 0xfe00) == 0xfc00
+            // fe80::/10 链路本地
+            || (first & 0xffc0) == 0xfe80
+            // 2001:db8::/32 文档示例
+            || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_detects_reserved_addresses() {
+        let cases = [
+            // 运营商 NAT 100.64.0.0/10
+            ("100.64.0.1", true),
+            ("100.127.255.255", true),
+            ("100.63.255.255", false),
+            ("100.128.0.1", false),
+            // 基准测试 198.18.0.0/15
+            ("198.18.0.1", true),
+            ("198.19.255.255", true),
+            ("198.20.0.1", false),
+            // 内网、回环、链路本地、保留
+            ("10.0.0.1", true),
+            ("172.16.5.4", true),
+            ("192.168.1.1", true),
+            ("127.0.0.1", true),
+            ("169.254.1.1", true),
+            ("0.0.0.0", true),
+            ("240.0.0.1", true),
+            ("255.255.255.255", true),
+            ("192.0.2.1", true),
+            // IPv4 映射地址按 IPv4 判断
+            ("::ffff:10.0.0.1", true),
+            ("::ffff:100.64.0.1", true),
+            ("::ffff:8.8.8.8", false),
+            // IPv6
+            ("::1", true),
+            ("::", true),
+            ("fc00::1", true),
+            ("fd12:3456::1", true),
+            ("fe80::1", true),
+            ("ff02::1", true),
+            ("2001:db8::1", true),
+            // 公网地址
+            ("8.8.8.8", false),
+            ("1.1.1.1", false),
+            ("2606:4700::1111", false),
+            ("2001:4860:4860::8888", false),
+        ];
+        for (ip, reserved) in cases {
+            let addr = ip.parse::<IpAddr>().unwrap();
+            assert_eq!(IpService::is_reserved(&addr), reserved, "{}", ip);
         }
     }
 }
@@ -0,0 +1,222 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::{app_config::SETTINGS, utils::date_utils::current_ms};
+
+lazy_static! {
+    pub static ref MAILER: Box<dyn Mailer> = {
+        match SETTINGS.mail_transport.as_str() {
+            "smtp" => Box::new(SmtpMailer {
+                host: SETTINGS.smtp_host.clone(),
+                port: SETTINGS.smtp_port,
+                from: SETTINGS.mail_from.clone(),
+            }),
+            _ => Box::new(FileMailer {
+                dir: PathBuf::from(&SETTINGS.mail_dir),
+                from: SETTINGS.mail_from.clone(),
+            }),
+        }
+    };
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// 邮件发送，根据配置 `mail_transport` 选择具体实现
+pub trait Mailer: Send + Sync {
+    fn send<'a>(&'a self, mail: &'a Mail) -> BoxFuture<'a, Result<()>>;
+}
+
+/// 写入本地目录，便于本地开发和测试查看
+pub struct FileMailer {
+    pub dir: PathBuf,
+    pub from: String,
+}
+
+impl Mailer for FileMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            // 收件人由访客填写，文件名只用地址的哈希，避免路径穿越
+            let to_hash = hex::encode(Sha256::digest(mail.to.as_bytes()));
+            let path = self.dir.join(format!("{}_{}.eml", current_ms(), &to_hash[..16]));
+            tokio::fs::write(&path, format_message(&self.from, mail)).await?;
+            tracing::info!("mail to {} written to {:?}", &mail.to, &path);
+            Ok(())
+        })
+    }
+}
+
+/// 最简 SMTP 客户端（无认证、无 TLS），用于内网中继或测试替身
+pub struct SmtpMailer {
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+}
+
+impl SmtpMailer {
+    async fn command(
+        reader: &mut BufReader<TcpStream>,
+        line: Option<&str>,
+        expect: &str,
+    ) -> Result<()> {
+        if let Some(l) = line {
+            reader.get_mut().write_all(format!("{l}\r\n").as_bytes()).await?;
+        }
+        // 多行响应以 "xyz-" 开头，最后一行为 "xyz "
+        loop {
+            let mut resp = String::new();
+            if reader.read_line(&mut resp).await? == 0 {
+                return Err(anyhow!("smtp connection closed"));
+            }
+            if !resp.starts_with(expect) {
+                return Err(anyhow!("smtp unexpected reply: {}", resp.trim()));
+            }
+            if resp.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            let mut reader = BufReader::new(stream);
+            Self::command(&mut reader, None, "220").await?;
+            Self::command(&mut reader, Some("HELO rchat"), "250").await?;
+            Self::command(&mut reader, Some(&format!("MAIL FROM:<{}>", &self.from)), "250").await?;
+            Self::command(&mut reader, Some(&format!("RCPT TO:<{}>", &mail.to)), "250").await?;
+            Self::command(&mut reader, Some("DATA"), "354").await?;
+            // 以 "." 开头的行需要转义
+            let data = format_message(&self.from, mail)
+                .lines()
+                .map(|l| if l.starts_with('.') { format!(".{l}") } else { l.to_owned() })
+                .collect::<Vec<String>>()
+                .join("\r\n");
+            Self::command(&mut reader, Some(&format!("{data}\r\n.")), "250").await?;
+            Self::command(&mut reader, Some("QUIT"), "221").await?;
+            tracing::info!("mail to {} sent via smtp {}:{}", &mail.to, &self.host, self.port);
+            Ok(())
+        })
+    }
+}
+
+fn format_message(from: &str, mail: &Mail) -> String {
+    // 主题可能包含中文，按 RFC 2047 编码
+    let subject = general_purpose::STANDARD.encode(mail.subject.as_bytes());
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: =?UTF-8?B?{}?=\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+        from, &mail.to, subject, &mail.body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn mail(to: &str) -> Mail {
+        Mail {
+            to: to.to_owned(),
+            subject: "邀请你加入客服团队".to_owned(),
+            body: "hello\r\n.dot line\r\nbye".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_writes_mails_inside_the_dir() {
+        let dir = std::env::temp_dir().join(format!("rchat-mails-{}", current_ms()));
+        let mailer = FileMailer {
+            dir: dir.clone(),
+            from: "rchat@localhost".to_owned(),
+        };
+        mailer.send(&mail("../../evil@example.com")).await.unwrap();
+
+        let entries = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].parent(), Some(dir.as_path()));
+        let content = std::fs::read_to_string(&entries[0]).unwrap();
+        assert!(content.contains("To: ../../evil@example.com\r\n"));
+        assert!(content.contains("Subject: =?UTF-8?B?"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // SMTP 替身：按顺序应答并记录收到的命令和邮件内容
+    async fn smtp_stand_in() -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = Vec::new();
+            reader.get_mut().write_all(b"220 stand-in\r\n").await.unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_owned();
+                let reply: &[u8] = if in_data {
+                    if line == "." {
+                        in_data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    b"221 bye\r\n"
+                } else if line.starts_with("HELO") {
+                    b"250-stand-in\r\n250 OK\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+                lines.push(line.clone());
+                reader.get_mut().write_all(reply).await.unwrap();
+                if line == "QUIT" {
+                    break;
+                }
+            }
+            lines
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn it_sends_through_smtp() {
+        let (port, handle) = smtp_stand_in().await;
+        let mailer = SmtpMailer {
+            host: "127.0.0.1".to_owned(),
+            port,
+            from: "rchat@localhost".to_owned(),
+        };
+        mailer.send(&mail("agent@example.com")).await.unwrap();
+
+        let lines = handle.await.unwrap();
+        assert!(lines.contains(&"MAIL FROM:<rchat@localhost>".to_owned()));
+        assert!(lines.contains(&"RCPT TO:<agent@example.com>".to_owned()));
+        // 以 "." 开头的行需要转义
+        assert!(lines.contains(&"..dot line".to_owned()));
+        assert_eq!(lines.last().map(|l| l.as_str()), Some("QUIT"));
+    }
+}
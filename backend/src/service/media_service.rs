@@ -0,0 +1,612 @@
+use std::{collections::HashMap, time::Duration};
+
+use zino::prelude::{AccessKeyId, NamedFile, SecretAccessKey, SecurityToken};
+
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::{Mutation, Query},
+    orm::Schema,
+    warn, Map, Uuid,
+};
+
+use crate::{
+    app_config::SETTINGS,
+    model::{chat_files::ChatFiles, ChatMedia, ChatMessage, ChatRoom, ChatWebsite},
+    utils::{image_utils, storage_utils::STORAGE},
+};
+
+use super::{
+    attachment_scanner::{ScanVerdict, SCANNER},
+    upload_service::UploadService,
+};
+
+// 单条消息最多引用的附件数，与 ChatMessage.file_ids 的 max_items 一致
+const MAX_FILES: usize = 5;
+// 迁移、清理时每批处理的记录数
+const MIGRATE_BATCH: usize = 200;
+// 隔离区 key 前缀
+const QUARANTINE_PREFIX: &str = "/quarantine";
+// 上传后超过该时长仍未扫描的附件由定时任务重试
+const SCAN_RETRY_SECS: u64 = 300;
+
+pub struct MediaService;
+
+/**
+ * 1.上传后保存附件记录：存储 key、大小、类型、上传人、房间
+ * 2.消息只保存附件 id，发送前校验附件属于当前房间
+ * 3.历史消息和实时推送按 id 展开为附件对象，链接在展示时签名
+ * 4.迁移旧消息的 str_files
+ * 5.删除附件：删除存储中的文件和附件记录，引用它的消息标记为删除
+ * 6.定时清理上传后一直没有发送的附件
+ * 7.图片上传时先去掉 EXIF 等元数据再写入存储，之后异步生成缩略图
+ * 8.上传后扫描病毒，感染的文件移到隔离区，不再提供下载，扫描失败的由定时任务重试
+ * 9.站点开启加密时附件加密后再写入存储，下载时用带有效期的 SecurityToken 解密
+ */
+
+impl MediaService {
+    // 1
+    pub async fn create(
+        room: &ChatRoom,
+        uploader_type: &str,
+        uploader_id: Option<Uuid>,
+        file_name: &str,
+        key: &str,
+        file_type: &str,
+        file_size: i64,
+        key_id: Option<String>,
+    ) -> Result<ChatMedia, Error> {
+        let mut media = ChatMedia::default();
+        media.id = Uuid::now_v7();
+        media.status = "uploaded".to_owned();
+        media.site_id = room.room_site_id;
+        media.room_id = room.id;
+        media.uploader_type = uploader_type.to_owned();
+        media.uploader_id = uploader_id;
+        media.file_name = file_name.to_owned();
+        media.path = key.to_owned();
+        media.file_type = file_type.to_owned();
+        media.file_size = file_size;
+        media.scan_status = "pending".to_owned();
+        media.key_id = key_id;
+        media.create_at = DateTime::now();
+        media.update_at = DateTime::now();
+        media.clone().insert().await?;
+        Ok(media)
+    }
+
+    // 2 去掉不属于当前房间和已隔离的附件 id，并展开附件
+    pub async fn attach(room: &ChatRoom, message: &mut ChatMessage) -> Result<(), Error> {
+        message.file_ids.truncate(MAX_FILES);
+        let medias = Self::find_medias(&message.file_ids).await?;
+        message.file_ids.retain(|id| {
+            medias
+                .get(id)
+                .is_some_and(|m| m.room_id == room.id && m.scan_status != "infected")
+        });
+        message.files = Self::to_files(&message.file_ids, &medias);
+        for media in medias.values() {
+            if message.file_ids.contains(&media.id) {
+                Self::mark_attached(media).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // 2.1 发送过的附件不再被清理，只更新 status，不覆盖扫描和缩略图的结果
+    async fn mark_attached(media: &ChatMedia) -> Result<(), Error> {
+        if media.status == "attached" {
+            return Ok(());
+        }
+        let mut query = Query::from_entry("id", media.id.to_string());
+        query.add_filter("status", "uploaded");
+        let mut updates = Map::new();
+        updates.upsert("status", "attached");
+        updates.upsert("update_at", DateTime::now());
+        ChatMedia::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(())
+    }
+
+    // 3 一次查出房间内所有消息的附件
+    pub async fn expand(room: &ChatRoom, messages: &mut [ChatMessage]) -> Result<(), Error> {
+        let ids = messages
+            .iter()
+            .flat_map(|m| m.file_ids.iter().copied())
+            .collect::<Vec<Uuid>>();
+        let medias = Self::find_medias(&ids).await?;
+        for message in messages.iter_mut() {
+            message.files = Self::to_files(&message.file_ids, &medias);
+        }
+        Ok(())
+    }
+
+    async fn find_medias(ids: &[Uuid]) -> Result<HashMap<Uuid, ChatMedia>, Error> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let ids = ids.iter().map(|id| id.to_string()).collect::<Vec<String>>();
+        let query = Query::from_entry("id", json!({"$in": ids}));
+        let medias = ChatMedia::find::<ChatMedia>(&query).await?;
+        Ok(medias.into_iter().map(|m| (m.id, m)).collect())
+    }
+
+    // 3.1 按 file_ids 的顺序展开
+    fn to_files(ids: &[Uuid], medias: &HashMap<Uuid, ChatMedia>) -> Vec<ChatFiles> {
+        ids.iter()
+            .filter_map(|id| medias.get(id))
+            .map(Self::to_file)
+            .collect()
+    }
+
+    // 已隔离的附件不返回链接
+    pub fn to_file(media: &ChatMedia) -> ChatFiles {
+        let url = match media.scan_status.as_str() {
+            "infected" => String::new(),
+            _ => Self::link(&media.id, None),
+        };
+        ChatFiles {
+            id: media.id,
+            file_key: media.path.clone(),
+            url,
+            file_name: media.file_name.clone(),
+            file_type: media.file_type.clone(),
+            file_size: media.file_size,
+            width: media.width,
+            height: media.height,
+            thumb_url: media
+                .thumb_key
+                .as_ref()
+                .map(|_| Self::link(&media.id, Some("thumb"))),
+            preview_url: media
+                .preview_key
+                .as_ref()
+                .map(|_| Self::link(&media.id, Some("preview"))),
+            scan_status: media.scan_status.clone(),
+        }
+    }
+
+    // 3.2 附件链接不带任何凭据，可以保存和推送，访问时按调用方的会话授权后跳转到短期签名链接
+    // size: thumb preview，为空时是原图
+    pub fn link(media_id: &Uuid, size: Option<&str>) -> String {
+        let link = format!("/load/file/{}", media_id);
+        match size {
+            Some(size) => format!("{}?size={}", link, size),
+            None => link,
+        }
+    }
+
+    // 3.3 访客只能访问所在房间的附件
+    pub async fn in_room(media: &ChatMedia, room_key: &str) -> Result<bool, Error> {
+        let query = Query::from_entry("room_key", room_key);
+        let room = ChatRoom::find_one::<ChatRoom>(&query).await?;
+        Ok(room.is_some_and(|r| r.id == media.room_id))
+    }
+
+    // 3.4 签发短期链接，调用方负责授权
+    // 缩略图还没有生成时返回原图，已隔离和启用扫描时还没扫描的附件不提供下载
+    pub async fn signed_url(media: &ChatMedia, size: Option<&str>) -> Result<Option<String>, Error> {
+        if !Self::downloadable(media) {
+            return Ok(None);
+        }
+        if media.key_id.is_some() {
+            return Self::decrypt_link(media, size).map(Some);
+        }
+        let key = Self::size_key(media, size);
+        let url = STORAGE
+            .generate_signed_url(key, SETTINGS.storage_url_expire_secs)
+            .await
+            .map_err(|e| warn!("sign file url {} error: {}", key, e))?;
+        Ok(Some(url))
+    }
+
+    // 4 旧消息的 str_files 转为附件记录，可以重复执行，返回迁移的消息数
+    pub async fn migrate_str_files() -> Result<usize, Error> {
+        let mut rooms: HashMap<Uuid, Option<ChatRoom>> = HashMap::new();
+        let mut last_id: Option<Uuid> = None;
+        let mut migrated = 0;
+        loop {
+            let mut query = Query::from_entry("str_files", json!({"$ne": ""}));
+            if let Some(last_id) = last_id {
+                query.add_filter("id", json!({"$gt": last_id.to_string()}));
+            }
+            query.order_asc("id");
+            query.set_limit(MIGRATE_BATCH);
+            let messages = ChatMessage::find::<ChatMessage>(&query).await?;
+            let Some(last) = messages.last() else {
+                break;
+            };
+            last_id = Some(last.id);
+            for mut message in messages {
+                if !rooms.contains_key(&message.room_id) {
+                    let room = ChatRoom::find_by_id::<ChatRoom>(&message.room_id).await?;
+                    rooms.insert(message.room_id, room);
+                }
+                let Some(Some(room)) = rooms.get(&message.room_id) else {
+                    continue;
+                };
+                match Self::migrate_message(room, &mut message).await {
+                    Ok(()) => migrated += 1,
+                    Err(e) => tracing::warn!("migrate files of message {} error: {}", message.id, e),
+                }
+            }
+        }
+        Ok(migrated)
+    }
+
+    // 4.1 旧格式: [{"url", "file_key", "file_name", ...}]
+    async fn migrate_message(room: &ChatRoom, message: &mut ChatMessage) -> Result<(), Error> {
+        let str_files = message.str_files.clone().unwrap_or_default();
+        let legacy_files = serde_json::from_str::<Vec<Map>>(&str_files)
+            .map_err(|e| warn!("invalid str_files: {}", e))?;
+        let mut file_ids = message.file_ids.clone();
+        for file in legacy_files {
+            let Some(key) = file.get_str("file_key").filter(|k| !k.is_empty()) else {
+                continue;
+            };
+            let media = match ChatMedia::find_one::<ChatMedia>(&Query::from_entry("path", key)).await? {
+                Some(media) => media,
+                None => {
+                    let file_size = STORAGE.size(key).await.unwrap_or(0) as i64;
+                    let file_type = mime_guess::from_path(key).first_or_octet_stream().to_string();
+                    let file_name = file.get_str("file_name").unwrap_or(key);
+                    let media = Self::create(
+                        room,
+                        &message.sender_type,
+                        message.user_id,
+                        file_name,
+                        key,
+                        &file_type,
+                        file_size,
+                        None,
+                    )
+                    .await?;
+                    // 有用量记录之后上传的文件在上传时已经计入，不再重复计入
+                    let counted_since = UploadService::counted_since(&room.room_site_id, &room.id).await?;
+                    if counted_since.map_or(true, |since| message.create_at < since) {
+                        UploadService::record_usage(&room.room_site_id, &room.id, file_size, 1).await?;
+                    }
+                    media
+                }
+            };
+            if !file_ids.contains(&media.id) && file_ids.len() < MAX_FILES {
+                file_ids.push(media.id);
+                Self::mark_attached(&media).await?;
+            }
+        }
+        message.file_ids = file_ids;
+        message.str_files = None;
+        message.update_at = DateTime::now();
+        message.clone().update().await?;
+        Ok(())
+    }
+
+    // 5 按附件 id 或存储 key 查找
+    pub async fn find(media_id: Option<&str>, file_key: Option<&str>) -> Result<Option<ChatMedia>, Error> {
+        let query = match (media_id, file_key) {
+            (Some(id), _) => Query::from_entry("id", id),
+            (None, Some(key)) => Query::from_entry("path", key),
+            (None, None) => return Ok(None),
+        };
+        ChatMedia::find_one::<ChatMedia>(&query).await
+    }
+
+    // 5.1 返回标记为删除的消息数
+    pub async fn delete(media: &ChatMedia) -> Result<usize, Error> {
+        for key in [Some(&media.path), media.thumb_key.as_ref(), media.preview_key.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            STORAGE
+                .delete(key)
+                .await
+                .map_err(|e| warn!("delete file {} error: {}", key, e))?;
+        }
+        let tombstoned = Self::tombstone_messages(media).await?;
+        UploadService::record_usage(&media.site_id, &media.room_id, -media.file_size, -1).await?;
+        ChatMedia::delete_many(&Query::from_entry("id", media.id.to_string())).await?;
+        Ok(tombstoned)
+    }
+
+    // 5.2 引用附件的消息都在上传之后发送，只查比附件 id 新的消息
+    async fn tombstone_messages(media: &ChatMedia) -> Result<usize, Error> {
+        if media.status != "attached" {
+            return Ok(0);
+        }
+        let mut tombstoned = 0;
+        for mut message in Self::referencing_messages(media).await? {
+            message.file_ids.retain(|id| *id != media.id);
+            message.status = "delete".to_owned();
+            message.update_at = DateTime::now();
+            message.update().await?;
+            tombstoned += 1;
+        }
+        Ok(tombstoned)
+    }
+
+    async fn referencing_messages(media: &ChatMedia) -> Result<Vec<ChatMessage>, Error> {
+        let mut query = Query::from_entry("room_id", media.room_id.to_string());
+        query.add_filter("id", json!({"$gt": media.id.to_string()}));
+        query.add_filter("status", json!({"$ne": "delete"}));
+        let messages = ChatMessage::find::<ChatMessage>(&query).await?;
+        Ok(messages
+            .into_iter()
+            .filter(|m| m.file_ids.contains(&media.id))
+            .collect())
+    }
+
+    // 6 返回清理的附件数，隔离区的文件保留，由客服手动删除
+    pub async fn gc_orphans() -> Result<usize, Error> {
+        let cutoff = DateTime::now() - Duration::from_secs(SETTINGS.orphan_upload_ttl_hours * 3600);
+        let mut query = Query::from_entry("status", "uploaded");
+        query.add_filter("scan_status", json!({"$ne": "infected"}));
+        query.add_filter("create_at", json!({"$lt": cutoff}));
+        query.order_asc("id");
+        query.set_limit(MIGRATE_BATCH);
+        let mut removed = 0;
+        for media in ChatMedia::find::<ChatMedia>(&query).await? {
+            match Self::delete(&media).await {
+                Ok(_) => removed += 1,
+                Err(e) => tracing::warn!("remove orphan upload {} error: {}", media.id, e),
+            }
+        }
+        Ok(removed)
+    }
+
+    // 7 在写入存储之前调用，不能解码的图片不接收
+    pub async fn strip_metadata(bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        tokio::task::spawn_blocking(move || image_utils::strip_metadata(&bytes))
+            .await
+            .map_err(|e| warn!("strip metadata task error: {}", e))?
+            .map_err(|e| warn!("strip image metadata error: {}", e))
+    }
+
+    // 7.1 生成缩略图并更新附件记录，返回更新后的附件
+    // 消息只保存附件 id，展开时按附件记录生成链接，不需要更新消息
+    async fn generate_thumbnails(mut media: ChatMedia) -> Result<ChatMedia, Error> {
+        let bytes = Self::read_file(&media, &media.path).await?;
+        let processed = tokio::task::spawn_blocking(move || image_utils::process_image(&bytes))
+            .await
+            .map_err(|e| warn!("thumbnail task error: {}", e))?
+            .map_err(|e| warn!("process image error: {}", e))?;
+        // 缩略图放在原图旁边：{原图 key 去掉扩展名}_{规格}.{扩展名}
+        let stem = media
+            .path
+            .rsplit_once('.')
+            .map_or(media.path.as_str(), |(stem, _)| stem)
+            .to_owned();
+        for (name, bytes, ext) in &processed.variants {
+            let key = format!("{}_{}.{}", stem, name, ext);
+            Self::write_file(&media, &key, bytes).await?;
+            match *name {
+                "thumb" => media.thumb_key = Some(key),
+                _ => media.preview_key = Some(key),
+            }
+        }
+        media.width = Some(processed.width as i32);
+        media.height = Some(processed.height as i32);
+        media.update_at = DateTime::now();
+        // 只更新缩略图相关的字段，发送消息时可能已并发修改了 status
+        let query = Query::from_entry("id", media.id.to_string());
+        let mut updates = Map::new();
+        updates.upsert("thumb_key", media.thumb_key.clone());
+        updates.upsert("preview_key", media.preview_key.clone());
+        updates.upsert("width", media.width);
+        updates.upsert("height", media.height);
+        updates.upsert("update_at", media.update_at);
+        ChatMedia::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(media)
+    }
+
+    // 8 未启用扫描时 pending 的附件也可以下载
+    pub fn downloadable(media: &ChatMedia) -> bool {
+        match media.scan_status.as_str() {
+            "infected" => false,
+            "clean" => true,
+            _ => SCANNER.name() == "none",
+        }
+    }
+
+    // 8.1 扫描后再生成缩略图，返回更新后的附件
+    pub async fn process_upload(media: ChatMedia) -> Result<ChatMedia, Error> {
+        let media = Self::scan(media).await?;
+        if media.scan_status == "clean"
+            && media.file_type.starts_with("image/")
+            && media.thumb_key.is_none()
+        {
+            return Self::generate_thumbnails(media).await;
+        }
+        Ok(media)
+    }
+
+    // 8.2 扫描存储中的文件，先占用再扫描，已被其他实例占用时直接返回
+    // 扫描失败时保持 scanning，超过 SCAN_RETRY_SECS 后由定时任务重新占用
+    async fn scan(mut media: ChatMedia) -> Result<ChatMedia, Error> {
+        if !matches!(media.scan_status.as_str(), "pending" | "scanning") {
+            return Ok(media);
+        }
+        if !Self::claim_scan(&media).await? {
+            return Ok(media);
+        }
+        let bytes = Self::read_file(&media, &media.path).await?;
+        let verdict = SCANNER
+            .scan(&bytes)
+            .await
+            .map_err(|e| warn!("scan file {} by {} error: {}", media.path, SCANNER.name(), e))?;
+        match verdict {
+            ScanVerdict::Clean => media.scan_status = "clean".to_owned(),
+            ScanVerdict::Infected(signature) => {
+                tracing::warn!("file {} infected: {}", media.id, signature);
+                Self::quarantine(&mut media, &bytes).await?;
+                media.scan_status = "infected".to_owned();
+                media.scan_result = Some(signature);
+            }
+        }
+        Self::save_scan_result(&media).await?;
+        Ok(media)
+    }
+
+    // 8.2.1 pending 或超时的 scanning 改为 scanning，占用成功返回 true
+    async fn claim_scan(media: &ChatMedia) -> Result<bool, Error> {
+        let sql = format!(
+            "UPDATE {table} SET scan_status = 'scanning', update_at = now(), version = version + 1 \
+            WHERE id = #{{id}} AND (scan_status = 'pending' \
+                OR (scan_status = 'scanning' AND update_at < now() - make_interval(secs => #{{secs}})))",
+            table = ChatMedia::table_name(),
+        );
+        let mut params = Map::new();
+        params.upsert("id", media.id.to_string());
+        params.upsert("secs", SCAN_RETRY_SECS);
+        let ctx = ChatMedia::execute(&sql, Some(&params)).await?;
+        Ok(ctx.rows_affected() == Some(1))
+    }
+
+    // 8.2.2 只更新扫描相关的字段，不覆盖发送消息时改的状态
+    async fn save_scan_result(media: &ChatMedia) -> Result<(), Error> {
+        let sql = format!(
+            "UPDATE {table} SET scan_status = #{{scan_status}}, scan_result = #{{scan_result}}, \
+                path = #{{path}}, thumb_key = #{{thumb_key}}, preview_key = #{{preview_key}}, \
+                update_at = now(), version = version + 1 \
+            WHERE id = #{{id}} AND scan_status = 'scanning'",
+            table = ChatMedia::table_name(),
+        );
+        let mut params = Map::new();
+        params.upsert("id", media.id.to_string());
+        params.upsert("scan_status", media.scan_status.as_str());
+        params.upsert("scan_result", media.scan_result.clone());
+        params.upsert("path", media.path.as_str());
+        params.upsert("thumb_key", media.thumb_key.clone());
+        params.upsert("preview_key", media.preview_key.clone());
+        let ctx = ChatMedia::execute(&sql, Some(&params)).await?;
+        if ctx.rows_affected() != Some(1) {
+            return Err(warn!("scan result of file {} is not saved", media.id));
+        }
+        Ok(())
+    }
+
+    // 8.3 文件移到隔离区，缩略图直接删除
+    async fn quarantine(media: &mut ChatMedia, bytes: &[u8]) -> Result<(), Error> {
+        let key = format!("{}{}", QUARANTINE_PREFIX, media.path);
+        Self::write_file(media, &key, bytes).await?;
+        for old in [Some(media.path.clone()), media.thumb_key.take(), media.preview_key.take()]
+            .into_iter()
+            .flatten()
+        {
+            STORAGE
+                .delete(&old)
+                .await
+                .map_err(|e| warn!("delete file {} error: {}", old, e))?;
+        }
+        media.path = key;
+        Ok(())
+    }
+
+    // 8.4 重试扫描失败的附件，返回处理的附件数
+    // 多个实例同时执行时，每个附件只有占用成功的实例会扫描
+    pub async fn scan_pending() -> Result<usize, Error> {
+        let cutoff = DateTime::now() - Duration::from_secs(SCAN_RETRY_SECS);
+        let mut query = Query::from_entry("scan_status", json!({"$in": ["pending", "scanning"]}));
+        query.add_filter("create_at", json!({"$lt": cutoff}));
+        query.add_filter("update_at", json!({"$lt": cutoff}));
+        query.order_asc("id");
+        query.set_limit(MIGRATE_BATCH);
+        let mut scanned = 0;
+        for media in ChatMedia::find::<ChatMedia>(&query).await? {
+            let media_id = media.id;
+            match Self::process_upload(media).await {
+                Ok(_) => scanned += 1,
+                Err(e) => tracing::warn!("rescan file {} error: {}", media_id, e),
+            }
+        }
+        Ok(scanned)
+    }
+
+    // 9 站点开启加密时返回密文和密钥 id
+    pub fn seal(site: &ChatWebsite, bytes: &[u8]) -> Result<(Vec<u8>, Option<String>), Error> {
+        match (site.encrypt_attachments, &site.attachment_key_id) {
+            (Some(true), Some(key_id)) => Ok((Self::encrypt(key_id, bytes)?, Some(key_id.clone()))),
+            _ => Ok((bytes.to_vec(), None)),
+        }
+    }
+
+    // 9.1 密钥由密钥 id 和应用密钥派生，不保存在数据库
+    pub fn secret_key(key_id: &str) -> SecretAccessKey {
+        SecretAccessKey::new(&AccessKeyId::from(key_id.to_owned()))
+    }
+
+    fn encrypt(key_id: &str, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut file = NamedFile::new("attachment");
+        file.set_bytes(bytes.to_vec());
+        file.encrypt_with(Self::secret_key(key_id).as_ref())?;
+        Ok(file.bytes().to_vec())
+    }
+
+    fn decrypt(key_id: &str, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut file = NamedFile::new("attachment");
+        file.set_bytes(bytes.to_vec());
+        file.decrypt_with(Self::secret_key(key_id))?;
+        Ok(file.bytes().to_vec())
+    }
+
+    // 9.2 读写附件及其缩略图，加密的附件自动加解密
+    async fn read_file(media: &ChatMedia, key: &str) -> Result<Vec<u8>, Error> {
+        let bytes = STORAGE
+            .read(key)
+            .await
+            .map_err(|e| warn!("read file {} error: {}", key, e))?;
+        match &media.key_id {
+            Some(key_id) => Self::decrypt(key_id, &bytes),
+            None => Ok(bytes),
+        }
+    }
+
+    async fn write_file(media: &ChatMedia, key: &str, bytes: &[u8]) -> Result<(), Error> {
+        let bytes = match &media.key_id {
+            Some(key_id) => Self::encrypt(key_id, bytes)?,
+            None => bytes.to_vec(),
+        };
+        STORAGE
+            .upload_file_bytes(key, &bytes)
+            .await
+            .map_err(|e| warn!("upload file {} error: {}", key, e))
+    }
+
+    fn size_key<'a>(media: &'a ChatMedia, size: Option<&str>) -> &'a str {
+        match size {
+            Some("thumb") => media.thumb_key.as_deref(),
+            Some("preview") => media.preview_key.as_deref(),
+            _ => None,
+        }
+        .unwrap_or(&media.path)
+    }
+
+    // 9.3 解密链接：access_key_id 为附件 id，令牌用站点密钥签发，只能访问这一个附件
+    fn decrypt_link(media: &ChatMedia, size: Option<&str>) -> Result<String, Error> {
+        let Some(key_id) = &media.key_id else {
+            return Err(warn!("file {} is not encrypted", media.id));
+        };
+        let expires = DateTime::now() + Duration::from_secs(SETTINGS.storage_url_expire_secs);
+        let access_key_id = AccessKeyId::from(media.id.to_string());
+        let security_token =
+            SecurityToken::try_new(access_key_id.clone(), expires, &Self::secret_key(key_id))?;
+        let mut query = Map::new();
+        query.upsert("access_key_id", access_key_id.to_string());
+        query.upsert("security_token", security_token.to_string());
+        if let Some(size) = size {
+            query.upsert("size", size);
+        }
+        Ok(format!(
+            "{}/pub/file/decrypt?{}",
+            SETTINGS.storage_url_base.trim_end_matches('/'),
+            query.to_query_string()
+        ))
+    }
+
+    // 9.4 解密后的文件，类型按存储 key 的扩展名
+    pub async fn decrypted_file(media: &ChatMedia, size: Option<&str>) -> Result<NamedFile, Error> {
+        let key = Self::size_key(media, size);
+        let bytes = Self::read_file(media, key).await?;
+        let file_name = key.rsplit('/').next().unwrap_or(key);
+        let mut file = NamedFile::new(file_name);
+        file.set_bytes(bytes);
+        file.set_content_type(mime_guess::from_path(key).first_or_octet_stream());
+        Ok(file)
+    }
+}
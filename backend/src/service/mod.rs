@@ -1,5 +1,20 @@
+pub mod analytics_service;
+pub mod api_key_service;
+pub mod attachment_scanner;
+pub mod audit_service;
 
+pub mod auto_reply_service;
+pub mod chat_bot;
 pub mod chat_service;
+pub mod geoip_provider;
 pub mod room_message_state;
 // pub(crate)
-pub mod ip_service;
\ No newline at end of file
+pub mod invitation_service;
+pub mod ip_service;
+pub mod mailer;
+pub mod media_service;
+pub mod permission_service;
+pub mod read_cursor_service;
+pub mod ticket_service;
+pub mod upload_service;
+pub mod webhook_service;
@@ -0,0 +1,193 @@
+use zino_core::{
+    datetime::DateTime,
+    error::Error,
+    extension::JsonObjectExt,
+    json,
+    model::Query,
+    orm::{ModelAccessor, Schema},
+    warn, Map, Uuid,
+};
+use zino_model::User;
+
+use crate::model::{ChatRoom, ChatWebsite, SiteMember};
+
+/// 站点角色，权限从低到高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SiteRole {
+    Viewer,
+    Agent,
+    Admin,
+    Owner,
+}
+
+impl SiteRole {
+    pub fn parse(role: &str) -> Option<SiteRole> {
+        match role {
+            "viewer" => Some(SiteRole::Viewer),
+            "agent" => Some(SiteRole::Agent),
+            "admin" => Some(SiteRole::Admin),
+            "owner" => Some(SiteRole::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SiteRole::Viewer => "viewer",
+            SiteRole::Agent => "agent",
+            SiteRole::Admin => "admin",
+            SiteRole::Owner => "owner",
+        }
+    }
+}
+
+pub struct PermissionService;
+
+/**
+ * 1.站点创建者默认为 owner，其他成员按角色授权
+ * 2.客服后台接口和客服 WebSocket 连接都通过 check 校验站点权限
+ * 3.成员管理，owner 不能被修改或移除
+ */
+
+impl PermissionService {
+    // 1
+    pub async fn role_of(user_id: &Uuid, site: &ChatWebsite) -> Result<Option<SiteRole>, Error> {
+        if site.user_id == *user_id {
+            return Ok(Some(SiteRole::Owner));
+        }
+        let mut query = Query::from_entry("site_id", site.id.to_string());
+        query.add_filter("user_id", user_id.to_string());
+        query.add_filter("status", "active");
+        let member = SiteMember::find_one::<SiteMember>(&query).await?;
+        Ok(member.and_then(|m| SiteRole::parse(&m.role)))
+    }
+
+    // 2
+    pub async fn check(
+        user_id: &Uuid,
+        site_id: &str,
+        required: SiteRole,
+    ) -> Result<(ChatWebsite, SiteRole), Error> {
+        let query = Query::from_entry("id", site_id);
+        let Some(site) = ChatWebsite::find_one::<ChatWebsite>(&query).await? else {
+            return Err(warn!("room site not found"));
+        };
+        match Self::role_of(user_id, &site).await? {
+            Some(role) if role >= required => Ok((site, role)),
+            Some(_) => Err(warn!("403 Forbidden: {} role required", required.as_str())),
+            None => Err(warn!("room site not found")),
+        }
+    }
+
+    // 2.1 WebSocket 连接只有 site_key
+    pub async fn check_by_key(
+        user_id: &Uuid,
+        site_key: &str,
+        required: SiteRole,
+    ) -> Result<(ChatWebsite, SiteRole), Error> {
+        let query = Query::from_entry("site_key", site_key);
+        let Some(site) = ChatWebsite::find_one::<ChatWebsite>(&query).await? else {
+            return Err(warn!("room site not found"));
+        };
+        Self::check(user_id, &site.id.to_string(), required).await
+    }
+
+    // 2.2 客服切换房间时，房间必须属于连接的站点
+    pub async fn check_room(
+        user_id: &Uuid,
+        site_key: &str,
+        room_id: &Uuid,
+        required: SiteRole,
+    ) -> Result<ChatRoom, Error> {
+        let (site, _) = Self::check_by_key(user_id, site_key, required).await?;
+        match ChatRoom::find_by_id::<ChatRoom>(room_id).await? {
+            Some(room) if room.room_site_id == site.id => Ok(room),
+            _ => Err(warn!("room not found")),
+        }
+    }
+
+    // 1.1 用户作为成员加入的站点
+    pub async fn member_site_ids(user_id: &Uuid) -> Result<Vec<Uuid>, Error> {
+        let mut query = Query::from_entry("user_id", user_id.to_string());
+        query.add_filter("status", "active");
+        let members = SiteMember::find::<SiteMember>(&query).await?;
+        Ok(members.into_iter().map(|m| m.site_id).collect())
+    }
+
+    // 3
+    pub async fn list_members(site_id: &Uuid) -> Result<Vec<SiteMember>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.add_filter("status", "active");
+        query.order_asc("create_at");
+        Ok(SiteMember::find::<SiteMember>(&query).await?)
+    }
+
+    // 3.1 客服名单，包括站点创建者
+    pub async fn list_roster(site: &ChatWebsite) -> Result<Vec<Map>, Error> {
+        let mut roster = Vec::new();
+        if let Some(owner) = User::find_by_id::<User>(&site.user_id).await? {
+            let mut entry = owner.snapshot();
+            entry.upsert("role", SiteRole::Owner.as_str());
+            roster.push(entry);
+        }
+        for member in Self::list_members(&site.id).await? {
+            if let Some(user) = User::find_by_id::<User>(&member.user_id).await? {
+                let mut entry = user.snapshot();
+                entry.upsert("role", member.role);
+                entry.upsert("joined_at", member.create_at);
+                roster.push(entry);
+            }
+        }
+        Ok(roster)
+    }
+
+    pub async fn save_member(
+        site: &ChatWebsite,
+        user_id: &Uuid,
+        role: &str,
+    ) -> Result<SiteMember, Error> {
+        let Some(site_role) = SiteRole::parse(role) else {
+            return Err(warn!("unknown role: {}", role));
+        };
+        if site_role == SiteRole::Owner || site.user_id == *user_id {
+            return Err(warn!("403 Forbidden: site owner can not be changed"));
+        }
+        if User::find_by_id::<User>(user_id).await?.is_none() {
+            return Err(warn!("user not found"));
+        }
+        let mut query = Query::from_entry("site_id", site.id.to_string());
+        query.add_filter("user_id", user_id.to_string());
+        if let Some(mut member) = SiteMember::find_one::<SiteMember>(&query).await? {
+            member.role = site_role.as_str().to_owned();
+            member.status = "active".to_owned();
+            member.update_at = DateTime::now();
+            member.clone().update().await?;
+            Ok(member)
+        } else {
+            let mut member = SiteMember::default();
+            member.id = Uuid::now_v7();
+            member.site_id = site.id;
+            member.user_id = *user_id;
+            member.role = site_role.as_str().to_owned();
+            member.status = "active".to_owned();
+            member.clone().insert().await?;
+            Ok(member)
+        }
+    }
+
+    pub async fn remove_member(site: &ChatWebsite, user_id: &Uuid) -> Result<(), Error> {
+        if site.user_id == *user_id {
+            return Err(warn!("403 Forbidden: site owner can not be removed"));
+        }
+        let mut query = Query::from_entry("site_id", site.id.to_string());
+        query.add_filter("user_id", user_id.to_string());
+        query.add_filter("role", json!({"$ne": "owner"}));
+        let Some(mut member) = SiteMember::find_one::<SiteMember>(&query).await? else {
+            return Err(warn!("member not found"));
+        };
+        member.status = "removed".to_owned();
+        member.update_at = DateTime::now();
+        member.update().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use zino_core::{
+    error::Error, extension::JsonObjectExt, json, model::Query, orm::Schema, Map, Uuid,
+};
+
+use crate::model::{ChatMessage, ChatReadCursor, ChatRoom};
+
+pub const AGENT_SIDE: &str = "agent";
+pub const VISITOR_SIDE: &str = "visitor";
+
+pub struct ReadCursorService;
+
+/**
+ * 1.打开房间时只记录一条已读位置，不再逐条修改消息状态
+ * 2.访客消息由客服端已读，其余消息由访客端已读
+ * 3.消息列表按已读位置得出已读状态
+ */
+
+impl ReadCursorService {
+    // 1 已读到房间最新一条消息，返回已读位置
+    pub async fn mark_read(
+        room: &ChatRoom,
+        side: &str,
+        user_id: Option<Uuid>,
+    ) -> Result<Option<Uuid>, Error> {
+        let mut last_query = Query::from_entry("room_id", room.id.to_string());
+        last_query.add_filter("status", json!({"$ne": "delete"}));
+        last_query.order_desc("id");
+        let Some(last) = ChatMessage::find_one::<ChatMessage>(&last_query).await? else {
+            return Ok(None);
+        };
+        // 以 (room_id, side) 唯一约束 upsert，同时进入房间也只有一条，已读位置只前进不后退
+        let sql = format!(
+            "INSERT INTO {table} AS c \
+                (id, room_id, side, last_read_id, user_id, read_at, create_at, update_at, version) \
+            VALUES (#{{id}}, #{{room_id}}, #{{side}}, #{{last_read_id}}, {user_id}, \
+                now(), now(), now(), 0) \
+            ON CONFLICT (room_id, side) DO UPDATE SET \
+                last_read_id = GREATEST(c.last_read_id, EXCLUDED.last_read_id), \
+                user_id = COALESCE(EXCLUDED.user_id, c.user_id), \
+                read_at = now(), update_at = now(), version = c.version + 1 \
+            RETURNING last_read_id",
+            table = ChatReadCursor::table_name(),
+            user_id = user_id.map_or("NULL".to_owned(), |id| format!("'{}'", id)),
+        );
+        let mut params = Map::new();
+        params.upsert("id", Uuid::now_v7().to_string());
+        params.upsert("room_id", room.id.to_string());
+        params.upsert("side", side);
+        params.upsert("last_read_id", last.id.to_string());
+        let last_read_id = ChatReadCursor::query_as::<Map>(&sql, Some(&params))
+            .await?
+            .first()
+            .and_then(|row| row.get_str("last_read_id"))
+            .and_then(|id| id.parse::<Uuid>().ok())
+            .unwrap_or(last.id);
+        Ok(Some(last_read_id))
+    }
+
+    // 房间 -> 已读位置
+    pub async fn cursors(room_ids: &[String], side: &str) -> Result<HashMap<Uuid, Uuid>, Error> {
+        if room_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut query = Query::from_entry("room_id", json!({"$in": room_ids}));
+        query.add_filter("side", side);
+        let cursors = ChatReadCursor::find::<ChatReadCursor>(&query).await?;
+        Ok(cursors
+            .into_iter()
+            .map(|c| (c.room_id, c.last_read_id))
+            .collect())
+    }
+
+    // 2 读取消息的一端
+    pub fn reader_side(message: &ChatMessage) -> &'static str {
+        if message.sender_type == "visitor" {
+            AGENT_SIDE
+        } else {
+            VISITOR_SIDE
+        }
+    }
+
+    // 2.1 没有已读位置的房间沿用消息上的状态
+    pub fn is_unread(message: &ChatMessage, cursor: Option<&Uuid>) -> bool {
+        message.status == "sended" && cursor.map_or(true, |c| message.id > *c)
+    }
+
+    // 3
+    pub async fn apply_read_state(room: &ChatRoom, messages: &mut [ChatMessage]) -> Result<(), Error> {
+        let room_ids = [room.id.to_string()];
+        let agent_cursor = Self::cursors(&room_ids, AGENT_SIDE).await?.remove(&room.id);
+        let visitor_cursor = Self::cursors(&room_ids, VISITOR_SIDE).await?.remove(&room.id);
+        for message in messages.iter_mut() {
+            let cursor = match Self::reader_side(message) {
+                AGENT_SIDE => agent_cursor.as_ref(),
+                _ => visitor_cursor.as_ref(),
+            };
+            if message.status == "sended" && !Self::is_unread(message, cursor) {
+                message.status = "readed".to_owned();
+            }
+        }
+        Ok(())
+    }
+}
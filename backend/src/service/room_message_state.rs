@@ -1,92 +1,316 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+};
 
-use crate::middleware::redis::REDIS_MANAGER;
+use crate::{
+    app_config::SETTINGS,
+    middleware::redis::REDIS_MANAGER,
+    utils::{date_utils::current_ms, metrics_utils},
+};
 use anyhow::Result;
+use redis::Script;
+
+/**
+ * 站点在 Redis 中的键：
+ * site:{site}:counts       hash，{room}:latest 未读数、{room}:total 消息数、total_unread 站点总未读数
+ * site:{site}:rooms        set，当前在线的房间
+ * site:{site}:active_rooms sorted set，按最后一条消息时间排序的房间
+ * 每次写入都会续期，长时间没有消息的站点键自动过期，未读数由定时任务按数据库重新计算
+ */
+
+const TOTAL_UNREAD: &str = "total_unread";
+
+lazy_static! {
+    // KEYS: counts active_rooms, ARGV: room count now ttl
+    static ref INCREASE_SCRIPT: Script = Script::new(
+        r"
+        redis.call('HINCRBY', KEYS[1], ARGV[1] .. ':latest', ARGV[2])
+        redis.call('HINCRBY', KEYS[1], ARGV[1] .. ':total', ARGV[2])
+        local unread = redis.call('HINCRBY', KEYS[1], 'total_unread', ARGV[2])
+        redis.call('ZADD', KEYS[2], ARGV[3], ARGV[1])
+        redis.call('EXPIRE', KEYS[1], ARGV[4])
+        redis.call('EXPIRE', KEYS[2], ARGV[4])
+        return unread
+        ",
+    );
+    // KEYS: counts, ARGV: room ttl
+    static ref RESET_SCRIPT: Script = Script::new(
+        r"
+        local latest = tonumber(redis.call('HGET', KEYS[1], ARGV[1] .. ':latest') or '0')
+        redis.call('HSET', KEYS[1], ARGV[1] .. ':latest', 0)
+        local unread = redis.call('HINCRBY', KEYS[1], 'total_unread', -latest)
+        if unread < 0 then
+            redis.call('HSET', KEYS[1], 'total_unread', 0)
+            unread = 0
+        end
+        redis.call('EXPIRE', KEYS[1], ARGV[2])
+        return unread
+        ",
+    );
+    // KEYS: counts, ARGV: room expected ttl
+    // 按差值修正站点总未读数，返回修正前的房间未读数
+    static ref REPAIR_ROOM_SCRIPT: Script = Script::new(
+        r"
+        local latest = tonumber(redis.call('HGET', KEYS[1], ARGV[1] .. ':latest') or '0')
+        local expected = tonumber(ARGV[2])
+        if latest ~= expected then
+            redis.call('HSET', KEYS[1], ARGV[1] .. ':latest', expected)
+            redis.call('HINCRBY', KEYS[1], 'total_unread', expected - latest)
+            redis.call('EXPIRE', KEYS[1], ARGV[3])
+        end
+        return latest
+        ",
+    );
+    // KEYS: 旧的字符串键 counts, ARGV: field ttl
+    // 旧值累加到 hash 中，迁移期间新写入的计数不会丢失
+    static ref MIGRATE_COUNT_SCRIPT: Script = Script::new(
+        r"
+        local value = redis.call('GET', KEYS[1])
+        if value then
+            redis.call('HINCRBY', KEYS[2], ARGV[1], tonumber(value))
+            redis.call('EXPIRE', KEYS[2], ARGV[2])
+        end
+        redis.call('DEL', KEYS[1])
+        return value and 1 or 0
+        ",
+    );
+}
 
 pub struct MessageStatusManager;
 
 impl MessageStatusManager {
+    // 记录 Redis 错误数
+    async fn observed<T>(op: &'static str, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        let result = fut.await;
+        if result.is_err() {
+            metrics_utils::redis_error(op);
+        }
+        result
+    }
+
+    fn counts_key(site: &str) -> String {
+        format!("site:{}:counts", site)
+    }
+
+    fn rooms_key(site: &str) -> String {
+        format!("site:{}:rooms", site)
+    }
+
+    fn active_rooms_key(site: &str) -> String {
+        format!("site:{}:active_rooms", site)
+    }
+
+    fn ttl_secs() -> String {
+        (SETTINGS.redis_key_ttl_days * 24 * 3600).to_string()
+    }
+
     // 增加房间的最新消息数和总消息数
     pub async fn increase_latest_count(site: &str, room_id: &str, count: usize) -> Result<()> {
-        let latest_key = format!("site:{}:room:{}:latest_count", site, room_id);
-        let total_key = format!("site:{}:room:{}:total_count", site, room_id);
-        let unread_key = format!("site:{}:total_unread", site);
-
-        // 增加最新消息数
-        REDIS_MANAGER.incr(&latest_key, count as isize).await?;
-        // 增加总消息数
-        REDIS_MANAGER.incr(&total_key, count as isize).await?;
-        // 增加站点的总未读消息数
-        REDIS_MANAGER.incr(&unread_key, count as isize).await?;
-        Ok(())
+        Self::observed("increase_latest_count", async {
+            // 最新消息数、总消息数、站点总未读数一起增加，并记录房间最后消息时间
+            let _: i64 = REDIS_MANAGER
+                .eval(
+                    &INCREASE_SCRIPT,
+                    &[&Self::counts_key(site), &Self::active_rooms_key(site)],
+                    &[
+                        room_id.to_owned(),
+                        count.to_string(),
+                        current_ms().to_string(),
+                        Self::ttl_secs(),
+                    ],
+                )
+                .await?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn reset_all_counts(site: &str) -> Result<()> {
-        // let latest_key = format!("site:{}:room:{}:latest_count", site, room_id);
-        // let total_key = format!("site:{}:room:{}:total_count", site, room_id);
-        let unread_key = format!("site:{}:total_unread", site);
-
-        // 增加最新消息数
-        // REDIS_MANAGER.set(&unread_key, 0 as isize).await?;
-        // // 增加总消息数
-        // REDIS_MANAGER.set(&site, 0 as isize).await?;
-        // 增加站点的总未读消息数
-        REDIS_MANAGER.set(&unread_key, 0 as isize).await?;
-        Ok(())
+        Self::observed("reset_all_counts", async {
+            REDIS_MANAGER.hset(&Self::counts_key(site), TOTAL_UNREAD, 0).await
+        })
+        .await
     }
 
     // 重置房间的最新消息数
     pub async fn reset_latest_count(site: &str, room_id: &str) -> Result<()> {
-        let latest_key = format!("site:{}:room:{}:latest_count", site, room_id);
-        let unread_key = format!("site:{}:total_unread", site);
-        // 获取最新消息数
-        let latest_count: i64 = REDIS_MANAGER.get(&latest_key).await?.unwrap_or(0);
-        // 减去总未读消息数中的最新消息数
-        REDIS_MANAGER
-            .incr(&unread_key, -(latest_count) as isize)
-            .await?;
-        // 重置最新消息数
-        REDIS_MANAGER.set(&latest_key, 0).await?;
-        Ok(())
+        Self::observed("reset_latest_count", async {
+            // 读取、清零、扣减在同一个脚本内完成，总未读数不会小于 0
+            let _: i64 = REDIS_MANAGER
+                .eval(
+                    &RESET_SCRIPT,
+                    &[&Self::counts_key(site)],
+                    &[room_id.to_owned(), Self::ttl_secs()],
+                )
+                .await?;
+            Ok(())
+        })
+        .await
     }
 
     // 获取房间的最新消息数和总消息数
     pub async fn get_room_message_counts(site: &str, room_id: &str) -> Result<(i64, i64)> {
-        let latest_key = format!("site:{}:room:{}:latest_count", site, room_id);
-        let total_key = format!("site:{}:room:{}:total_count", site, room_id);
-
-        let latest_count: i64 = REDIS_MANAGER.get(&latest_key).await?.unwrap_or(0);
-        let total_count: i64 = REDIS_MANAGER.get(&total_key).await?.unwrap_or(0);
-
-        Ok((latest_count, total_count))
+        Self::observed("get_room_message_counts", async {
+            let counts_key = Self::counts_key(site);
+            let mut pipe = redis::pipe();
+            pipe.hget(&counts_key, format!("{}:latest", room_id))
+                .hget(&counts_key, format!("{}:total", room_id));
+            let (latest_count, total_count): (Option<i64>, Option<i64>) =
+                REDIS_MANAGER.pipeline(&pipe).await?;
+            Ok((latest_count.unwrap_or(0), total_count.unwrap_or(0)))
+        })
+        .await
     }
 
     // 获取站点的总未读消息数量
     pub async fn get_total_unread(site: &str) -> Result<i64> {
-        let unread_key = format!("site:{}:total_unread", site);
-        let total_unread: i64 = REDIS_MANAGER.get(&unread_key).await?.unwrap_or(0);
-        Ok(total_unread)
+        Self::observed("get_total_unread", async {
+            let total_unread: i64 = REDIS_MANAGER
+                .hget(&Self::counts_key(site), TOTAL_UNREAD)
+                .await?
+                .unwrap_or(0);
+            Ok(total_unread)
+        })
+        .await
+    }
+
+    // 站点总未读数和 since 毫秒时间戳之后有消息的房间未读数，一次往返取回
+    pub async fn site_unread_counts(site: &str, since: i64) -> Result<(i64, HashMap<String, i64>)> {
+        Self::observed("site_unread_counts", async {
+            let active_key = Self::active_rooms_key(site);
+            let mut pipe = redis::pipe();
+            pipe.zrembyscore(&active_key, "-inf", format!("({}", since))
+                .ignore()
+                .zrevrangebyscore(&active_key, "+inf", since)
+                .hgetall(Self::counts_key(site));
+            let (rooms, counts): (Vec<String>, HashMap<String, i64>) =
+                REDIS_MANAGER.pipeline(&pipe).await?;
+            let total_unread = counts.get(TOTAL_UNREAD).copied().unwrap_or(0);
+            let room_counts = rooms
+                .into_iter()
+                .map(|room| {
+                    let latest = counts.get(&format!("{}:latest", room)).copied().unwrap_or(0);
+                    (room, latest)
+                })
+                .collect();
+            Ok((total_unread, room_counts))
+        })
+        .await
+    }
+
+    // 房间上线，多实例各自增删，互不覆盖
+    pub async fn add_site_room(site: &str, room_id: &str) -> Result<()> {
+        Self::observed("add_site_room", async {
+            let rooms_key = Self::rooms_key(site);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .sadd(&rooms_key, room_id)
+                .ignore()
+                .expire(&rooms_key, SETTINGS.redis_key_ttl_days as i64 * 24 * 3600)
+                .ignore();
+            let _: () = REDIS_MANAGER.pipeline(&pipe).await?;
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn set_site_rooms(site: &str, rooms: &HashSet<String>) -> Result<()> {
-        let room_key = format!("site:{}:rooms", site);
-        let val: Vec<String> = rooms.clone().into_iter().collect();
-        tracing::info!("set site rooms {:?}", &rooms);
-        REDIS_MANAGER.set(&room_key, val.join(",")).await?;
-        Ok(())
+    pub async fn remove_site_room(site: &str, room_id: &str) -> Result<()> {
+        Self::observed("remove_site_room", async {
+            REDIS_MANAGER.srem(&Self::rooms_key(site), room_id).await
+        })
+        .await
     }
 
     pub async fn get_site_rooms(site: &str) -> Result<HashSet<String>> {
-        let room_key = format!("site:{}:rooms", site);
-        let rooms: Option<String> = REDIS_MANAGER.get(&room_key).await?;
-        tracing::info!("rooms: {}:{:?}", site,rooms);
-        let mut hs = HashSet::<String>::new();
-        if let Some(rs) = rooms {
-            rs.split(",").for_each(|r| {
-                hs.insert(r.to_string());
-            });
-        }
-        Ok(hs)
-        // Ok(())
+        Self::observed("get_site_rooms", async {
+            REDIS_MANAGER.smembers(&Self::rooms_key(site)).await
+        })
+        .await
+    }
+
+    // 修正房间未读数，返回修正前的值
+    pub async fn repair_room_count(site: &str, room_id: &str, expected: i64) -> Result<i64> {
+        Self::observed("repair_room_count", async {
+            REDIS_MANAGER
+                .eval(
+                    &REPAIR_ROOM_SCRIPT,
+                    &[&Self::counts_key(site)],
+                    &[room_id.to_owned(), expected.to_string(), Self::ttl_secs()],
+                )
+                .await
+        })
+        .await
+    }
+
+    // 站点下记录了未读数的房间
+    pub async fn counted_rooms(site: &str) -> Result<Vec<String>> {
+        Self::observed("counted_rooms", async {
+            let counts = REDIS_MANAGER.hgetall::<i64>(&Self::counts_key(site)).await?;
+            Ok(counts
+                .keys()
+                .filter_map(|k| k.strip_suffix(":latest"))
+                .map(|k| k.to_owned())
+                .collect())
+        })
+        .await
+    }
+
+    pub async fn set_total_unread(site: &str, total: i64) -> Result<()> {
+        Self::observed("set_total_unread", async {
+            REDIS_MANAGER.hset(&Self::counts_key(site), TOTAL_UNREAD, total).await
+        })
+        .await
+    }
+
+    // 迁移旧格式的键：逗号拼接的房间列表改为 set，每个房间的计数键合并到站点 hash
+    // 可以重复执行，返回迁移的键数量
+    pub async fn migrate_legacy_keys() -> Result<usize> {
+        Self::observed("migrate_legacy_keys", async {
+            let ttl = Self::ttl_secs();
+            let mut migrated = 0;
+            for key in REDIS_MANAGER.scan_keys("site:*:rooms").await? {
+                if REDIS_MANAGER.key_type(&key).await? != "string" {
+                    continue;
+                }
+                let rooms: Option<String> = REDIS_MANAGER.get(&key).await?;
+                REDIS_MANAGER.del(&key).await?;
+                let site = key.trim_start_matches("site:").trim_end_matches(":rooms");
+                for room in rooms.unwrap_or_default().split(',').filter(|r| !r.is_empty()) {
+                    Self::add_site_room(site, room).await?;
+                }
+                migrated += 1;
+            }
+            for key in REDIS_MANAGER.scan_keys("site:*:room:*_count").await? {
+                // site:{site}:room:{room}:latest_count
+                let parts = key.split(':').collect::<Vec<&str>>();
+                let field = match (parts.len(), parts.last()) {
+                    (5, Some(&"latest_count")) => format!("{}:latest", parts[3]),
+                    (5, Some(&"total_count")) => format!("{}:total", parts[3]),
+                    _ => continue,
+                };
+                let _: i64 = REDIS_MANAGER
+                    .eval(
+                        &MIGRATE_COUNT_SCRIPT,
+                        &[&key, &Self::counts_key(parts[1])],
+                        &[field, ttl.clone()],
+                    )
+                    .await?;
+                migrated += 1;
+            }
+            for key in REDIS_MANAGER.scan_keys("site:*:total_unread").await? {
+                let site = key.trim_start_matches("site:").trim_end_matches(":total_unread");
+                let _: i64 = REDIS_MANAGER
+                    .eval(
+                        &MIGRATE_COUNT_SCRIPT,
+                        &[&key, &Self::counts_key(site)],
+                        &[TOTAL_UNREAD.to_owned(), ttl.clone()],
+                    )
+                    .await?;
+                migrated += 1;
+            }
+            Ok(migrated)
+        })
+        .await
     }
 }
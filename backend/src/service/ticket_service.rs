@@ -0,0 +1,168 @@
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::{Mutation, Query},
+    orm::Schema, warn, JsonValue, Map, Uuid,
+};
+
+use crate::model::{ChatMessage, ChatRoom, ChatTicket, ChatWebsite};
+
+use super::{
+    chat_service::ChatService,
+    mailer::{Mail, MAILER},
+};
+
+pub struct TicketService;
+
+/**
+ * 1.客服不在线时访客留言（姓名、邮箱、内容），关联到已有或新建的房间
+ * 2.客服在留言箱中查看留言
+ * 3.客服回复留言，通过邮件发送给访客，并在访客下次打开窗口时展示
+ */
+
+impl TicketService {
+    // 1
+    pub async fn leave_message(
+        site_key: &str,
+        room_key: Option<String>,
+        name: &str,
+        email: &str,
+        content: &str,
+    ) -> Result<ChatTicket, Error> {
+        let query = Query::from_entry("site_key", site_key);
+        let Some(site) = ChatWebsite::find_one::<ChatWebsite>(&query).await? else {
+            return Err(warn!("site not found"));
+        };
+        let room = match ChatService::new_room(site_key.to_owned(), room_key).await? {
+            Some(room) => room,
+            None => return Err(warn!("init room error")),
+        };
+
+        // 留言同时作为一条普通消息保存，客服打开房间即可看到
+        let mut message = ChatMessage::default();
+        message.id = Uuid::now_v7();
+        message.name = name.to_owned();
+        message.content = content.to_owned();
+        message.room_id = room.id;
+        message.status = "sended".to_owned();
+        message.sender_type = "visitor".to_owned();
+        message.create_at = DateTime::now();
+        message.update_at = DateTime::now();
+        ChatService::save_message(&message).await?;
+
+        let mut ticket = ChatTicket::default();
+        ticket.id = Uuid::now_v7();
+        ticket.site_id = site.id;
+        ticket.room_id = room.id;
+        ticket.name = name.to_owned();
+        ticket.email = email.to_owned();
+        ticket.content = content.to_owned();
+        ticket.status = "open".to_owned();
+        let result = ticket.clone();
+        ticket.insert().await?;
+        Ok(result)
+    }
+
+    // 2
+    pub async fn list_tickets(
+        site_id: &Uuid,
+        status: Option<String>,
+        page: usize,
+        page_num: usize,
+    ) -> Result<Map, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        if let Some(s) = status {
+            query.add_filter("status", s);
+        }
+        let count_query = query.clone();
+        query.order_by("create_at", true);
+        query.set_limit(page_num);
+        query.set_offset((page - 1) * page_num);
+        let total = ChatTicket::count(&count_query).await?;
+        let data = ChatTicket::find::<ChatTicket>(&query).await?;
+        let md = data
+            .iter()
+            .map(|t| -> JsonValue { serde_json::to_value(t).unwrap() })
+            .collect::<Vec<JsonValue>>();
+        let mut res = Map::new();
+        res.append(&mut Map::from_entry("data", md));
+        res.append(&mut Map::from_entry("total", total));
+        Ok(res)
+    }
+
+    pub async fn find_ticket(site_id: &Uuid, ticket_id: &str) -> Result<ChatTicket, Error> {
+        let mut query = Query::from_entry("id", ticket_id);
+        query.add_filter("site_id", site_id.to_string());
+        match ChatTicket::find_one::<ChatTicket>(&query).await? {
+            Some(ticket) => Ok(ticket),
+            None => Err(warn!("ticket not found")),
+        }
+    }
+
+    // 3
+    pub async fn reply_ticket(
+        ticket: &mut ChatTicket,
+        user_id: &Uuid,
+        user_name: &str,
+        content: &str,
+    ) -> Result<(), Error> {
+        let mut message = ChatMessage::default();
+        message.id = Uuid::now_v7();
+        message.name = user_name.to_owned();
+        message.user_id = Some(*user_id);
+        message.content = content.to_owned();
+        message.room_id = ticket.room_id;
+        message.status = "sended".to_owned();
+        message.sender_type = "agent".to_owned();
+        message.create_at = DateTime::now();
+        message.update_at = DateTime::now();
+        ChatService::save_message(&message).await?;
+
+        ticket.status = "replied".to_owned();
+        ticket.reply_content = Some(content.to_owned());
+        ticket.reply_user_id = Some(*user_id);
+        ticket.reply_at = Some(DateTime::now());
+        ticket.reply_delivered = false;
+        let mail = Mail {
+            to: ticket.email.clone(),
+            subject: "您的留言已回复".to_owned(),
+            body: format!(
+                "{}，您好：\n\n您的留言：\n{}\n\n回复：\n{}\n",
+                &ticket.name, &ticket.content, content
+            ),
+        };
+        // 邮件失败不影响回复，访客下次访问时仍能看到
+        match MAILER.send(&mail).await {
+            Ok(_) => ticket.reply_mailed = true,
+            Err(e) => tracing::warn!("mail ticket {} reply error: {}", &ticket.id, e),
+        }
+        ticket.update_at = DateTime::now();
+        ticket.clone().update().await?;
+        Ok(())
+    }
+
+    pub async fn close_ticket(ticket: &mut ChatTicket) -> Result<(), Error> {
+        ticket.status = "closed".to_owned();
+        ticket.update_at = DateTime::now();
+        ticket.clone().update().await?;
+        Ok(())
+    }
+
+    // 3.1 访客再次打开窗口，取出尚未展示的回复
+    pub async fn take_undelivered_replies(room: &ChatRoom) -> Result<Vec<ChatTicket>, Error> {
+        let mut query = Query::from_entry("room_id", room.id.to_string());
+        query.add_filter("status", "replied");
+        query.add_filter("reply_delivered", false);
+        query.order_asc("reply_at");
+        let tickets = ChatTicket::find::<ChatTicket>(&query).await?;
+        if tickets.is_empty() {
+            return Ok(tickets);
+        }
+        // 一条语句标记为已展示，只更新本次取出的留言
+        let ids = tickets.iter().map(|t| t.id.to_string()).collect::<Vec<String>>();
+        let query = Query::from_entry("id", json!({"$in": ids}));
+        let mut updates = Map::new();
+        updates.upsert("reply_delivered", true);
+        updates.upsert("update_at", DateTime::now());
+        ChatTicket::update_many(&query, &mut Mutation::new(updates)).await?;
+        Ok(tickets)
+    }
+}
@@ -0,0 +1,347 @@
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::Query, orm::Schema,
+    JsonValue, Map, Uuid,
+};
+
+use crate::{
+    app_config::SETTINGS,
+    model::{ChatRoom, ChatWebsite, StorageUsage},
+};
+
+// 文件名（不含扩展名）最大字符数
+const MAX_NAME_CHARS: usize = 64;
+
+pub struct UploadService;
+
+/**
+ * 1.上传限制按站点配置，未配置时使用全局配置
+ * 2.文件类型按文件内容识别，不信任扩展名
+ * 3.文件名只保留字母数字和 . - _，扩展名与识别出的类型一致
+ * 4.按房间和站点统计已用空间，超出配额拒绝上传
+ */
+
+/// 站点的上传限制
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    pub max_bytes: u64,
+    pub mime_types: Vec<String>,
+    pub room_quota_bytes: u64,
+    pub site_quota_bytes: u64,
+}
+
+/// 拒绝上传的原因，返回给窗口展示
+#[derive(Debug, Clone)]
+pub enum UploadRejection {
+    FileTooLarge { limit: u64 },
+    UnsupportedType { mime: String },
+    QuotaExceeded { scope: &'static str, limit: u64, used: u64 },
+}
+
+impl UploadRejection {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::FileTooLarge { .. } => "file_too_large",
+            Self::UnsupportedType { .. } => "unsupported_type",
+            Self::QuotaExceeded { .. } => "quota_exceeded",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::FileTooLarge { limit } => format!("文件大小不能超过 {}", format_bytes(*limit)),
+            Self::UnsupportedType { mime } => format!("不支持的文件类型：{}", mime),
+            Self::QuotaExceeded { scope: "room", .. } => "当前会话的附件空间已用完".to_owned(),
+            Self::QuotaExceeded { .. } => "站点的附件空间已用完".to_owned(),
+        }
+    }
+
+    pub fn to_json(&self, file_name: &str) -> JsonValue {
+        let mut data = json!({
+            "error": self.code(),
+            "message": self.message(),
+            "file_name": file_name,
+        });
+        match self {
+            Self::FileTooLarge { limit } => data["limit"] = json!(limit),
+            Self::UnsupportedType { mime } => data["mime_type"] = json!(mime),
+            Self::QuotaExceeded { scope, limit, used } => {
+                data["scope"] = json!(scope);
+                data["limit"] = json!(limit);
+                data["used"] = json!(used);
+            }
+        }
+        data
+    }
+}
+
+impl UploadService {
+    // 上传目标：房间和所属站点
+    pub async fn find_target(room_key: &str) -> Result<Option<(ChatWebsite, ChatRoom)>, Error> {
+        let query = Query::from_entry("room_key", room_key);
+        let Some(room) = ChatRoom::find_one::<ChatRoom>(&query).await? else {
+            return Ok(None);
+        };
+        let Some(site) = ChatWebsite::find_by_id::<ChatWebsite>(&room.room_site_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some((site, room)))
+    }
+
+    // 1
+    pub fn policy(site: &ChatWebsite) -> UploadPolicy {
+        let mime_types = site
+            .upload_mime_types
+            .as_deref()
+            .unwrap_or(&SETTINGS.upload_mime_types)
+            .split(',')
+            .map(|m| m.trim().to_lowercase())
+            .filter(|m| !m.is_empty())
+            .collect();
+        UploadPolicy {
+            max_bytes: site.upload_max_bytes.map_or(SETTINGS.upload_max_bytes, |b| b as u64),
+            mime_types,
+            room_quota_bytes: site.room_quota_bytes.map_or(SETTINGS.room_quota_bytes, |q| q as u64),
+            site_quota_bytes: site.site_quota_bytes.map_or(SETTINGS.site_quota_bytes, |q| q as u64),
+        }
+    }
+
+    // 2 识别不出的二进制内容按 application/octet-stream 处理
+    pub fn sniff_mime(bytes: &[u8]) -> String {
+        if let Some(kind) = infer::get(bytes) {
+            return kind.mime_type().to_owned();
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            "text/plain".to_owned()
+        } else {
+            "application/octet-stream".to_owned()
+        }
+    }
+
+    // 2.1 校验大小和类型，返回识别出的类型
+    pub fn check_file(policy: &UploadPolicy, bytes: &[u8]) -> Result<String, UploadRejection> {
+        if bytes.len() as u64 > policy.max_bytes {
+            return Err(UploadRejection::FileTooLarge { limit: policy.max_bytes });
+        }
+        let mime = Self::sniff_mime(bytes);
+        let allowed = policy.mime_types.iter().any(|m| match m.strip_suffix("/*") {
+            Some(prefix) => m == "*/*" || mime.split('/').next() == Some(prefix),
+            None => *m == mime,
+        });
+        if !allowed {
+            return Err(UploadRejection::UnsupportedType { mime });
+        }
+        Ok(mime)
+    }
+
+    // 3
+    pub fn sanitize_file_name(file_name: &str, mime: &str) -> String {
+        let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        let cleaned = base
+            .chars()
+            .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+            .collect::<String>();
+        let cleaned = cleaned.trim_start_matches('.');
+        let (stem, ext) = match cleaned.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, ext.to_lowercase()),
+            _ => (cleaned, String::new()),
+        };
+        let mut stem = stem.chars().take(MAX_NAME_CHARS).collect::<String>();
+        if stem.is_empty() {
+            stem = "file".to_owned();
+        }
+        // 扩展名与内容不符时改用类型对应的扩展名，避免下载时按扩展名当作网页打开
+        let ext = match mime_guess::get_mime_extensions_str(mime) {
+            Some(exts) if !exts.contains(&ext.as_str()) => match mime {
+                "text/plain" => "txt".to_owned(),
+                "image/jpeg" => "jpg".to_owned(),
+                _ => exts[0].to_owned(),
+            },
+            Some(_) => ext,
+            None => "bin".to_owned(),
+        };
+        format!("{}.{}", stem, ext)
+    }
+
+    // 4 占用配额：先房间后站点，各用一条条件 upsert，并发上传也不会超出配额
+    //   站点超出时退回已占用的房间配额，上传失败时调用方用 record_usage 退回
+    pub async fn reserve_quota(
+        policy: &UploadPolicy,
+        site_id: &Uuid,
+        room_id: &Uuid,
+        size: u64,
+    ) -> Result<Result<(), UploadRejection>, Error> {
+        let bytes = size as i64;
+        let room_reserved = size <= policy.room_quota_bytes
+            && Self::add_usage(site_id, room_id, bytes, 1, Some(policy.room_quota_bytes)).await?;
+        if !room_reserved {
+            return Ok(Err(UploadRejection::QuotaExceeded {
+                scope: "room",
+                limit: policy.room_quota_bytes,
+                used: Self::used_bytes(site_id, room_id).await?,
+            }));
+        }
+        let site_reserved = size <= policy.site_quota_bytes
+            && Self::add_usage(site_id, &Uuid::nil(), bytes, 1, Some(policy.site_quota_bytes)).await?;
+        if !site_reserved {
+            Self::add_usage(site_id, room_id, -bytes, -1, None).await?;
+            return Ok(Err(UploadRejection::QuotaExceeded {
+                scope: "site",
+                limit: policy.site_quota_bytes,
+                used: Self::used_bytes(site_id, &Uuid::nil()).await?,
+            }));
+        }
+        Ok(Ok(()))
+    }
+
+    // 4.1 记录房间和站点的用量，删除文件时传入负数
+    pub async fn record_usage(site_id: &Uuid, room_id: &Uuid, bytes: i64, files: i64) -> Result<(), Error> {
+        Self::add_usage(site_id, room_id, bytes, files, None).await?;
+        Self::add_usage(site_id, &Uuid::nil(), bytes, files, None).await?;
+        Ok(())
+    }
+
+    // 4.2 房间开始统计用量的时间，此后上传的文件都已计入
+    pub async fn counted_since(site_id: &Uuid, room_id: &Uuid) -> Result<Option<DateTime>, Error> {
+        Ok(Self::find_usage(site_id, room_id).await?.map(|u| u.create_at))
+    }
+
+    async fn used_bytes(site_id: &Uuid, room_id: &Uuid) -> Result<u64, Error> {
+        let usage = Self::find_usage(site_id, room_id).await?;
+        Ok(usage.map_or(0, |u| u.used_bytes.max(0) as u64))
+    }
+
+    async fn find_usage(site_id: &Uuid, room_id: &Uuid) -> Result<Option<StorageUsage>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.add_filter("room_id", room_id.to_string());
+        StorageUsage::find_one::<StorageUsage>(&query).await
+    }
+
+    // 4.3 按 (site_id, room_id) upsert 用量，传入 quota 时只在增加后不超出时更新，返回是否已记录
+    async fn add_usage(
+        site_id: &Uuid,
+        room_id: &Uuid,
+        bytes: i64,
+        files: i64,
+        quota: Option<u64>,
+    ) -> Result<bool, Error> {
+        let condition = match quota {
+            Some(quota) => format!("WHERE u.used_bytes + #{{bytes}} <= {}", quota),
+            None => String::new(),
+        };
+        let sql = format!(
+            "INSERT INTO {table} AS u \
+                (id, site_id, room_id, used_bytes, file_count, create_at, update_at, version) \
+            VALUES (#{{id}}, #{{site_id}}, #{{room_id}}, GREATEST(#{{bytes}}, 0), \
+                GREATEST(#{{files}}, 0), now(), now(), 0) \
+            ON CONFLICT (site_id, room_id) DO UPDATE SET \
+                used_bytes = GREATEST(u.used_bytes + #{{bytes}}, 0), \
+                file_count = GREATEST(u.file_count + #{{files}}, 0), \
+                update_at = now(), version = u.version + 1 \
+            {condition}",
+            table = StorageUsage::table_name(),
+        );
+        let mut params = Map::new();
+        params.upsert("id", Uuid::now_v7().to_string());
+        params.upsert("site_id", site_id.to_string());
+        params.upsert("room_id", room_id.to_string());
+        params.upsert("bytes", bytes);
+        params.upsert("files", files);
+        let ctx = StorageUsage::execute(&sql, Some(&params)).await?;
+        Ok(ctx.rows_affected() == Some(1))
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{}KB", bytes / 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0, 0, 0x0d];
+    const JPEG: &[u8] = &[0xff, 0xd8, 0xff, 0xe0, 0, 0x10, b'J', b'F', b'I', b'F'];
+    const PDF: &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+
+    fn policy(mime_types: &[&str]) -> UploadPolicy {
+        UploadPolicy {
+            max_bytes: 100,
+            mime_types: mime_types.iter().map(|m| m.to_string()).collect(),
+            room_quota_bytes: 1000,
+            site_quota_bytes: 1000,
+        }
+    }
+
+    #[test]
+    fn it_sniffs_mime_from_content() {
+        assert_eq!(UploadService::sniff_mime(PNG), "image/png");
+        assert_eq!(UploadService::sniff_mime(JPEG), "image/jpeg");
+        assert_eq!(UploadService::sniff_mime(PDF), "application/pdf");
+        assert_eq!(UploadService::sniff_mime(b"hello"), "text/plain");
+        assert_eq!(UploadService::sniff_mime(&[0x00, 0x9f, 0x92, 0x96]), "application/octet-stream");
+    }
+
+    #[test]
+    fn it_checks_mime_allow_list() {
+        let cases: [(&[&str], &[u8], Result<&str, &str>); 8] = [
+            (&["image/*"], PNG, Ok("image/png")),
+            (&["image/*"], JPEG, Ok("image/jpeg")),
+            (&["image/*"], PDF, Err("application/pdf")),
+            (&["image/png"], JPEG, Err("image/jpeg")),
+            (&["application/*"], PNG, Err("image/png")),
+            (&["image/*", "application/pdf"], PDF, Ok("application/pdf")),
+            (&["*/*"], b"hello", Ok("text/plain")),
+            (&[], PNG, Err("image/png")),
+        ];
+        for (mime_types, bytes, expected) in cases {
+            let result = UploadService::check_file(&policy(mime_types), bytes);
+            match (result, expected) {
+                (Ok(mime), Ok(expected)) => assert_eq!(mime, expected, "{:?}", mime_types),
+                (Err(UploadRejection::UnsupportedType { mime }), Err(expected)) => {
+                    assert_eq!(mime, expected, "{:?}", mime_types)
+                }
+                (result, expected) => panic!("{:?}: {:?} != {:?}", mime_types, result, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_large_files() {
+        let bytes = [b'a'; 101];
+        let result = UploadService::check_file(&policy(&["*/*"]), &bytes);
+        assert!(matches!(result, Err(UploadRejection::FileTooLarge { limit: 100 })));
+        assert!(UploadService::check_file(&policy(&["*/*"]), &bytes[..100]).is_ok());
+    }
+
+    #[test]
+    fn it_sanitizes_file_names() {
+        let long_name = format!("{}.pdf", "a".repeat(200));
+        let cases = [
+            // 扩展名与识别出的类型不符
+            ("report.png", "application/pdf", "report.pdf".to_owned()),
+            ("index.html", "text/plain", "index.txt".to_owned()),
+            ("PHOTO.JPG", "image/jpeg", "PHOTO.jpg".to_owned()),
+            // 路径只保留文件名
+            ("../../etc/passwd", "text/plain", "passwd.txt".to_owned()),
+            ("/var/www/shell.png", "image/png", "shell.png".to_owned()),
+            ("..\\..\\a.jpg", "image/jpeg", "a.jpg".to_owned()),
+            ("..", "text/plain", "file.txt".to_owned()),
+            // 隐藏文件和没有扩展名
+            (".htaccess", "text/plain", "htaccess.txt".to_owned()),
+            ("..env.pdf", "application/pdf", "env.pdf".to_owned()),
+            ("README", "application/pdf", "README.pdf".to_owned()),
+            ("", "image/png", "file.png".to_owned()),
+            // 特殊字符
+            ("my file<script>.pdf", "application/pdf", "my_file_script_.pdf".to_owned()),
+            // 过长的文件名
+            (long_name.as_str(), "application/pdf", format!("{}.pdf", "a".repeat(MAX_NAME_CHARS))),
+        ];
+        for (file_name, mime, expected) in cases {
+            assert_eq!(UploadService::sanitize_file_name(file_name, mime), expected, "{}", file_name);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zino_core::{
+    datetime::DateTime, error::Error, extension::JsonObjectExt, json, model::{Mutation, Query}, orm::Schema,
+    warn, JsonValue, Map, Uuid,
+};
+
+use crate::{
+    model::{WebhookDelivery, WebhookSubscription},
+    utils::{date_utils::current_s, generate_random_string},
+};
+
+pub const EVENTS: [&str; 6] = [
+    "room.created",
+    "visitor.connected",
+    "visitor.disconnected",
+    "message.created",
+    "room.closed",
+    "rating.submitted",
+];
+
+// 最多重试次数，超过后标记为失败，可手动重放
+const MAX_ATTEMPTS: u32 = 8;
+// 第 n 次失败后等待 BACKOFF_BASE * 2^(n-1) 秒
+const BACKOFF_BASE: u64 = 30;
+// 每轮最多推送条数
+const BATCH_SIZE: usize = 50;
+// 单次推送超时
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+// 认领后的锁定时长，需大于一轮推送的最长耗时
+const LOCK_SECS: u64 = BATCH_SIZE as u64 * DELIVERY_TIMEOUT_SECS + 60;
+
+pub struct WebhookService;
+
+/**
+ * 1.客服配置站点事件订阅
+ * 2.事件发生时为每个订阅生成一条推送记录
+ * 3.后台任务推送，先认领再推送，避免多个实例或重叠的任务重复推送，失败按指数退避重试
+ * 4.推送记录查询与重放
+ */
+
+impl WebhookService {
+    // 1
+    pub async fn list_subscriptions(site_id: &Uuid) -> Result<Vec<WebhookSubscription>, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.order_by("create_at", true);
+        Ok(WebhookSubscription::find::<WebhookSubscription>(&query).await?)
+    }
+
+    pub async fn save_subscription(sub: &mut WebhookSubscription) -> Result<(), Error> {
+        if !sub.url.starts_with("http://") && !sub.url.starts_with("https://") {
+            return Err(warn!("invalid webhook url: {}", &sub.url));
+        }
+        if let Some(e) = sub.events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+            return Err(warn!("unknown event: {}", e));
+        }
+        sub.update_at = DateTime::now();
+        let mut query = Query::from_entry("id", sub.id.to_string());
+        query.add_filter("site_id", sub.site_id.to_string());
+        if let Some(exists) = WebhookSubscription::find_one::<WebhookSubscription>(&query).await? {
+            if sub.secret.is_empty() {
+                sub.secret = exists.secret;
+            }
+            sub.clone().update().await?;
+        } else {
+            sub.id = Uuid::now_v7();
+            sub.create_at = DateTime::now();
+            if sub.secret.is_empty() {
+                sub.secret = generate_random_string(32);
+            }
+            sub.clone().insert().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_subscription(site_id: &Uuid, sub_id: &str) -> Result<(), Error> {
+        let mut query = Query::from_entry("id", sub_id);
+        query.add_filter("site_id", site_id.to_string());
+        WebhookSubscription::delete_many(&query).await?;
+        Ok(())
+    }
+
+    // 2
+    pub async fn emit(site_id: &Uuid, event: &str, data: JsonValue) -> Result<(), Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        query.add_filter("status", "active");
+        let subs = WebhookSubscription::find::<WebhookSubscription>(&query).await?;
+        for sub in subs.iter().filter(|s| s.events.iter().any(|e| e == event)) {
+            let mut delivery = WebhookDelivery::default();
+            delivery.id = Uuid::now_v7();
+            let payload = json!({
+                "id": delivery.id.to_string(),
+                "event": event,
+                "site_id": site_id.to_string(),
+                "created_at": DateTime::now(),
+                "data": data.clone(),
+            });
+            delivery.subscription_id = sub.id;
+            delivery.site_id = *site_id;
+            delivery.event = event.to_owned();
+            delivery.payload = payload.to_string();
+            delivery.status = "pending".to_owned();
+            delivery.next_attempt_at = DateTime::now();
+            delivery.insert().await?;
+        }
+        Ok(())
+    }
+
+    // 2.1 在 actor 或请求处理中调用，不等待结果
+    pub fn emit_later(site_id: Uuid, event: &'static str, data: JsonValue) {
+        actix::spawn(async move {
+            if let Err(e) = Self::emit(&site_id, event, data).await {
+                tracing::warn!("emit webhook event {} error: {}", event, e);
+            }
+        });
+    }
+
+    // 3 返回本实例认领并处理的条数，单条出错不影响其它记录
+    pub async fn deliver_pending() -> Result<usize, Error> {
+        let now = DateTime::now();
+        let mut query = Query::from_entry(
+            "$or",
+            json!([
+                {"status": "pending", "next_attempt_at": {"$le": now}},
+                {"status": "delivering", "locked_until": {"$lt": now}},
+            ]),
+        );
+        query.order_asc("next_attempt_at");
+        query.set_limit(BATCH_SIZE);
+        let candidates = WebhookDelivery::find::<WebhookDelivery>(&query).await?;
+        let client = reqwest::Client::new();
+        let mut count = 0;
+        for delivery in candidates {
+            let Some(mut delivery) = Self::claim(delivery).await? else {
+                continue;
+            };
+            count += 1;
+            if let Err(e) = Self::deliver_claimed(&client, &mut delivery).await {
+                tracing::warn!("webhook delivery {} error: {}", &delivery.id, e);
+            }
+        }
+        Ok(count)
+    }
+
+    // 3.1 按 version 条件更新认领，其它实例已认领时影响行数为 0
+    async fn claim(mut delivery: WebhookDelivery) -> Result<Option<WebhookDelivery>, Error> {
+        let locked_until = DateTime::now() + Duration::from_secs(LOCK_SECS);
+        let mut query = Query::from_entry("id", delivery.id.to_string());
+        query.add_filter("version", delivery.version);
+        let mut updates = Map::new();
+        updates.upsert("status", "delivering");
+        updates.upsert("locked_until", locked_until);
+        updates.upsert("version", delivery.version + 1);
+        updates.upsert("update_at", DateTime::now());
+        let ctx = WebhookDelivery::update_many(&query, &mut Mutation::new(updates)).await?;
+        if ctx.rows_affected() != Some(1) {
+            return Ok(None);
+        }
+        delivery.status = "delivering".to_owned();
+        delivery.locked_until = Some(locked_until);
+        delivery.version += 1;
+        Ok(Some(delivery))
+    }
+
+    async fn deliver_claimed(
+        client: &reqwest::Client,
+        delivery: &mut WebhookDelivery,
+    ) -> Result<(), Error> {
+        match WebhookSubscription::find_by_id::<WebhookSubscription>(&delivery.subscription_id).await? {
+            Some(sub) => Self::deliver(client, &sub, delivery).await,
+            None => {
+                delivery.status = "failed".to_owned();
+                delivery.last_error = Some("subscription removed".to_owned());
+            }
+        }
+        delivery.locked_until = None;
+        delivery.update_at = DateTime::now();
+        delivery.clone().update().await?;
+        Ok(())
+    }
+
+    async fn deliver(
+        client: &reqwest::Client,
+        sub: &WebhookSubscription,
+        delivery: &mut WebhookDelivery,
+    ) {
+        let timestamp = current_s().to_string();
+        let signature = Self::sign(&sub.secret, &timestamp, &delivery.payload);
+        delivery.attempts += 1;
+        let result = client
+            .post(&sub.url)
+            .timeout(Duration::from_secs(DELIVERY_TIMEOUT_SECS))
+            .header("Content-Type", "application/json")
+            .header("X-Rchat-Event", &delivery.event)
+            .header("X-Rchat-Delivery", delivery.id.to_string())
+            .header("X-Rchat-Timestamp", &timestamp)
+            .header("X-Rchat-Signature", format!("sha256={}", signature))
+            .body(delivery.payload.clone())
+            .send()
+            .await;
+        let error = match result {
+            Ok(resp) => {
+                delivery.response_status = Some(resp.status().as_u16() as i32);
+                if resp.status().is_success() {
+                    None
+                } else {
+                    Some(format!("response status {}", resp.status()))
+                }
+            }
+            Err(e) => Some(e.to_string()),
+        };
+        match error {
+            None => {
+                delivery.status = "delivered".to_owned();
+                delivery.delivered_at = Some(DateTime::now());
+                delivery.last_error = None;
+            }
+            Some(e) => {
+                tracing::warn!("webhook delivery {} attempt {} error: {}", &delivery.id, delivery.attempts, &e);
+                delivery.last_error = Some(e);
+                if delivery.attempts >= MAX_ATTEMPTS {
+                    delivery.status = "failed".to_owned();
+                } else {
+                    delivery.status = "pending".to_owned();
+                    let wait = BACKOFF_BASE * 2u64.pow(delivery.attempts - 1);
+                    delivery.next_attempt_at = DateTime::now() + Duration::from_secs(wait);
+                }
+            }
+        }
+    }
+
+    /// 签名内容为 `{timestamp}.{payload}`，接收方用相同密钥校验 `X-Rchat-Signature`
+    pub fn sign(secret: &str, timestamp: &str, payload: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(payload.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    // 4
+    pub async fn list_deliveries(
+        site_id: &Uuid,
+        subscription_id: Option<String>,
+        status: Option<String>,
+        page: usize,
+        page_num: usize,
+    ) -> Result<Map, Error> {
+        let mut query = Query::from_entry("site_id", site_id.to_string());
+        if let Some(sid) = subscription_id {
+            query.add_filter("subscription_id", sid);
+        }
+        if let Some(s) = status {
+            query.add_filter("status", s);
+        }
+        let count_query = query.clone();
+        query.order_by("create_at", true);
+        query.set_limit(page_num);
+        query.set_offset((page - 1) * page_num);
+        let total = WebhookDelivery::count(&count_query).await?;
+        let data = WebhookDelivery::find::<WebhookDelivery>(&query).await?;
+        let md = data
+            .iter()
+            .map(|d| -> JsonValue { serde_json::to_value(d).unwrap() })
+            .collect::<Vec<JsonValue>>();
+        let mut res = Map::new();
+        res.append(&mut Map::from_entry("data", md));
+        res.append(&mut Map::from_entry("total", total));
+        Ok(res)
+    }
+
+    // 4.1 重新加入推送队列，内容不变
+    pub async fn replay(site_id: &Uuid, delivery_id: &str) -> Result<WebhookDelivery, Error> {
+        let mut query = Query::from_entry("id", delivery_id);
+        query.add_filter("site_id", site_id.to_string());
+        let Some(mut delivery) = WebhookDelivery::find_one::<WebhookDelivery>(&query).await? else {
+            return Err(warn!("delivery not found"));
+        };
+        if delivery.status == "delivering" {
+            return Err(warn!("delivery is in progress"));
+        }
+        delivery.status = "pending".to_owned();
+        delivery.attempts = 0;
+        delivery.next_attempt_at = DateTime::now();
+        delivery.locked_until = None;
+        delivery.update_at = DateTime::now();
+        delivery.clone().update().await?;
+        Ok(delivery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    struct Received {
+        headers: Map,
+        body: String,
+    }
+
+    // 本地 HTTP 接收端，按顺序返回给定状态码，收到的请求通过 channel 传回
+    async fn receiver(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = Map::new();
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).await.unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    headers.upsert(name.to_lowercase(), value);
+                }
+                let length = headers
+                    .get_str("content-length")
+                    .and_then(|l| l.parse::<usize>().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+                let resp = format!("HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                reader.get_mut().write_all(resp.as_bytes()).await.unwrap();
+                let body = String::from_utf8(body).unwrap();
+                tx.send(Received { headers, body }).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn fixture(url: &str) -> (WebhookSubscription, WebhookDelivery) {
+        let mut sub = WebhookSubscription::default();
+        sub.url = url.to_owned();
+        sub.secret = "test-secret".to_owned();
+        let mut delivery = WebhookDelivery::default();
+        delivery.id = Uuid::now_v7();
+        delivery.event = "message.created".to_owned();
+        delivery.payload = json!({"event": "message.created", "data": {"content": "hi"}}).to_string();
+        delivery.status = "delivering".to_owned();
+        (sub, delivery)
+    }
+
+    #[tokio::test]
+    async fn it_signs_and_delivers() {
+        let (url, mut rx) = receiver(vec![200]).await;
+        let (sub, mut delivery) = fixture(&url);
+        WebhookService::deliver(&reqwest::Client::new(), &sub, &mut delivery).await;
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.body, delivery.payload);
+        assert_eq!(received.headers.get_str("x-rchat-event"), Some("message.created"));
+        let timestamp = received.headers.get_str("x-rchat-timestamp").unwrap();
+        let expected = format!("sha256={}", WebhookService::sign("test-secret", timestamp, &received.body));
+        assert_eq!(received.headers.get_str("x-rchat-signature"), Some(expected.as_str()));
+        assert_eq!(delivery.status, "delivered");
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn it_backs_off_and_fails_after_max_attempts() {
+        let (url, mut rx) = receiver(vec![500, 503]).await;
+        let (sub, mut delivery) = fixture(&url);
+        let client = reqwest::Client::new();
+
+        let before = DateTime::now();
+        WebhookService::deliver(&client, &sub, &mut delivery).await;
+        rx.recv().await.unwrap();
+        assert_eq!(delivery.status, "pending");
+        assert_eq!(delivery.response_status, Some(500));
+        assert!(delivery.next_attempt_at >= before + Duration::from_secs(BACKOFF_BASE));
+
+        // 第二次失败等待时间翻倍
+        delivery.attempts = 1;
+        let before = DateTime::now();
+        WebhookService::deliver(&client, &sub, &mut delivery).await;
+        rx.recv().await.unwrap();
+        assert!(delivery.next_attempt_at >= before + Duration::from_secs(BACKOFF_BASE * 2));
+
+        // 接收端已关闭，连接失败也计入重试次数
+        delivery.attempts = MAX_ATTEMPTS - 1;
+        WebhookService::deliver(&client, &sub, &mut delivery).await;
+        assert_eq!(delivery.status, "failed");
+        assert!(delivery.last_error.is_some());
+    }
+}
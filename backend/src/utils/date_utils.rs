@@ -43,6 +43,22 @@ pub fn format_date_ymd(date: DateTime<FixedOffset>) -> String {
     return date.format(fmt).to_string();
 }
 
+// 配置时区下某一天的起止时间戳（秒），左闭右开
+pub fn day_range_s(day: &str) -> anyhow::Result<(i64, i64)> {
+    let date = NaiveDate::parse_from_str(day, "%Y-%m-%d")?;
+    let offset = FixedOffset::east_opt(SETTINGS.time_zone * 3600).unwrap();
+    let start = offset
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap()
+        .timestamp();
+    Ok((start, start + 86400))
+}
+
+// 配置时区下前 n 天的日期
+pub fn date_ymd_days_ago(days: i64) -> String {
+    format_date_ymd(current_date() - chrono::Duration::days(days))
+}
+
 pub fn with_current_date(time: &str) -> anyhow::Result<DateTime<FixedOffset>> {
     let fmthour = "%Y-%m-%d %H:%M:%S%.3f%z";
     let date = current_date_ymd();
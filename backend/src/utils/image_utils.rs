@@ -0,0 +1,140 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, bail, Result};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+// 缩略图规格：名称、最长边
+pub const THUMB_SIZES: [(&str, u32); 2] = [("thumb", 240), ("preview", 1024)];
+const JPEG_QUALITY: u8 = 85;
+
+/// 生成的缩略图，重新编码后不包含 EXIF 等元数据
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    // 规格名称、内容、扩展名
+    pub variants: Vec<(&'static str, Vec<u8>, &'static str)>,
+}
+
+// 上传时去掉元数据：按 EXIF 方向摆正后用原格式重新编码
+// gif bmp 不带 EXIF 原样返回，其他格式和无法解码的图片返回错误
+pub fn strip_metadata(bytes: &[u8]) -> Result<Vec<u8>> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format().ok_or_else(|| anyhow!("unknown image format"))?;
+    match format {
+        ImageFormat::Gif | ImageFormat::Bmp => return Ok(bytes.to_vec()),
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP => {}
+        _ => bail!("unsupported image format: {:?}", format),
+    }
+    encode(&decode(reader)?, format)
+}
+
+// 生成缩略图，上传时已经去掉了元数据，这里仍按 EXIF 方向摆正
+pub fn process_image(bytes: &[u8]) -> Result<ProcessedImage> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let img = decode(reader)?;
+    // 带透明通道的图片缩略图用 png，其余用 jpeg
+    let thumb_format = if img.color().has_alpha() {
+        ImageFormat::Png
+    } else {
+        ImageFormat::Jpeg
+    };
+    let mut variants = Vec::with_capacity(THUMB_SIZES.len());
+    for (name, max) in THUMB_SIZES {
+        // 原图比规格小时不放大
+        let resized = if img.width() > max || img.height() > max {
+            img.thumbnail(max, max)
+        } else {
+            img.clone()
+        };
+        variants.push((name, encode(&resized, thumb_format)?, extension(thumb_format)));
+    }
+    Ok(ProcessedImage {
+        width: img.width(),
+        height: img.height(),
+        variants,
+    })
+}
+
+fn decode(reader: ImageReader<Cursor<&[u8]>>) -> Result<DynamicImage> {
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+        }
+        // webp 编码器只支持 8 位无损
+        ImageFormat::WebP => {
+            DynamicImage::ImageRgba8(img.to_rgba8()).write_to(&mut Cursor::new(&mut buffer), format)?
+        }
+        _ => img.write_to(&mut Cursor::new(&mut buffer), format)?,
+    }
+    Ok(buffer)
+}
+
+fn extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "png",
+        _ => "jpg",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn sample(format: ImageFormat) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 3, image::Rgb([200, 80, 20])));
+        encode(&img, format).unwrap()
+    }
+
+    // 在 SOI 之后插入带 GPS 标记的 APP1 段
+    fn with_exif(jpeg: &[u8]) -> Vec<u8> {
+        let payload = b"Exif\0\0MM\0*GPSLatitude";
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend_from_slice(&[0xff, 0xe1]);
+        bytes.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes.extend_from_slice(&jpeg[2..]);
+        bytes
+    }
+
+    fn contains(bytes: &[u8], needle: &[u8]) -> bool {
+        bytes.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn it_strips_exif_from_jpeg() {
+        let bytes = with_exif(&sample(ImageFormat::Jpeg));
+        assert!(contains(&bytes, b"GPSLatitude"));
+        let stripped = strip_metadata(&bytes).unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        let img = image::load_from_memory(&stripped).unwrap();
+        assert_eq!((img.width(), img.height()), (4, 3));
+    }
+
+    #[test]
+    fn it_reencodes_png_and_webp_in_place() {
+        for format in [ImageFormat::Png, ImageFormat::WebP] {
+            let stripped = strip_metadata(&sample(format)).unwrap();
+            assert_eq!(image::guess_format(&stripped).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn it_rejects_undecodable_images() {
+        let mut bytes = sample(ImageFormat::Jpeg);
+        bytes.truncate(20);
+        assert!(strip_metadata(&bytes).is_err());
+        assert!(strip_metadata(b"not an image").is_err());
+    }
+}
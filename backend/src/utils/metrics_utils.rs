@@ -0,0 +1,71 @@
+//! 聊天运行时指标，通过 zino 配置的 Prometheus exporter 暴露（见 config.*.toml 的 [metrics]）
+use std::time::Duration;
+
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+
+pub fn describe() {
+    describe_gauge!("rchat_active_sockets", "Open websocket connections per site and kind");
+    describe_gauge!("rchat_rooms", "Rooms tracked by the chat server");
+    describe_counter!("rchat_messages_total", "Chat messages received per site and sender");
+    describe_histogram!(
+        "rchat_message_persist_seconds",
+        Unit::Seconds,
+        "Latency of persisting a chat message"
+    );
+    describe_counter!("rchat_redis_errors_total", "Redis errors from message status manager");
+    describe_counter!("rchat_heartbeat_timeouts_total", "Websocket heartbeat timeouts");
+    describe_histogram!("rchat_oss_upload_seconds", Unit::Seconds, "OSS upload duration");
+    describe_counter!("rchat_oss_upload_failures_total", "OSS upload failures");
+    describe_counter!("rchat_audit_failures_total", "Audit events that failed to persist");
+}
+
+fn socket_kind(is_agent: bool) -> &'static str {
+    if is_agent {
+        "agent"
+    } else {
+        "visitor"
+    }
+}
+
+pub fn socket_opened(site_key: &str, is_agent: bool) {
+    gauge!("rchat_active_sockets", "site" => site_key.to_owned(), "kind" => socket_kind(is_agent))
+        .increment(1.0);
+}
+
+pub fn socket_closed(site_key: &str, is_agent: bool) {
+    gauge!("rchat_active_sockets", "site" => site_key.to_owned(), "kind" => socket_kind(is_agent))
+        .decrement(1.0);
+}
+
+pub fn set_rooms(count: usize) {
+    gauge!("rchat_rooms").set(count as f64);
+}
+
+// 每秒消息数由 rate(rchat_messages_total[1m]) 计算
+pub fn message_received(site_key: &str, is_agent: bool) {
+    counter!("rchat_messages_total", "site" => site_key.to_owned(), "sender" => socket_kind(is_agent))
+        .increment(1);
+}
+
+pub fn message_persisted(elapsed: Duration) {
+    histogram!("rchat_message_persist_seconds").record(elapsed.as_secs_f64());
+}
+
+pub fn redis_error(op: &'static str) {
+    counter!("rchat_redis_errors_total", "op" => op).increment(1);
+}
+
+pub fn heartbeat_timeout(is_agent: bool) {
+    counter!("rchat_heartbeat_timeouts_total", "kind" => socket_kind(is_agent)).increment(1);
+}
+
+pub fn audit_failed(action: &str) {
+    counter!("rchat_audit_failures_total", "action" => action.to_owned()).increment(1);
+}
+
+pub fn oss_uploaded(elapsed: Duration, ok: bool) {
+    histogram!("rchat_oss_upload_seconds").record(elapsed.as_secs_f64());
+    if !ok {
+        counter!("rchat_oss_upload_failures_total").increment(1);
+    }
+}
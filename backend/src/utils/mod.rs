@@ -1,5 +1,7 @@
 pub mod date_utils;
-pub mod oss_utils;
+pub mod image_utils;
+pub mod metrics_utils;
+pub mod storage_utils;
 
 use rand::{distributions::Alphanumeric, Rng};
 use serde::de::value;
@@ -14,6 +16,34 @@ pub fn generate_random_string(length: usize) -> String {
         .collect()
 }
 
+// 邮箱格式校验，只允许 RFC 5322 常见字符，不接受路径分隔符和空白
+pub fn is_valid_email(email: &str) -> bool {
+    if email.len() > 254 {
+        return false;
+    }
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+=?^_`{|}~.-".contains(c));
+    let labels = domain.split('.').collect::<Vec<&str>>();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    local_ok && domain_ok
+}
+
 pub fn str_to_usize(str: &str) -> Result<usize> {
     match str.parse::<usize>() {
         Ok(r) => Ok(r),
@@ -34,6 +64,34 @@ pub fn str_from_map(key: &str, map: &Map) -> Result<Option<String>> {
     }
 }
 
+// 表单中的数字可能是字符串
+pub fn i64_from_map(key: &str, map: &Map) -> Result<Option<i64>> {
+    match str_from_map(key, map)? {
+        Some(v) if !v.is_empty() => match v.parse::<i64>() {
+            Ok(d) => Ok(Some(d)),
+            Err(_) => {
+                let validation = Validation::from_entry("err_msg", warn!("{key} should be a number"));
+                Err(Rejection::bad_request(validation).into())
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+pub fn bool_from_map(key: &str, map: &Map) -> Result<Option<bool>> {
+    match str_from_map(key, map)? {
+        Some(v) if !v.is_empty() => match v.as_str() {
+            "true" | "1" => Ok(Some(true)),
+            "false" | "0" => Ok(Some(false)),
+            _ => {
+                let validation = Validation::from_entry("err_msg", warn!("{key} should be a boolean"));
+                Err(Rejection::bad_request(validation).into())
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
 pub fn str_from_map_required(key: &str, map: &Map) -> Result<String> {
     let key_clone = key.to_string().clone();
     match map.get(key) {
@@ -77,3 +135,31 @@ pub fn usize_from_map_default(key: &str, map: &Map, default: usize) -> Result<us
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_email;
+
+    #[test]
+    fn it_validates_emails() {
+        for email in ["a@b.co", "first.last+tag@mail.example.com", "x_y-z@sub-domain.cn"] {
+            assert!(is_valid_email(email), "{email}");
+        }
+        for email in [
+            "",
+            "no-at",
+            "@b.co",
+            "a@",
+            "a@localhost",
+            "../../x@y.com",
+            "a/b@c.com",
+            "a@b/../c.com",
+            "a b@c.com",
+            ".a@b.com",
+            "a..b@c.com",
+            "a@-b.com",
+        ] {
+            assert!(!is_valid_email(email), "{email}");
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use opendal::services::{Fs, Memory, Oss, S3};
+use opendal::Operator;
+use sha2::Sha256;
+use urlencoding::encode as url_encode;
+
+use crate::app_config::SETTINGS;
+use crate::utils::{date_utils::current_s, metrics_utils};
+
+lazy_static! {
+    pub static ref STORAGE: StorageClient = {
+        let operator = build_operator(&SETTINGS.storage_backend).expect("Failed to create storage");
+        StorageClient::new(operator)
+    };
+}
+
+/// 根据配置 `storage_backend` 创建存储：oss s3 fs memory
+fn build_operator(backend: &str) -> Result<Operator> {
+    let op = match backend {
+        "oss" => {
+            let builder = Oss::default()
+                .bucket(&SETTINGS.oss_bucket_name)
+                .endpoint(&SETTINGS.oss_endpoint)
+                .access_key_id(&SETTINGS.oss_access_key_id)
+                .access_key_secret(&SETTINGS.oss_access_key_secret);
+            Operator::new(builder)?.finish()
+        }
+        "s3" => {
+            let builder = S3::default()
+                .bucket(&SETTINGS.s3_bucket)
+                .endpoint(&SETTINGS.s3_endpoint)
+                .region(&SETTINGS.s3_region)
+                .access_key_id(&SETTINGS.s3_access_key_id)
+                .secret_access_key(&SETTINGS.s3_secret_access_key);
+            Operator::new(builder)?.finish()
+        }
+        "fs" => Operator::new(Fs::default().root(&SETTINGS.storage_root))?.finish(),
+        "memory" => Operator::new(Memory::default())?.finish(),
+        _ => return Err(anyhow!("unknown storage backend: {}", backend)),
+    };
+    // 本地签名链接依赖密钥，不能为空
+    if !op.info().full_capability().presign_read && SETTINGS.storage_sign_secret.is_empty() {
+        return Err(anyhow!("storage_sign_secret is required for {} storage", backend));
+    }
+    Ok(op)
+}
+
+/// 聊天附件存储，存储桶应设为私有，只通过签名链接访问
+/// 不支持预签名的存储（fs memory）由 /pub/file/download 提供签名链接
+pub struct StorageClient {
+    client: Operator,
+}
+
+impl StorageClient {
+    pub fn new(client: Operator) -> Self {
+        Self { client }
+    }
+
+    pub async fn upload_file(&self, key: &str, file_path: &str) -> Result<()> {
+        let buffer = tokio::fs::read(file_path).await?;
+        self.upload_file_bytes(key, &buffer).await
+    }
+
+    // 存储桶私有，上传后不返回链接，访问时按需签发短期链接
+    pub async fn upload_file_bytes(&self, key: &str, file_bytes: &[u8]) -> Result<()> {
+        let bytes = file_bytes.to_owned();
+        let start = Instant::now();
+        let result = self.client.write(key, bytes).await;
+        metrics_utils::oss_uploaded(start.elapsed(), result.is_ok());
+        result?;
+        Ok(())
+    }
+
+    pub async fn size(&self, key: &str) -> Result<u64> {
+        Ok(self.client.stat(key).await?.content_length())
+    }
+
+    // 对象不存在时也返回成功
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        Ok(self.client.delete(key).await?)
+    }
+
+    pub async fn read(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(self.client.read(key).await?.to_vec())
+    }
+
+    pub async fn generate_signed_url(&self, key: &str, expires_in: u64) -> Result<String> {
+        if self.client.info().full_capability().presign_read {
+            let presign = self.client.presign_read(key, Duration::from_secs(expires_in)).await?;
+            return Ok(presign.uri().to_string());
+        }
+        let expires = current_s() + expires_in;
+        Ok(format!(
+            "{}/pub/file/download?key={}&expires={}&signature={}",
+            SETTINGS.storage_url_base.trim_end_matches('/'),
+            url_encode(key),
+            expires,
+            local_signature(key, expires)
+        ))
+    }
+
+    // 校验本地签名链接，过期或签名不符返回 false
+    pub fn verify_signed_url(&self, key: &str, expires: u64, signature: &str) -> bool {
+        if expires < current_s() {
+            return false;
+        }
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        signer(key, expires).verify_slice(&signature).is_ok()
+    }
+}
+
+fn signer(key: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(SETTINGS.storage_sign_secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(format!("{}\n{}", key, expires).as_bytes());
+    mac
+}
+
+fn local_signature(key: &str, expires: u64) -> String {
+    hex::encode(signer(key, expires).finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn memory_storage() -> StorageClient {
+        StorageClient::new(Operator::new(Memory::default()).unwrap().finish())
+    }
+
+    // 从本地签名链接中取出查询参数
+    fn query_params(url: &str) -> HashMap<String, String> {
+        let (_, query) = url.split_once('?').unwrap();
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.to_owned(), urlencoding::decode(v).unwrap().into_owned()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn it_uploads_reads_and_deletes_bytes() {
+        let storage = memory_storage();
+        storage.upload_file_bytes("chat/site/a.txt", b"hello").await.unwrap();
+        assert_eq!(storage.read("chat/site/a.txt").await.unwrap(), b"hello");
+        assert_eq!(storage.size("chat/site/a.txt").await.unwrap(), 5);
+        storage.delete("chat/site/a.txt").await.unwrap();
+        assert!(storage.read("chat/site/a.txt").await.is_err());
+        // 删除不存在的对象也成功
+        storage.delete("chat/site/a.txt").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_signs_and_verifies_local_urls() {
+        let storage = memory_storage();
+        let key = "chat/site/a b&c.png";
+        storage.upload_file_bytes(key, b"png").await.unwrap();
+        let url = storage.generate_signed_url(key, 60).await.unwrap();
+        assert!(url.contains("/pub/file/download?"));
+
+        let params = query_params(&url);
+        assert_eq!(params["key"], key);
+        let expires = params["expires"].parse::<u64>().unwrap();
+        let signature = &params["signature"];
+        assert!(storage.verify_signed_url(key, expires, signature));
+        assert!(!storage.verify_signed_url("chat/site/other.png", expires, signature));
+        assert!(!storage.verify_signed_url(key, expires + 1, signature));
+        assert!(!storage.verify_signed_url(key, expires, "not-hex"));
+    }
+
+    #[test]
+    fn it_rejects_expired_urls() {
+        let storage = memory_storage();
+        let expires = current_s() - 1;
+        let signature = local_signature("chat/site/a.png", expires);
+        assert!(!storage.verify_signed_url("chat/site/a.png", expires, &signature));
+    }
+}
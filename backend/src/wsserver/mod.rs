@@ -7,7 +7,13 @@ use zino::{prelude::ExtractRejection, Request};
 use zino_core::{auth::{JwtClaims, UserSession}, json, model::Query, orm::Schema, Uuid};
 use zino_model::User;
 
-use crate::{model::ChatRoom, service::chat_service::ChatService};
+use crate::{
+    model::{ChatRoom, ChatWebsite},
+    service::{
+        chat_service::ChatService,
+        permission_service::{PermissionService, SiteRole},
+    },
+};
 use zino::prelude::RequestContext;
 
 pub mod server;
@@ -28,6 +34,7 @@ pub async fn chat_route(
     let room_key = query_params.get("room_key").map(|el| el.clone());
     let mut room = ChatRoom::default();
     let mut user_type = 0 as usize;
+    let mut site: Option<ChatWebsite> = None;
     tracing::info!("join chat with:{}", client);
     if client == "0" {// 服务端加入
         // req.hea
@@ -36,6 +43,11 @@ pub async fn chat_route(
         match new_req.parse_jwt_claims(JwtClaims::shared_key()) {
             Ok(claims) => {
                 if let Ok(user_session) = UserSession::<Uuid>::try_from_jwt_claims(claims) {
+                    // 站点成员且至少为 agent 才能接待访客
+                    site = match PermissionService::check_by_key(user_session.user_id(), key, SiteRole::Agent).await {
+                        Ok((site, _)) => Some(site),
+                        Err(e) => return Err(error::ErrorForbidden(e)),
+                    };
                     match User::find_by_id::<User>(user_session.user_id()).await {
                         Ok(us) => {
                             tracing::info!("asigin user...");
@@ -53,7 +65,11 @@ pub async fn chat_route(
             }
         }
         if room_key.is_none() { return Err(error::ErrorBadRequest("room_key must provied")); }
-        room = match ChatRoom::find_one::<ChatRoom>(&Query::from_entry("room_key", room_key.clone().unwrap().to_string())).await {
+        let mut room_query = Query::from_entry("room_key", room_key.clone().unwrap().to_string());
+        if let Some(site) = &site {
+            room_query.add_filter("room_site_id", site.id.to_string());
+        }
+        room = match ChatRoom::find_one::<ChatRoom>(&room_query).await {
             Ok(ro) => if ro.is_some() { ro.unwrap() } else { 
                 // return Err(error::ErrorBadRequest("room not found"));
                 tracing::info!("room not found, create none default.");
@@ -4,19 +4,32 @@
 use actix::prelude::*;
 use futures::sink::Send;
 use rand::{rngs::ThreadRng, Rng};
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 use tokio::{sync::oneshot, task};
 use zino::prelude::{DateTime, ModelAccessor, Query};
-use zino_core::orm::Schema;
+use zino_core::{json, orm::Schema};
 
 use crate::{
     dto::chat_message_entity::{ChatMessageDto, ChatNotify, ChatNotifyMessageDto},
     model::{ChatMessage, ChatRoom},
-    service::{chat_service::ChatService, room_message_state::MessageStatusManager},
+    service::{
+        chat_bot::{BotOutcome, BotService},
+        chat_service::ChatService,
+        media_service::MediaService,
+        room_message_state::MessageStatusManager,
+        webhook_service::WebhookService,
+    },
+    utils::metrics_utils,
 };
 
 use super::session::WsChatSession;
 
+/// 同一站点的未读通知合并发送的间隔
+const NOTIFY_BATCH_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Chat server sends this messages to session
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -74,6 +87,24 @@ pub struct Join {
     pub session: WsChatSession,
 }
 
+/// Broadcast an already persisted message to every session in the room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomBroadcast {
+    /// Room name
+    pub room: String,
+    pub msg: String,
+}
+
+/// Visitor asks for a human agent
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Handoff {
+    /// Client ID
+    pub id: usize,
+    pub session: WsChatSession,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChatServer {
     // 用于接收消息
@@ -83,6 +114,10 @@ pub struct ChatServer {
     rooms: HashMap<String, HashSet<usize>>,
     // 记录站点关联房间
     site_rooms: HashMap<String, HashSet<String>>,
+    // 上次发给客服的未读数，用于计算增量
+    notified_counts: HashMap<String, ChatNotifyMessageDto>,
+    // 等待合并发送通知的站点
+    pending_notify: HashSet<String>,
     rng: ThreadRng,
     // visitor_count: Arc<AtomicUsize>,
 }
@@ -97,6 +132,8 @@ impl ChatServer {
             rng: rand::thread_rng(),
             // visitor_count,
             site_rooms: HashMap::new(),
+            notified_counts: HashMap::new(),
+            pending_notify: HashSet::new(),
         }
     }
 
@@ -109,6 +146,13 @@ impl ChatServer {
             .entry(room_site.to_string())
             .or_default()
             .insert(room.to_string());
+        metrics_utils::set_rooms(self.rooms.len());
+        let (site_key, room_id) = (room_site.to_string(), room.to_string());
+        actix::spawn(async move {
+            if let Err(e) = MessageStatusManager::add_site_room(&site_key, &room_id).await {
+                tracing::error!("Add redis site:{} room error: {}", &site_key, e);
+            }
+        });
         id
     }
 }
@@ -127,12 +171,102 @@ impl ChatServer {
         }
     }
 
+    /// Send message to all sessions in the room, including the sender
+    fn send_room_message(&self, room: &str, message: &str) {
+        if let Some(sessions) = self.rooms.get(room) {
+            for id in sessions {
+                if let Some(addr) = self.sessions.get(id) {
+                    addr.do_send(Message(message.to_owned()));
+                }
+            }
+        }
+    }
+
     /// 给服务人员发送消息
     fn send_server_message(&self, site_key: &str, message: &str) {
         if let Some((room, addr)) = self.server_sessions.get(site_key) {
             addr.do_send(Message(message.to_owned()));
         }
     }
+
+    /// 给服务人员发送未读通知，只发送与上次相比有变化的房间
+    fn send_notify(&mut self, site_key: &str, mut notify: ChatNotify) {
+        if !self.server_sessions.contains_key(site_key) {
+            return;
+        }
+        let mut current = notify.message.clone();
+        current.handoff_room_id = None;
+        let changed = match self.notified_counts.get(site_key) {
+            Some(last) => notify.into_delta(last),
+            None => true,
+        };
+        self.notified_counts.insert(site_key.to_owned(), current);
+        if !changed {
+            return;
+        }
+        match serde_json::to_string(&notify) {
+            Ok(json) => {
+                tracing::info!("send notify: {}", &json);
+                self.send_server_message(site_key, &json);
+            }
+            Err(e) => tracing::warn!("notify serialize error: {:?}", e),
+        }
+    }
+
+    /// 机器人或自动回复规则应答访客消息，回复推送到房间，需要转人工时通知客服
+    fn spawn_bot_reply(&self, site_key: String, room: String, mess: ChatMessage, ctx: &mut Context<Self>) {
+        let fut = async move {
+            let outcome = match BotService::on_visitor_message(&site_key, &mess).await {
+                Ok(o) => o,
+                Err(e) => {
+                    tracing::warn!("bot reply error: {:?}", e);
+                    BotOutcome::default()
+                }
+            };
+            let handoff_notify = if outcome.handoff {
+                Some(ChatNotify::new_handoff(&site_key, &mess.room_id.to_string()).await)
+            } else {
+                None
+            };
+            (site_key, outcome.reply, handoff_notify)
+        }
+        .into_actor(self)
+        .map(move |(site_key, reply, handoff_notify), act, _ctx| {
+            if let Some(reply) = reply {
+                let bot_msg = ChatMessageDto::new_bot_msg(
+                    &reply.content,
+                    Some(reply.name.clone()),
+                    Some(room.clone()),
+                );
+                match serde_json::to_string(&bot_msg) {
+                    Ok(json) => act.send_room_message(&room, &json),
+                    Err(e) => tracing::error!("auto reply handle error: {:?}", e),
+                }
+            }
+            if let Some(notify) = handoff_notify {
+                act.send_notify(&site_key, notify);
+            }
+        });
+        ctx.spawn(fut);
+    }
+
+    /// 访客消息较多时，同一站点的通知在一个间隔内只计算和发送一次
+    fn schedule_notify(&mut self, site_key: &str, ctx: &mut Context<Self>) {
+        if !self.pending_notify.insert(site_key.to_owned()) {
+            return;
+        }
+        let site_key = site_key.to_owned();
+        ctx.run_later(NOTIFY_BATCH_INTERVAL, move |act, ctx| {
+            act.pending_notify.remove(&site_key);
+            let fut = async move {
+                let notify = ChatNotify::new_from_redis(&site_key).await;
+                (site_key, notify)
+            }
+            .into_actor(act)
+            .map(|(site_key, notify), act, _ctx| act.send_notify(&site_key, notify));
+            ctx.spawn(fut);
+        });
+    }
 }
 
 impl Actor for ChatServer {
@@ -151,8 +285,30 @@ impl Handler<Connect> for ChatServer {
                 msg.session.site_key.clone(),
                 (msg.room.clone(), msg.addr.clone()),
             );
+            // 新连接的客服需要完整的未读数
+            self.notified_counts.remove(&msg.session.site_key);
         }
         let room: &String = &msg.room;
+        metrics_utils::socket_opened(&msg.session.site_key, user.is_some());
+        if user.is_none() {
+            let chat_room = &msg.session.room_obj;
+            WebhookService::emit_later(
+                chat_room.room_site_id,
+                "visitor.connected",
+                json!({
+                    "room_id": chat_room.id.to_string(),
+                    "room_key": &chat_room.room_key,
+                    "client_info": &chat_room.client_info,
+                }),
+            );
+        }
+        if user.is_none() && !self.server_sessions.contains_key(&msg.session.site_key) {
+            // 客服不在线，提示访客留言
+            let offline = ChatMessageDto::new_offline_msg("客服暂时不在线，请留言", Some(room.clone()));
+            if let Ok(json) = serde_json::to_string(&offline) {
+                msg.addr.do_send(Message(json));
+            }
+        }
         let id = self.add_room(&msg.session.site_key, room, msg.addr);
         id
     }
@@ -171,7 +327,11 @@ impl Handler<Disconnect> for ChatServer {
                     rooms.push(room_id.to_owned());
                 }
             }
+            metrics_utils::socket_closed(&msg.session.site_key, msg.session.user.is_some());
         }
+        // 没有连接的房间不再保留，否则房间数只增不减
+        self.rooms.retain(|_, sessions| !sessions.is_empty());
+        metrics_utils::set_rooms(self.rooms.len());
 
         // Server offline logic
         if let Some(user) = msg.session.user {
@@ -181,6 +341,14 @@ impl Handler<Disconnect> for ChatServer {
             let mut chat_room = msg.session.room_obj.clone();
             chat_room.status = "offline".to_string();
             chat_room.update_at = DateTime::now();
+            WebhookService::emit_later(
+                chat_room.room_site_id,
+                "visitor.disconnected",
+                json!({
+                    "room_id": chat_room.id.to_string(),
+                    "room_key": &chat_room.room_key,
+                }),
+            );
 
             // Clear room status cache
             let site_key = msg.session.site_key.clone();
@@ -191,31 +359,22 @@ impl Handler<Disconnect> for ChatServer {
                 .remove(&room_id);
 
             // Handle Redis update and room status update asynchronously
-            if let Some(rooms_set) = self.site_rooms.get(&site_key) {
-                let rooms_set_cloned = rooms_set.clone();
-                let site_key_clone = site_key.clone();
-                let task = async move {
-                    if let Err(e) =
-                        MessageStatusManager::set_site_rooms(&site_key_clone, &rooms_set_cloned)
-                            .await
-                    {
-                        tracing::error!("Update redis site:{} rooms error: {}", &site_key_clone, e);
-                    } else {
-                        tracing::info!(
-                            "Update redis site:{} rooms:{:?}",
-                            &site_key_clone,
-                            &rooms_set_cloned
-                        );
-                    }
-
-                    if let Err(e) = chat_room.update().await {
-                        tracing::warn!("message save error: {:?}", e);
-                    }
+            let task = async move {
+                if let Err(e) =
+                    MessageStatusManager::remove_site_room(&site_key, &room_id).await
+                {
+                    tracing::error!("Update redis site:{} rooms error: {}", &site_key, e);
+                } else {
+                    tracing::info!("Remove redis site:{} room:{}", &site_key, &room_id);
                 }
-                .into_actor(self);
 
-                ctx.spawn(task);
+                if let Err(e) = chat_room.update().await {
+                    tracing::warn!("message save error: {:?}", e);
+                }
             }
+            .into_actor(self);
+
+            ctx.spawn(task);
         }
     }
 }
@@ -227,7 +386,6 @@ impl Handler<ClientMessage> for ChatServer {
         // 发送前保存
         tracing::info!("ClientMessage: {:?}", &msg);
         let site_key = msg.session.site_key.clone();
-        let str_files = msg.mess.str_files.clone();
         let s_session = self.server_sessions.get(&site_key).clone();
         tracing::info!("server_sessions: {:?}", &s_session);
         let s_in_room = if s_session.is_some() {
@@ -238,6 +396,11 @@ impl Handler<ClientMessage> for ChatServer {
         let (tx, mut rx) = oneshot::channel::<String>();
         let room_id = msg.id.clone();
         let room_key = msg.room.clone();
+        let from_visitor = msg.session.user.is_none();
+        let reply_site_key = site_key.clone();
+        let mut mess = msg.mess.clone();
+        let site_id = msg.session.room_obj.room_site_id;
+        metrics_utils::message_received(&site_key, !from_visitor);
         // 异步任务
         let fut = async move {
             if s_in_room {
@@ -245,23 +408,33 @@ impl Handler<ClientMessage> for ChatServer {
                 let _ = tx.send("send_message".to_string());
             } else {
                 let _ = MessageStatusManager::increase_latest_count(&site_key, &room_key, 1).await;
-                // 不在房间，发送通知
-                let notify_message = ChatNotify::new_from_redis(&site_key).await;
-                match serde_json::to_string(&notify_message) {
-                    Ok(notify_json) => {
-                        tracing::info!("send notify: {}", &notify_json);
-                        let _ = tx.send(notify_json);
-                    }
-                    Err(e) => {
-                        tracing::warn!("message save error: {:?}", e);
-                    }
+                // 不在房间，合并后发送通知
+                let _ = tx.send("notify".to_string());
+            }
+            // 只保留本房间上传的附件，并展开用于推送
+            // 客服切换房间后 session 中的房间不是当前房间，按消息重新查询
+            if !mess.file_ids.is_empty() {
+                let attached = match ChatRoom::find_by_id::<ChatRoom>(&mess.room_id).await {
+                    Ok(Some(room)) => MediaService::attach(&room, &mut mess).await,
+                    Ok(None) => Err(zino_core::warn!("room not found")),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = attached {
+                    tracing::warn!("attach message files error: {:?}", e);
+                    mess.file_ids.clear();
+                    mess.files.clear();
                 }
             }
-            let result = msg.mess.insert().await;
-            result
+            let persist_start = Instant::now();
+            let result = ChatService::save_message(&mess).await;
+            metrics_utils::message_persisted(persist_start.elapsed());
+            if result.is_ok() {
+                WebhookService::emit_later(site_id, "message.created", json!(mess));
+            }
+            (result, mess)
         }
         .into_actor(self)
-        .map(move |result, act, _ctx| {
+        .map(move |(result, mess), act, ctx| {
             tracing::info!("handle result");
             match result {
                 Ok(_) => {
@@ -269,11 +442,10 @@ impl Handler<ClientMessage> for ChatServer {
                     if let Ok(rs) = rx.try_recv() {
                         if rs == "send_message" {
                             tracing::info!("msg.session: {:?}", &msg.session);
-                            // let str_files = msg.mess.str_files.as_ref().map(|s| s.clone());
                             let user_name = msg.session.user.as_ref().map(|u| u.name().to_string());
-                            let message_data = ChatMessageDto::new_text_str_files_msg(
+                            let message_data = ChatMessageDto::new_text_files_msg(
                                 &msg.msg,
-                                str_files,
+                                mess.files.clone(),
                                 msg.session.user.is_none(),
                                 user_name,
                                 Some(msg.room.clone()),
@@ -289,10 +461,14 @@ impl Handler<ClientMessage> for ChatServer {
                             act.send_message(&msg.room, json.as_str(), room_id);
                         } else {
                             if msg.session.user.is_none() {
-                                act.send_server_message(&msg.session.site_key, &rs);
+                                act.schedule_notify(&msg.session.site_key, ctx);
                             }
                         }
                     }
+                    // 访客消息推送之后再由机器人或自动回复规则应答，不阻塞消息推送
+                    if from_visitor {
+                        act.spawn_bot_reply(reply_site_key, msg.room.clone(), mess, ctx);
+                    }
                 }
                 Err(e) => {
                     tracing::warn!("message save error: {:?}", e);
@@ -325,6 +501,11 @@ impl Handler<Join> for ChatServer {
             room_id,
             session,
         } = msg;
+        // 会话只会切换到校验过的房间，见 WsChatSession::join_room
+        if session.room_obj.id.to_string() != room_id {
+            tracing::warn!("session {} joins an unauthorized room {}", id, room_id);
+            return;
+        }
         let site_key = session.site_key.clone();
         let mut rooms = Vec::new();
         for (n, sessions) in &mut self.rooms {
@@ -332,6 +513,7 @@ impl Handler<Join> for ChatServer {
                 rooms.push(n.to_owned());
             }
         }
+        self.rooms.retain(|_, sessions| !sessions.is_empty());
 
         let user = session.user;
         tracing::info!("{:?} joined {}", &user, &room_id);
@@ -342,6 +524,7 @@ impl Handler<Join> for ChatServer {
         }
 
         self.rooms.entry(room_id.clone()).or_default().insert(id);
+        metrics_utils::set_rooms(self.rooms.len());
 
         // 发起服务通知
         let from_user = user.is_none();
@@ -358,41 +541,77 @@ impl Handler<Join> for ChatServer {
             self.send_message(&room_id, &msg_str, id);
         }
 
-        let (tx, mut rx) = oneshot::channel::<String>();
         let sk_clone = site_key.clone();
         let room_key = room_id.clone();
+        let agent_id = user.as_ref().map(|u| u.user_session().user_id().clone());
         let fut = async move {
             let query = Query::from_entry("id", room_key.clone());
-            if let Ok(chat_room) = ChatRoom::find_one::<ChatRoom>(&query).await {
-                if let Some(cr) = chat_room {
-                    let _r = ChatService::join_room(&cr).await;
-                    // 加入之后 更新房间消息
-                    let notify_message = ChatNotify::new_from_redis(&site_key).await;
-                    match serde_json::to_string(&notify_message) {
-                        Ok(notify_json) => {
-                            tracing::info!("send notify: {}", &notify_json);
-                            let _ = tx.send(notify_json);
-                        }
-                        Err(e) => {
-                            tracing::warn!("message save error: {:?}", e);
-                            let _ = tx.send("error".to_string());
+            match ChatRoom::find_one::<ChatRoom>(&query).await {
+                Ok(Some(mut cr)) => {
+                    let _r = ChatService::join_room(&cr, agent_id).await;
+                    if let Some(uid) = &agent_id {
+                        if let Err(e) = ChatService::assign_room(&mut cr, uid).await {
+                            tracing::warn!("assign room error: {:?}", e);
                         }
                     }
-                } else {
-                    let _ = tx.send("error".to_string());
+                    // 加入之后 更新房间消息
+                    Some(ChatNotify::new_from_redis(&site_key).await)
                 }
-            } else {
-                let _ = tx.send("error".to_string());
+                _ => None,
             }
-            
         }
         .into_actor(self)
-        .map(move |result, act, _ctx| {
-            if let Ok(rs) = rx.try_recv() {
-                if "error" != &rs {
-                    act.send_server_message(&sk_clone, &rs);
-                } else {
-                    tracing::warn!("send notify error and ignore");
+        .map(move |notify, act, _ctx| {
+            match notify {
+                Some(n) => act.send_notify(&sk_clone, n),
+                None => tracing::warn!("send notify error and ignore"),
+            }
+        });
+        context.spawn(fut);
+    }
+}
+
+/// Handler for `RoomBroadcast` message.
+impl Handler<RoomBroadcast> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: RoomBroadcast, _: &mut Context<Self>) {
+        self.send_room_message(&msg.room, &msg.msg);
+    }
+}
+
+/// Put the visitor's room into the agent queue and notify agents
+impl Handler<Handoff> for ChatServer {
+    type Result = ();
+    fn handle(&mut self, msg: Handoff, context: &mut Context<Self>) {
+        let site_key = msg.session.site_key.clone();
+        let room_id = msg.session.room.clone();
+        let room_uuid = msg.session.room_obj.id;
+        let fut = async move {
+            let mut chat_room = match ChatRoom::find_by_id::<ChatRoom>(&room_uuid).await {
+                Ok(Some(r)) => r,
+                _ => {
+                    tracing::warn!("handoff room {} not found", &room_id);
+                    return None;
+                }
+            };
+            if let Err(e) = ChatService::escalate_room(&mut chat_room).await {
+                tracing::warn!("escalate room error: {:?}", e);
+                return None;
+            }
+            Some(ChatNotify::new_handoff(&site_key, &room_id).await)
+        }
+        .into_actor(self)
+        .map(move |notify, act, _ctx| {
+            if let Some(n) = notify {
+                act.send_notify(&msg.session.site_key, n);
+                let tip = ChatMessageDto::new_notify_msg(
+                    "正在为您转接人工客服，请稍候",
+                    false,
+                    None,
+                    Some(msg.session.room.clone()),
+                );
+                if let Ok(json) = serde_json::to_string(&tip) {
+                    act.send_room_message(&msg.session.room, &json);
                 }
             }
         });
@@ -7,7 +7,11 @@ use zino_model::User;
 
 use crate::{
     model::{ChatMessage, ChatRoom},
-    service::room_message_state::MessageStatusManager,
+    service::{
+        permission_service::{PermissionService, SiteRole},
+        room_message_state::MessageStatusManager,
+    },
+    utils::metrics_utils,
 };
 
 use super::server;
@@ -49,6 +53,7 @@ impl WsChatSession {
             if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
                 // heartbeat timed out
                 tracing::info!("Websocket Client heartbeat failed, disconnecting!");
+                metrics_utils::heartbeat_timeout(act.user.is_some());
                 // notify chat server
                 act.addr.do_send(server::Disconnect {
                     id: act.id,
@@ -63,6 +68,17 @@ impl WsChatSession {
             ctx.ping(b"");
         });
     }
+
+    /// switch to a room that has already been authorized
+    fn join_room(&mut self, room: ChatRoom) {
+        self.room = room.id.to_string();
+        self.room_obj = room;
+        self.addr.do_send(server::Join {
+            id: self.id,
+            room_id: self.room.clone(),
+            session: self.clone(),
+        });
+    }
 }
 
 impl Actor for WsChatSession {
@@ -165,16 +181,43 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                                 .wait(ctx)
                         }
                         "/join" => {
-                            if v.len() == 2 {
-                                self.room = v[1].to_owned();
-                                self.addr.do_send(server::Join {
+                            let Some(room_id) = v.get(1).and_then(|r| Uuid::parse_str(r.trim()).ok()) else {
+                                ctx.text("!!! invalid room id");
+                                return;
+                            };
+                            match &self.user {
+                                // 访客只能加入自己的房间
+                                None if room_id == self.room_obj.id => {
+                                    self.join_room(self.room_obj.clone());
+                                }
+                                None => ctx.text("!!! can not join this room"),
+                                // 客服只能加入所在站点的房间，加入前重新校验站点角色
+                                Some(user) => {
+                                    let user_id = *user.user_session().user_id();
+                                    let site_key = self.site_key.clone();
+                                    async move {
+                                        PermissionService::check_room(&user_id, &site_key, &room_id, SiteRole::Agent)
+                                            .await
+                                    }
+                                    .into_actor(self)
+                                    .map(|result, act, ctx| match result {
+                                        Ok(room) => act.join_room(room),
+                                        Err(e) => {
+                                            tracing::warn!("join room error: {}", e);
+                                            ctx.text("!!! can not join this room");
+                                        }
+                                    })
+                                    .wait(ctx);
+                                }
+                            }
+                        }
+                        "/human" => {
+                            // 访客请求转人工
+                            if self.user.is_none() {
+                                self.addr.do_send(server::Handoff {
                                     id: self.id,
-                                    room_id: self.room.clone(),
                                     session: self.clone(),
                                 });
-                                // ctx.text("joined");
-                            } else {
-                                // ctx.text("!!! room name is required");
                             }
                         }
                         "/name" => {
@@ -201,14 +244,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                         } else {
                             "".to_owned()
                         };
-                        mess.room_id = Uuid::parse_str(&self.room).unwrap();
+                        mess.room_id = match Uuid::parse_str(&self.room) {
+                            Ok(room_id) => room_id,
+                            Err(_) => {
+                                ctx.text("!!! join a room first");
+                                return;
+                            }
+                        };
                         mess.create_at = DateTime::now();
                         mess.update_at = DateTime::now();
                         mess.status = "sended".to_string();
+                        mess.sender_type = if self.user.is_some() {
+                            "agent".to_string()
+                        } else {
+                            "visitor".to_string()
+                        };
                         mess.user_id = match &self.user {
                             Some(u) => Some(u.user_session().user_id().clone()),
                             None => None,
                         };
+                        // 附件只通过 file_ids 引用，由服务端展开
+                        mess.str_files = None;
+                        mess.files.clear();
 
                         // let room_id = mess.clone().room_id.clone().to_string();
                         // let site = self.site_key.clone();
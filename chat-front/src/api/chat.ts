@@ -23,3 +23,13 @@ export const loadSite = async (payload: any) => {
     }
 };
 
+// 附件的短期签名链接，需要附件所在房间的 room_key
+export const loadFileUrl = async (payload: any) => {
+    try {
+        const response = await apiClient.post('/load/file-url', payload);
+        return response;
+    } catch (error) {
+        console.error('Error posting data:', error);
+        throw error;
+    }
+};
@@ -0,0 +1,54 @@
+import { reactive } from 'vue';
+
+type SignedUrl = { url: string; expiresAt: number };
+type Signer = (mediaId: string, size?: string) => Promise<{ url: string; expires_in: number }>;
+type FileLike = { id?: string; media_id?: string; scan_status?: string };
+
+// 附件链接 /load/file/{id} 不带凭据，展示前用 room_key 换取短期签名链接
+// 按附件和尺寸缓存，过期前 30 秒重新获取
+export function createFileUrlResolver(sign: Signer) {
+    const cache = reactive<Record<string, SignedUrl>>({});
+    const pending = new Map<string, Promise<string>>();
+
+    const mediaIdOf = (file: FileLike) => file?.media_id || file?.id;
+
+    const load = (mediaId: string, size?: string) => {
+        const key = `${mediaId}:${size ?? ''}`;
+        let task = pending.get(key);
+        if (!task) {
+            task = sign(mediaId, size)
+                .then((res) => {
+                    cache[key] = {
+                        url: res.url,
+                        expiresAt: Date.now() + Math.max(res.expires_in - 30, 0) * 1000,
+                    };
+                    return res.url;
+                })
+                .finally(() => pending.delete(key));
+            pending.set(key, task);
+        }
+        return task;
+    };
+
+    // 模板中使用，还没有签名时先返回空，获取后自动刷新
+    const fileUrl = (file: FileLike, size?: string) => {
+        const mediaId = mediaIdOf(file);
+        if (!mediaId || file.scan_status === 'infected') return '';
+        const cached = cache[`${mediaId}:${size ?? ''}`];
+        if (!cached || cached.expiresAt < Date.now()) {
+            load(mediaId, size).catch((e) => console.error(e));
+        }
+        return cached?.url ?? '';
+    };
+
+    // 打开、下载时使用，总是返回未过期的链接
+    const resolveFileUrl = async (file: FileLike, size?: string) => {
+        const mediaId = mediaIdOf(file);
+        if (!mediaId || file.scan_status === 'infected') return '';
+        const cached = cache[`${mediaId}:${size ?? ''}`];
+        if (cached && cached.expiresAt > Date.now()) return cached.url;
+        return load(mediaId, size);
+    };
+
+    return { fileUrl, resolveFileUrl };
+}
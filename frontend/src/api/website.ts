@@ -99,3 +99,19 @@ export const listMessages = (data?: object) => {
     // { params: data }
   );
 };
+
+export type FileUrlResult = {
+  status: number;
+  success: boolean;
+  data: {
+    url: string;
+    media_id: string;
+    file_key: string;
+    expires_in: number;
+  };
+};
+
+// 附件的短期签名链接，按登录客服的站点权限授权
+export const getFileUrl = (data?: object) => {
+  return http.post<FileUrlResult, object>("/api/file/url", { data });
+};
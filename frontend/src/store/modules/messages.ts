@@ -1,7 +1,10 @@
 // src/store/modules/messages.ts
 import { defineStore } from "pinia";
 import { reactive, ref } from "vue";
-import type { ChatMessageDto } from "@/utils/websocketService";
+import type {
+  ChatMessageDto,
+  ChatMessageFileDto
+} from "@/utils/websocketService";
 
 export const useMessagesStore = defineStore("messages", () => {
   // 使用 reactive 管理状态
@@ -16,13 +19,20 @@ export const useMessagesStore = defineStore("messages", () => {
       if (!messages[room_id]) {
         messages[room_id] = [];
       }
-      if (message.str_files) {
-        message.files = JSON.parse(message.str_files);
-      }
       messages[room_id].push(message);
     }
   };
 
+  // 替换已有消息中的同一附件，带上缩略图信息
+  const updateMedia = (room_id: string, file: ChatMessageFileDto) => {
+    messages[room_id]?.forEach(m => {
+      const index = m.files?.findIndex(f => f.id === file.id) ?? -1;
+      if (index >= 0) {
+        m.files.splice(index, 1, file);
+      }
+    });
+  };
+
   const resetMessage = (room_id: string) => {
     messages[room_id] = [];
   };
@@ -43,6 +53,7 @@ export const useMessagesStore = defineStore("messages", () => {
     messages,
     serverNotify,
     addMessage,
+    updateMedia,
     resetMessage,
     handleAddFirstMessage
   };
@@ -8,10 +8,21 @@ interface ChatNotifyMessageDto {
   total_unread: number; // 对应 Rust 中的 i32
   new_message: boolean; // 对应 Rust 中的 bool
   message_counts: { [key: string]: number }; // 对应 Rust 中的 HashMap<String, i32>
+  handoff_room_id?: string;
+  delta?: boolean; // 为 true 时 message_counts 只包含有变化的房间
 }
-interface ChatMessageFileDto {
+export interface ChatMessageFileDto {
+  id: string;
+  file_key: string;
   url: string;
-  name: string;
+  file_name: string;
+  file_type: string;
+  file_size: number;
+  width?: number;
+  height?: number;
+  thumb_url?: string;
+  preview_url?: string;
+  scan_status?: string; // pending scanning clean infected
 }
 
 export interface ChatMessageDto {
@@ -19,12 +30,14 @@ export interface ChatMessageDto {
   time: string;
   user: boolean;
   user_name?: string;
-  str_files?: string;
+  file_ids?: string[];
   files: ChatMessageFileDto[];
   notify: string;
   to_server?: boolean;
   message?: ChatNotifyMessageDto;
   room_id?: string;
+  // 图片缩略图生成完成后推送的附件更新
+  media_update?: ChatMessageFileDto;
 }
 
 // src/websocketService.ts
@@ -92,8 +105,17 @@ class WebsocketService {
   public handleMessage(message: ChatMessageDto) {
     // console.log("handleMessage:", message);
     if (message.to_server) {
+      const last = this.messagesStore.serverNotify?.message;
+      if (message.message?.delta && last) {
+        message.message.message_counts = {
+          ...last.message_counts,
+          ...message.message.message_counts
+        };
+      }
       this.messagesStore.serverNotify = message;
       sendNewMessageNotification(JSON.stringify(message));
+    } else if (message.media_update) {
+      this.messagesStore.updateMedia(message.room_id, message.media_update);
     } else {
       this.messagesStore.addMessage(message);
     }
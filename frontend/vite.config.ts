@@ -36,6 +36,11 @@ export default ({ mode }: ConfigEnv): UserConfigExport => {
           target: "http://127.0.0.1:6080",
           changeOrigin: true,
           ws: true // 启用 Websocket 代理
+        },
+        // 附件链接
+        "/load/file": {
+          target: "http://127.0.0.1:6080",
+          changeOrigin: true
         }
       },
       // 预热文件以提前转换和缓存结果，降低启动期间的初始页面加载时长并防止转换瀑布